@@ -0,0 +1,374 @@
+use crate::io::debug;
+use crate::models::givme::GivMe;
+use crate::models::secret::Secret;
+use crate::os::vault_path;
+use std::path::{Path, PathBuf};
+
+/// Path of agent socket, in a directory only current user can enter
+pub fn agent_socket_path(handle: &GivMe) -> Result<PathBuf, String> {
+    let vault = vault_path(handle)?;
+    Ok(Path::new(&vault)
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("agent")
+        .join("socket"))
+}
+
+/// Master Key cached by a running agent, `None` if there is no agent
+/// or it can't be reached
+pub fn agent_password(handle: &GivMe) -> Option<Secret> {
+    match agent_request("unlock", handle) {
+        Ok(response) => {
+            let password = response.expose().lines().nth(1).map(Secret::from);
+            if password.is_none() {
+                debug("Agent sent no Master Key");
+            }
+            password
+        }
+        Err(err) => {
+            debug(format!("No agent: {}", err).as_str());
+            None
+        }
+    }
+}
+
+/// Whether an agent answers on the socket of this vault
+pub fn agent_running(handle: &GivMe) -> bool {
+    agent_request("ping", handle).is_ok()
+}
+
+/// Stop running agent so it wipes the Master Key. Returns `false` if
+/// there was no agent.
+pub fn lock_agent(handle: &GivMe) -> Result<bool, String> {
+    if !agent_running(handle) {
+        return Ok(false);
+    }
+    agent_request("lock", handle).map(|_| true)
+}
+
+/// Start agent in background holding `password`, which has to be the
+/// adjusted Master Key already checked against the vault
+#[cfg(unix)]
+pub fn start_agent(
+    password: &Secret,
+    idle: u64,
+    lifetime: u64,
+    handle: &GivMe,
+) -> Result<(), String> {
+    use std::io::Write;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    let exe = std::env::current_exe().map_err(|err| err.to_string())?;
+    let mut child = Command::new(exe)
+        .args(["agent", "--serve", "--idle"])
+        .arg(idle.to_string())
+        .arg("--lifetime")
+        .arg(lifetime.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        /* Own process group, so closing the terminal leaves it running */
+        .process_group(0)
+        .spawn()
+        .map_err(|err| format!("Can't start agent: {}", err))?;
+    let line = Secret::from(format!("{}\n", password.expose()));
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(line.expose().as_bytes())
+        .map_err(|err| format!("Can't pass Master Key to agent: {}", err))?;
+
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(5) {
+        if agent_running(handle) {
+            return Ok(());
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("Agent exited with {}", status));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Err(String::from("Agent did not come up in time"))
+}
+
+/// Master Key handed over by `start_agent()` on stdin
+pub fn receive_agent_password() -> Result<Secret, String> {
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .map_err(|err| format!("Can't read Master Key: {}", err))?;
+    let line = Secret::from(line);
+    let password = Secret::from(line.expose().trim_end_matches('\n'));
+    if password.expose().is_empty() {
+        return Err(String::from("No Master Key given to agent"));
+    }
+    Ok(password)
+}
+
+/// Serve `password` on agent socket until agent is idle for `idle`
+/// seconds, `lifetime` seconds have passed or it is locked
+#[cfg(unix)]
+pub fn serve_agent(
+    password: Secret,
+    idle: u64,
+    lifetime: u64,
+    handle: &GivMe,
+) -> Result<(), String> {
+    use std::fs::{self, DirBuilder};
+    use std::io::{BufRead, BufReader, ErrorKind, Write};
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::UnixListener;
+    use std::time::{Duration, Instant};
+
+    let path = agent_socket_path(handle)?;
+    let dir = path.parent().unwrap();
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .and_then(|_| fs::set_permissions(dir, fs::Permissions::from_mode(0o700)))
+        .map_err(|err| format!("{}: {}", dir.display(), err))?;
+    /* Left behind by an agent which was killed */
+    if path.exists() {
+        fs::remove_file(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    let listener = UnixListener::bind(&path)
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        .and_then(|listener| {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).map(|_| listener)
+        })
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    let started = Instant::now();
+    let mut last_used = Instant::now();
+    while started.elapsed() < Duration::from_secs(lifetime)
+        && last_used.elapsed() < Duration::from_secs(idle)
+    {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(err) => {
+                debug(format!("Agent can't accept: {}", err).as_str());
+                continue;
+            }
+        };
+        let _ = stream.set_nonblocking(false);
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+        let mut writer = &stream;
+        /* Directory already keeps others out, unless its mode was changed */
+        match peer_uid(&stream) {
+            Ok(uid) if uid == current_uid() => {}
+            Ok(uid) => {
+                debug(format!("Agent refused user {}", uid).as_str());
+                let _ = writer.write_all(b"error Not your agent\n");
+                continue;
+            }
+            Err(err) => {
+                debug(format!("Agent can't tell who is connected: {}", err).as_str());
+                continue;
+            }
+        }
+        let mut reader = BufReader::new(&stream);
+        let mut command = String::new();
+        if reader.read_line(&mut command).is_err() {
+            continue;
+        }
+        match command.trim() {
+            "ping" => {
+                let _ = writer.write_all(b"ok\n");
+            }
+            "unlock" => {
+                let response = Secret::from(format!("ok\n{}\n", password.expose()));
+                let _ = writer.write_all(response.expose().as_bytes());
+                last_used = Instant::now();
+            }
+            "lock" => {
+                let _ = writer.write_all(b"ok\n");
+                break;
+            }
+            _ => {
+                let _ = writer.write_all(b"error Unknown command\n");
+            }
+        }
+    }
+    let _ = fs::remove_file(&path);
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn geteuid() -> u32;
+    #[cfg(target_os = "linux")]
+    fn getsockopt(socket: i32, level: i32, name: i32, value: *mut u8, len: *mut u32) -> i32;
+    #[cfg(not(target_os = "linux"))]
+    fn getpeereid(socket: i32, uid: *mut u32, gid: *mut u32) -> i32;
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    unsafe { geteuid() }
+}
+
+/// User id of process on the other end of `stream`, `SO_PEERCRED` on Linux
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> std::io::Result<u32> {
+    use std::os::unix::io::AsRawFd;
+    const SOL_SOCKET: i32 = 1;
+    const SO_PEERCRED: i32 = 17;
+    /* struct ucred: pid, uid and gid */
+    let mut credentials = [0u32; 3];
+    let mut len = std::mem::size_of_val(&credentials) as u32;
+    let result = unsafe {
+        getsockopt(
+            stream.as_raw_fd(),
+            SOL_SOCKET,
+            SO_PEERCRED,
+            credentials.as_mut_ptr() as *mut u8,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(credentials[1])
+}
+
+/// User id of process on the other end of `stream`
+#[cfg(all(unix, not(target_os = "linux")))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> std::io::Result<u32> {
+    use std::os::unix::io::AsRawFd;
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// Send one command to agent and read its whole response
+#[cfg(unix)]
+fn agent_request(command: &str, handle: &GivMe) -> Result<Secret, String> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let path = agent_socket_path(handle)?;
+    let mut stream = UnixStream::connect(&path).map_err(|err| err.to_string())?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .and_then(|_| stream.write_all(format!("{}\n", command).as_bytes()))
+        .map_err(|err| err.to_string())?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|err| err.to_string())?;
+    let response = Secret::from(response);
+    if response.expose().starts_with("ok\n") {
+        Ok(response)
+    } else {
+        Err(format!("Agent says: {}", response.expose().trim()))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn start_agent(_: &Secret, _: u64, _: u64, _: &GivMe) -> Result<(), String> {
+    Err(String::from("Agent needs Unix sockets"))
+}
+
+#[cfg(not(unix))]
+pub fn serve_agent(_: Secret, _: u64, _: u64, _: &GivMe) -> Result<(), String> {
+    Err(String::from("Agent needs Unix sockets"))
+}
+
+#[cfg(not(unix))]
+fn agent_request(_: &str, _: &GivMe) -> Result<Secret, String> {
+    Err(String::from("Agent needs Unix sockets"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::os::get_os_and_username;
+    use crate::utils::{lock_test_home, test_dir};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    fn test_handle() -> GivMe {
+        let mut handle = GivMe::new();
+        get_os_and_username(&mut handle);
+        handle
+    }
+
+    fn wait_until(what: &str, done: impl Fn() -> bool) {
+        let started = Instant::now();
+        while !done() {
+            assert!(started.elapsed() < Duration::from_secs(10), "{}", what);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Run agent aside with `idle` and `lifetime`, `check` is called once
+    /// it answers. Returns how long agent ran.
+    fn with_agent(idle: u64, lifetime: u64, check: impl FnOnce(&GivMe)) -> Duration {
+        std::env::set_var("HOME", test_dir("home"));
+        let handle = test_handle();
+        let started = Instant::now();
+        std::thread::scope(|scope| {
+            let agent = scope.spawn(move || {
+                serve_agent(Secret::from("cached key"), idle, lifetime, &test_handle())
+            });
+            wait_until("agent never came up", || agent_running(&handle));
+            check(&handle);
+            agent.join().unwrap().unwrap();
+        });
+        assert!(!agent_socket_path(&handle).unwrap().exists());
+        assert_eq!(agent_password(&handle), None);
+        started.elapsed()
+    }
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn socket_is_private_and_lock_forgets_key() {
+        let _home = lock_test_home();
+        with_agent(60, 60, |handle| {
+            let path = agent_socket_path(handle).unwrap();
+            assert_eq!(mode(path.parent().unwrap()), 0o700);
+            assert_eq!(mode(&path), 0o600);
+            assert_eq!(agent_password(handle), Some(Secret::from("cached key")));
+            assert_eq!(lock_agent(handle), Ok(true));
+        });
+    }
+
+    #[test]
+    fn agent_stops_when_idle_or_too_old() {
+        let _home = lock_test_home();
+        let idle = with_agent(1, 60, |_| {});
+        assert!(idle >= Duration::from_secs(1) && idle < Duration::from_secs(5));
+
+        /* Being used does not keep it past its lifetime */
+        let lifetime = with_agent(60, 2, |handle| {
+            let started = Instant::now();
+            while agent_password(handle).is_some() {
+                assert!(started.elapsed() < Duration::from_secs(10));
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        });
+        assert!(lifetime >= Duration::from_secs(2) && lifetime < Duration::from_secs(6));
+    }
+
+    #[test]
+    fn peer_is_current_user() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        assert_eq!(peer_uid(&ours).unwrap(), current_uid());
+        assert_eq!(peer_uid(&theirs).unwrap(), current_uid());
+    }
+}
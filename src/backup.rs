@@ -0,0 +1,304 @@
+use crate::io::{ask_pass_and_extract_key, debug};
+use crate::models::credentials::Credentials;
+use crate::models::givme::GivMe;
+use crate::os::{vault_path, write_file_atomically};
+use crate::sql::get_sql_con;
+use crate::storage::{require_sqlite, storage_path, MemoryBackend, StorageBackend};
+use crate::utils::timestamp_now;
+use sqlite::{Connection, OpenFlags, State};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Automatic backups kept in backup directory, older ones are removed
+pub const AUTO_BACKUPS_KEPT: usize = 10;
+
+/// Directory next to vault holding automatic backups and
+/// backups made without a destination
+pub fn backup_dir(handle: &GivMe) -> Result<PathBuf, String> {
+    let vault = vault_path(handle)?;
+    Ok(Path::new(&vault)
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("backups"))
+}
+
+/// `dir/{prefix}-{timestamp}{suffix}.{extension}`, with a counter
+/// added if two backups are made within the same second
+fn timestamped_path(dir: &Path, prefix: &str, suffix: &str, extension: &str) -> PathBuf {
+    let stamp = timestamp_now();
+    let mut path = dir.join(format!("{}-{}{}.{}", prefix, stamp, suffix, extension));
+    let mut counter = 2;
+    while path.exists() {
+        path = dir.join(format!(
+            "{}-{}{}-{}.{}",
+            prefix, stamp, suffix, counter, extension
+        ));
+        counter += 1;
+    }
+    path
+}
+
+/// Copy live vault to `out` as a consistent snapshot. `VACUUM INTO`
+/// reads the database in a single transaction, so a backup taken while
+/// another givme is writing is never half updated.
+fn snapshot_vault(out: &Path, handle: &GivMe) -> Result<(), String> {
+    let con = match handle.sql_con.as_ref() {
+        Some(con) => con,
+        None => return Err(String::from("Sql connection was not initialized")),
+    };
+    let out_path = out.to_string_lossy().to_string();
+    let mut statement = con
+        .prepare("VACUUM INTO ?")
+        .map_err(|err| err.to_string())?;
+    statement
+        .bind(1, out_path.as_str())
+        .map_err(|err| err.to_string())?;
+    while statement.next().map_err(|err| err.to_string())? != State::Done {}
+
+    /* Vault is encrypted, but still nobody else needs to read it */
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(out, fs::Permissions::from_mode(0o600))
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Backup vault as asked by `givme backup`. `dest` may be a file or an
+/// existing directory, backup gets a timestamped name in the latter
+/// case. Without `dest` backup goes to `backup_dir()`. Returns path
+/// of backup.
+pub fn backup_vault(dest: Option<&str>, handle: &GivMe) -> Result<String, String> {
+    require_sqlite("Backup", handle)?;
+    let out = match dest {
+        Some(dest) if Path::new(dest).is_dir() => {
+            timestamped_path(Path::new(dest), "cred", "", "db")
+        }
+        Some(dest) if Path::new(dest).exists() => {
+            return Err(format!("'{}' already exists", dest));
+        }
+        Some(dest) => PathBuf::from(dest),
+        None => {
+            let dir = backup_dir(handle)?;
+            fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+            timestamped_path(&dir, "cred", "", "db")
+        }
+    };
+    snapshot_vault(&out, handle)?;
+    Ok(out.to_string_lossy().to_string())
+}
+
+/// Backup vault before a destructive operation named `reason`, like
+/// `delete` or `migrate`. JSON and sealed storage are backed up as a
+/// copy of their file. Only newest `AUTO_BACKUPS_KEPT` automatic
+/// backups are kept.
+pub fn auto_backup(reason: &str, handle: &GivMe) -> Result<String, String> {
+    let dir = backup_dir(handle)?;
+    fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+    let suffix = format!("-{}", reason);
+    let out = if handle.sql_con.is_some() {
+        let out = timestamped_path(&dir, "auto", &suffix, "db");
+        snapshot_vault(&out, handle)?;
+        out
+    } else {
+        /* File is only ever replaced atomically, so a copy is never half updated */
+        let vault = storage_path(handle)?;
+        let extension = Path::new(&vault)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .unwrap_or_else(|| String::from("vault"));
+        let out = timestamped_path(&dir, "auto", &suffix, &extension);
+        let data = fs::read(&vault).map_err(|err| format!("{}: {}", vault, err))?;
+        write_file_atomically(&out.to_string_lossy(), &data, false)
+            .map_err(|err| err.to_string())?;
+        out
+    };
+    debug(format!("Automatic backup at {}", out.display()).as_str());
+
+    let mut auto_backups: Vec<PathBuf> = fs::read_dir(&dir)
+        .map_err(|err| err.to_string())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().starts_with("auto-"))
+                .unwrap_or_default()
+        })
+        .collect();
+    /* Timestamp follows prefix, so names sort by time of backup */
+    auto_backups.sort();
+    let excess = auto_backups.len().saturating_sub(AUTO_BACKUPS_KEPT);
+    for old in &auto_backups[..excess] {
+        fs::remove_file(old).map_err(|err| err.to_string())?;
+    }
+    Ok(out.to_string_lossy().to_string())
+}
+
+/// Replace live vault with `backup` as asked by `givme restore`.
+///
+/// Backup has to pass SQLite integrity check and user has to enter
+/// master key of the backup, which may differ from current one. Live
+/// vault, if any, is backed up automatically before being replaced.
+/// Backups made by older versions are upgraded once opened as vault.
+pub fn restore_vault(backup: &str, handle: &mut GivMe) -> Result<(), String> {
+    require_sqlite("Restore", handle)?;
+    let mut backup_handle = open_backup(backup)?;
+    /* Exits on wrong master key */
+    ask_pass_and_extract_key(&mut backup_handle).map_err(|_| String::from("Invalid Password"))?;
+    drop(backup_handle);
+
+    let live = vault_path(handle)?;
+    if Path::new(&live).exists() {
+        get_sql_con(handle);
+        let saved = auto_backup("restore", handle)?;
+        println!("Current vault saved to {}", saved);
+        handle.sql_con = None;
+    } else if let Some(parent) = Path::new(&live).parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let data = fs::read(backup).map_err(|err| err.to_string())?;
+    write_file_atomically(&live, &data, true).map_err(|err| err.to_string())
+}
+
+/// Check `backup` and load its Master Key row into a handle of its own,
+/// ready for the Master Key to be entered. Only `key` and `value`
+/// columns are read, which every version of `cred` table has, so
+/// backups taken before a migration open too.
+fn open_backup(backup: &str) -> Result<GivMe, String> {
+    if !Path::new(backup).is_file() {
+        return Err(format!("'{}' does not exist", backup));
+    }
+    let con = Connection::open_with_flags(backup, OpenFlags::new().set_read_only())
+        .map_err(|err| err.to_string())?;
+    let mut statement = con
+        .prepare("PRAGMA integrity_check")
+        .map_err(|err| err.to_string())?;
+    if statement.next().map_err(|err| err.to_string())? != State::Row
+        || statement.read::<String>(0).map_err(|err| err.to_string())? != "ok"
+    {
+        return Err(String::from("Backup is corrupted"));
+    }
+    drop(statement);
+    if !is_vault(&con) {
+        return Err(String::from("Not a givme vault"));
+    }
+    let mut statement = con
+        .prepare("SELECT value FROM cred WHERE key = 'secret_key'")
+        .map_err(|err| err.to_string())?;
+    statement.next().map_err(|err| err.to_string())?;
+    let secret_key = statement.read::<String>(0).map_err(|err| err.to_string())?;
+
+    let mut storage = MemoryBackend::new();
+    storage.put(&Credentials::new(
+        String::from("secret_key"),
+        secret_key,
+        String::new(),
+    ))?;
+    let mut backup_handle = GivMe::new();
+    backup_handle.storage = Some(Box::new(storage));
+    Ok(backup_handle)
+}
+
+/// Check if database has a cred table with master key
+fn is_vault(con: &Connection) -> bool {
+    let count = |sql: &str| -> Option<i64> {
+        let mut statement = con.prepare(sql).ok()?;
+        statement.next().ok()?;
+        statement.read::<i64>(0).ok()
+    };
+    count("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'cred'")
+        .unwrap_or_default()
+        > 0
+        && count("SELECT COUNT(*) FROM cred WHERE key = 'secret_key'").unwrap_or_default() > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::encrypt;
+    use crate::io::unlock_with_master_key;
+    use crate::models::secret::Secret;
+    use crate::storage::storage_mut;
+    use crate::utils::{adjust_password_length, lock_test_home, test_dir, test_vault};
+
+    /// Vault in the layout of the first release, as kept by the
+    /// automatic backup taken before `upgrade_sql()` adds columns
+    fn write_first_release_vault(path: &str, master_key: &str, vault_key: &str) {
+        let password = Secret::from(adjust_password_length(master_key, 24));
+        let mut password_handle = GivMe::new();
+        password_handle.key = Some(password.clone());
+        password_handle.password = Some(password);
+        let secret_key =
+            base64::encode(encrypt(&format!("0000{}", vault_key), &mut password_handle).unwrap());
+
+        let con = Connection::open(path).unwrap();
+        con.execute("CREATE TABLE cred (key TEXT, value TEXT, info TEXT)")
+            .unwrap();
+        let mut statement = con
+            .prepare("INSERT INTO cred (key, value, info) VALUES ('secret_key', ?, '')")
+            .unwrap();
+        statement.bind(1, secret_key.as_str()).unwrap();
+        while statement.next().unwrap() != State::Done {}
+    }
+
+    #[test]
+    fn opens_backup_taken_before_migration() {
+        let dir = test_dir("restore");
+        let backup = format!("{}/auto-migrate.db", dir);
+        let vault_key = "456789abcdefghijklmnopqrstuv";
+        write_first_release_vault(&backup, "Key", vault_key);
+
+        let mut backup_handle = open_backup(&backup).unwrap();
+        assert!(unlock_with_master_key("Wrong", &mut backup_handle).is_err());
+        unlock_with_master_key("Key", &mut backup_handle).unwrap();
+        assert_eq!(backup_handle.key.as_ref().unwrap().expose(), vault_key);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_files_which_are_not_vaults() {
+        let dir = test_dir("restore");
+        assert!(open_backup(&format!("{}/missing.db", dir)).is_err());
+
+        let empty = format!("{}/empty.db", dir);
+        Connection::open(&empty)
+            .unwrap()
+            .execute("CREATE TABLE cred (key TEXT, value TEXT, info TEXT)")
+            .unwrap();
+        assert_eq!(
+            open_backup(&empty).err().as_deref(),
+            Some("Not a givme vault")
+        );
+
+        let other = format!("{}/other.db", dir);
+        Connection::open(&other)
+            .unwrap()
+            .execute("CREATE TABLE notes (text TEXT)")
+            .unwrap();
+        assert_eq!(
+            open_backup(&other).err().as_deref(),
+            Some("Not a givme vault")
+        );
+
+        let garbage = format!("{}/garbage.db", dir);
+        fs::write(&garbage, "not a database at all, just some text").unwrap();
+        assert!(open_backup(&garbage).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn auto_backup_copies_file_storage() {
+        let _home = lock_test_home();
+        for kind in ["json", "sealed"] {
+            let mut handle = test_vault(kind, None);
+            let cred = Credentials::new(String::from("a"), String::from("b"), String::new());
+            storage_mut(&mut handle).put(&cred).unwrap();
+
+            let out = auto_backup("test", &handle).unwrap();
+            assert!(out.contains("/auto-") && out.ends_with(&format!("-test.{}", kind)));
+            let vault = storage_path(&handle).unwrap();
+            assert_eq!(fs::read(&out).unwrap(), fs::read(&vault).unwrap());
+        }
+    }
+}
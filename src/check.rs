@@ -0,0 +1,261 @@
+use crate::backup::auto_backup;
+use crate::cred::decrypt_fields;
+use crate::encryption::{decrypt, encrypt_legacy, name_index};
+use crate::io::ask_pass_and_extract_key;
+use crate::models::{
+    check_report::CheckReport, credentials::Credentials, givme::GivMe, secret::exposed,
+};
+use crate::sql::{get_all_rows_from_sql, get_from_sql, quarantine_in_sql};
+use sqlite::State;
+use std::collections::HashMap;
+
+/// Messages of SQLite's own integrity check, empty if database is fine
+fn sqlite_problems(handle: &GivMe) -> Vec<String> {
+    let con = match handle.sql_con.as_ref() {
+        Some(con) => con,
+        None => return vec![String::from("Sql connection was not initialized")],
+    };
+    let mut statement = match con.prepare("PRAGMA integrity_check") {
+        Ok(statement) => statement,
+        Err(err) => return vec![err.to_string()],
+    };
+    let mut messages = Vec::new();
+    loop {
+        match statement.next() {
+            Ok(State::Row) => messages.push(statement.read::<String>(0).unwrap_or_default()),
+            Ok(State::Done) => break,
+            Err(err) => {
+                messages.push(err.to_string());
+                break;
+            }
+        }
+    }
+    messages.retain(|message| message != "ok");
+    messages
+}
+
+/// Encryption has no authentication tag, so a damaged row usually
+/// shows up as control characters in decrypted text
+fn looks_decrypted(text: &str) -> bool {
+    !text
+        .chars()
+        .any(|c| c.is_control() && !['\n', '\r', '\t'].contains(&c))
+}
+
+/// Why an entry row can't be used, `None` if it is fine
+fn row_problem(stored_key: &str, cred: &mut Credentials, handle: &mut GivMe) -> Option<String> {
+    let indexed = cred.name.is_some();
+    if let Err(err) = decrypt_fields(cred, handle) {
+        return Some(format!("Does not decrypt: {}", err));
+    }
+    let fields = [
+        exposed(&cred.info),
+        exposed(&cred.otp),
+        exposed(&cred.username),
+        cred.url.as_deref(),
+    ];
+    if !looks_decrypted(&cred.key)
+        || !looks_decrypted(cred.value.expose())
+        || fields
+            .iter()
+            .any(|field| !looks_decrypted(field.unwrap_or_default()))
+    {
+        return Some(String::from("Decrypts to garbage"));
+    }
+    /* Entries are found by name index, older ones by encrypting the name */
+    let lookup_key = if indexed {
+        name_index(&cred.key, handle)
+    } else {
+        encrypt_legacy(&cred.key, handle)
+            .map(base64::encode)
+            .ok_or_else(String::new)
+    };
+    match lookup_key {
+        Ok(lookup_key) if lookup_key == stored_key => None,
+        _ => Some(format!("'{}' can't be looked up by its name", cred.key)),
+    }
+}
+
+/// Verify vault as asked by `givme check`, asking for Master Key unless
+/// `handle` is unlocked already. Problems with single rows
+/// are moved to `cred_quarantine` table when `quarantine` is set, after
+/// an automatic backup.
+pub fn check_vault(quarantine: bool, handle: &mut GivMe) -> CheckReport {
+    let mut report = CheckReport::default();
+    for message in sqlite_problems(handle) {
+        report.problems.push((None, format!("SQLite: {}", message)));
+    }
+    if !report.problems.is_empty() {
+        /* Reading rows of a damaged database is not safe */
+        return report;
+    }
+
+    match get_from_sql("secret_key", handle).len() {
+        0 => {
+            report
+                .problems
+                .push((None, String::from("Master key row is missing")));
+            return report;
+        }
+        1 => {}
+        count => report.problems.push((
+            None,
+            format!("{} master key rows, only first one is used", count),
+        )),
+    }
+    if handle.key.is_none() {
+        ask_pass_and_extract_key(handle).unwrap();
+    }
+
+    let identities = get_from_sql("identity_key", handle);
+    if identities.len() > 1 {
+        report.problems.push((
+            None,
+            format!("{} identity rows, only first one is used", identities.len()),
+        ));
+    }
+    for identity in &identities {
+        let decrypted = base64::decode(identity.value.expose())
+            .map_err(|err| err.to_string())
+            .and_then(|value| decrypt(&value, handle));
+        if !decrypted
+            .map(|text| looks_decrypted(&text))
+            .unwrap_or_default()
+        {
+            report
+                .problems
+                .push((None, String::from("Identity key does not decrypt")));
+        }
+    }
+
+    let mut seen: HashMap<String, i64> = HashMap::new();
+    let mut bad_rows: Vec<(i64, String)> = Vec::new();
+    for (rowid, mut cred) in get_all_rows_from_sql(handle) {
+        report.rows_checked += 1;
+        let stored_key = cred.key.clone();
+        if let Some(first) = seen.get(&stored_key) {
+            bad_rows.push((rowid, format!("Duplicate of row {}", first)));
+            continue;
+        }
+        seen.insert(stored_key.clone(), rowid);
+        if let Some(problem) = row_problem(&stored_key, &mut cred, handle) {
+            bad_rows.push((rowid, problem));
+        }
+    }
+
+    if quarantine && !bad_rows.is_empty() {
+        if let Err(err) = auto_backup("check", handle) {
+            report
+                .problems
+                .push((None, format!("Nothing quarantined, backup failed: {}", err)));
+        } else {
+            for (rowid, reason) in &bad_rows {
+                match quarantine_in_sql(*rowid, reason, handle) {
+                    Ok(_) => report.quarantined.push(*rowid),
+                    Err(err) => report
+                        .problems
+                        .push((Some(*rowid), format!("Quarantine failed: {}", err))),
+                }
+            }
+        }
+    }
+    for (rowid, reason) in bad_rows {
+        report.problems.push((Some(rowid), reason));
+    }
+    report
+}
+
+/// Prints findings of `check_vault()` to user
+pub fn show_check_report(report: &CheckReport) {
+    for (rowid, problem) in &report.problems {
+        match rowid {
+            Some(rowid) => println!("Row {}: {}", rowid, problem),
+            None => println!("{}", problem),
+        }
+    }
+    if !report.quarantined.is_empty() {
+        println!(
+            "\n{} rows moved to cred_quarantine table",
+            report.quarantined.len()
+        );
+    }
+    println!(
+        "\nChecked: {}  Problems: {}",
+        report.rows_checked,
+        report.problems.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::{give_credentials, save_credentials};
+    use crate::utils::{lock_test_home, test_vault};
+
+    fn execute(sql: &str, handle: &GivMe) {
+        handle.sql_con.as_ref().unwrap().execute(sql).unwrap();
+    }
+
+    fn count(table: &str, handle: &GivMe) -> i64 {
+        let con = handle.sql_con.as_ref().unwrap();
+        let mut statement = con
+            .prepare(format!("SELECT count(*) FROM {}", table))
+            .unwrap();
+        statement.next().unwrap();
+        statement.read::<i64>(0).unwrap()
+    }
+
+    #[test]
+    fn control_characters_mean_garbage() {
+        assert!(looks_decrypted("plain text\twith\r\nlines ünï"));
+        assert!(!looks_decrypted("bad\u{0}byte"));
+        assert!(!looks_decrypted("\u{1b}[2J"));
+    }
+
+    #[test]
+    fn finds_and_quarantines_bad_rows() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        let cred = Credentials::new(String::from("good"), String::from("value"), String::new());
+        save_credentials(cred, &mut handle).unwrap();
+        let report = check_vault(false, &mut handle);
+        assert_eq!(report.rows_checked, 1);
+        assert!(report.problems.is_empty());
+
+        execute(
+            "INSERT INTO cred SELECT * FROM cred WHERE rowid = (SELECT max(rowid) FROM cred)",
+            &handle,
+        );
+        execute(
+            "INSERT INTO cred (key, value, info) VALUES ('bm90IGEga2V5', 'bm90IGEgdmFsdWU=', '')",
+            &handle,
+        );
+        let report = check_vault(false, &mut handle);
+        assert_eq!(report.rows_checked, 3);
+        let reasons: Vec<&str> = report
+            .problems
+            .iter()
+            .map(|(rowid, reason)| {
+                assert!(rowid.is_some(), "{}", reason);
+                reason.as_str()
+            })
+            .collect();
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons
+            .iter()
+            .any(|reason| reason.starts_with("Duplicate of row")));
+        assert!(reasons
+            .iter()
+            .any(|reason| reason.starts_with("Does not decrypt")));
+        assert!(report.quarantined.is_empty());
+        assert_eq!(count("cred", &handle), 5);
+
+        let report = check_vault(true, &mut handle);
+        assert_eq!(report.quarantined.len(), 2);
+        assert_eq!(count("cred_quarantine", &handle), 2);
+        let report = check_vault(false, &mut handle);
+        assert_eq!((report.rows_checked, report.problems.len()), (1, 0));
+        let good = give_credentials(String::from("good"), &mut handle).unwrap();
+        assert_eq!(good.value.expose(), "value");
+    }
+}
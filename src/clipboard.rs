@@ -0,0 +1,285 @@
+use crate::io::debug;
+use crate::models::givme::GivMe;
+use crate::models::secret::Secret;
+use crate::settings::load_config;
+use nettle::hash::{Hash, Sha256};
+use std::env;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Clipboard programs tried in order when config has no
+/// `clipboard_copy`, as command to copy stdin and command to print
+/// what clipboard holds
+const CLIPBOARD_COMMANDS: [(&[&str], &[&str]); 5] = [
+    (&["wl-copy"], &["wl-paste", "--no-newline"]),
+    (
+        &["xclip", "-selection", "clipboard"],
+        &["xclip", "-selection", "clipboard", "-o"],
+    ),
+    (
+        &["xsel", "--clipboard", "--input"],
+        &["xsel", "--clipboard", "--output"],
+    ),
+    (&["pbcopy"], &["pbpaste"]),
+    (&["clip"], &[]),
+];
+
+/// Command copying stdin to clipboard and command printing it, the
+/// latter empty if clipboard can't be read
+type ClipboardCommands = (Vec<String>, Vec<String>);
+
+/// Put `text` on clipboard. Clipboard is emptied after `clipboard_clear`
+/// seconds from config if it still holds `text` by then. Returns those
+/// seconds, 0 when clipboard is left as it is.
+pub fn copy_to_clipboard(text: &str, handle: &GivMe) -> Result<u64, String> {
+    let config = load_config(handle)?;
+    let (copy, paste) = clipboard_commands(handle)?
+        .into_iter()
+        .find(|(copy, _)| program_exists(&copy[0]))
+        .ok_or_else(|| {
+            String::from("No clipboard program found, set `clipboard_copy` in config")
+        })?;
+    run_clipboard_command(&copy, Some(text))?;
+    if config.clipboard_clear == 0 {
+        return Ok(0);
+    }
+    if paste.is_empty() {
+        debug("Clipboard can't be read, so it is not cleared");
+        return Ok(0);
+    }
+    schedule_clear(text, config.clipboard_clear)?;
+    Ok(config.clipboard_clear)
+}
+
+/// Wait `seconds` and empty clipboard if it still holds text whose
+/// digest is read from stdin. Runs in background, started by
+/// `copy_to_clipboard()` so the value itself never has to be kept.
+pub fn clear_clipboard_later(seconds: u64, handle: &GivMe) -> Result<(), String> {
+    let mut digest = String::new();
+    std::io::stdin()
+        .read_to_string(&mut digest)
+        .map_err(|err| format!("Can't read clipboard digest: {}", err))?;
+    std::thread::sleep(std::time::Duration::from_secs(seconds));
+
+    let (copy, paste) = clipboard_commands(handle)?
+        .into_iter()
+        .find(|(copy, paste)| !paste.is_empty() && program_exists(&copy[0]))
+        .ok_or_else(|| String::from("No clipboard program found"))?;
+    let current = run_clipboard_command(&paste, None)?;
+    if clipboard_digest(current.expose()) == digest.trim() {
+        run_clipboard_command(&copy, Some(""))?;
+        debug("Clipboard cleared");
+    } else {
+        debug("Clipboard changed since copying, left as it is");
+    }
+    Ok(())
+}
+
+/// Copy and paste commands to try, from config or known programs
+fn clipboard_commands(handle: &GivMe) -> Result<Vec<ClipboardCommands>, String> {
+    let config = load_config(handle)?;
+    if let Some(copy) = config.clipboard_copy.as_deref() {
+        let copy =
+            split_command(copy).map_err(|err| format!("`clipboard_copy` in config: {}", err))?;
+        let paste = split_command(config.clipboard_paste.as_deref().unwrap_or_default())
+            .map_err(|err| format!("`clipboard_paste` in config: {}", err))?;
+        if copy.is_empty() {
+            return Err(String::from("`clipboard_copy` in config is empty"));
+        }
+        return Ok(vec![(copy, paste)]);
+    }
+    Ok(CLIPBOARD_COMMANDS
+        .iter()
+        .map(|(copy, paste)| {
+            let owned = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+            (owned(copy), owned(paste))
+        })
+        .collect())
+}
+
+/// Program and arguments of a command from config. Quotes and
+/// backslashes work like in a shell, nothing else is expanded.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("Unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        /* Like a shell, backslash escapes only these in double quotes */
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(String::from("Unterminated double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("Unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(String::from("Command ends with a backslash")),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Start a copy of this program which clears clipboard later
+fn schedule_clear(text: &str, seconds: u64) -> Result<(), String> {
+    let exe = env::current_exe().map_err(|err| err.to_string())?;
+    let mut command = Command::new(exe);
+    command
+        .args(["clear-clipboard", "--after"])
+        .arg(seconds.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    /* Own process group, so closing the terminal does not stop it */
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command
+        .spawn()
+        .map_err(|err| format!("Can't start clipboard clearing: {}", err))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(clipboard_digest(text).as_bytes())
+        .map_err(|err| format!("Can't start clipboard clearing: {}", err))
+}
+
+/// Run a clipboard program. It copies `input` if given, otherwise what
+/// it prints is returned.
+fn run_clipboard_command(command: &[String], input: Option<&str>) -> Result<Secret, String> {
+    /* Copying programs may stay in background holding stdout open */
+    let (stdin, stdout) = match input {
+        Some(_) => (Stdio::piped(), Stdio::null()),
+        None => (Stdio::null(), Stdio::piped()),
+    };
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("Can't run {}: {}", command[0], err))?;
+    if let Some(input) = input {
+        /* Dropping stdin closes it, so program knows text is complete */
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .map_err(|err| format!("Can't write to {}: {}", command[0], err))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("Can't run {}: {}", command[0], err))?;
+    let printed = Secret::from(String::from_utf8_lossy(&output.stdout).to_string());
+    if output.status.success() {
+        Ok(printed)
+    } else {
+        Err(format!("{} exited with {}", command[0], output.status))
+    }
+}
+
+/// Whether `program` is a path to a file or found in `PATH`
+fn program_exists(program: &str) -> bool {
+    if Path::new(program).components().count() > 1 {
+        return Path::new(program).is_file();
+    }
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| {
+            dir.join(program).is_file() || dir.join(format!("{}.exe", program)).is_file()
+        })
+    })
+}
+
+/// Hex SHA-256 of clipboard text, so clearing process never sees it
+fn clipboard_digest(text: &str) -> String {
+    let mut hasher = Sha256::default();
+    let mut digest = [0u8; 32];
+    hasher.update(text.as_bytes());
+    hasher.digest(&mut digest);
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::config_path;
+    use crate::utils::{lock_test_home, test_dir, test_vault};
+    use std::fs;
+
+    #[test]
+    fn splits_commands_like_a_shell() {
+        let split = |command: &str| split_command(command).unwrap();
+        assert_eq!(
+            split("  xclip  -selection clipboard "),
+            ["xclip", "-selection", "clipboard"]
+        );
+        assert_eq!(
+            split("'/opt/my tools/copy' --quiet"),
+            ["/opt/my tools/copy", "--quiet"]
+        );
+        assert_eq!(split(r#""a \"b\" \$c \d" e\ f"#), [r#"a "b" $c \d"#, "e f"]);
+        assert_eq!(split("'' \"\" x''y"), ["", "", "xy"]);
+        assert!(split("").is_empty());
+        assert!(split_command("'open").is_err());
+        assert!(split_command("\"open").is_err());
+        assert!(split_command("trailing\\").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_with_configured_command() {
+        use std::os::unix::fs::PermissionsExt;
+        let _home = lock_test_home();
+        let handle = test_vault("json", None);
+        let dir = test_dir("clipboard");
+        let program = format!("{}/fake copy", dir);
+        let clipboard = format!("{}/clip board", dir);
+        fs::write(&program, "#!/bin/sh\ncat > \"$1\"\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = config_path(&handle).unwrap();
+        let mut settings = fs::read_to_string(&config).unwrap();
+        settings += &format!(
+            "clipboard_copy = '{}' \"{}\"\nclipboard_paste = cat '{}'\nclipboard_clear = 0\n",
+            program, clipboard, clipboard
+        );
+        fs::write(&config, settings).unwrap();
+
+        assert_eq!(copy_to_clipboard("s3cret value", &handle), Ok(0));
+        assert_eq!(fs::read_to_string(&clipboard).unwrap(), "s3cret value");
+        let (copy, paste) = clipboard_commands(&handle).unwrap().remove(0);
+        assert_eq!(copy, [program, clipboard]);
+        assert_eq!(
+            run_clipboard_command(&paste, None).unwrap().expose(),
+            "s3cret value"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
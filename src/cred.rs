@@ -1,27 +1,41 @@
-use crate::encryption::{decrypt, encrypt};
+use crate::backup::auto_backup;
+use crate::encryption::{decrypt, encrypt, encrypt_legacy, name_index, open_name, seal_name};
 use crate::models::credentials::Credentials;
 use crate::models::givme::GivMe;
-use crate::sql::*;
+use crate::models::secret::Secret;
+use crate::storage::{storage, storage_mut};
 
-/// A sort of wrapper to `get_from_sql()`. This function take
+/// A sort of wrapper to `StorageBackend::get()`. This function take
 /// care of all encryption and decryption needed to retreive
-/// data from Sqlite.
+/// data from storage.
 pub fn give_credentials(key: String, handle: &mut GivMe) -> Option<Credentials> {
-    let original_key = base64::encode(encrypt(key.clone(), handle).unwrap());
-    let mut creds: Vec<Credentials> = get_from_sql(&original_key, handle);
+    let mut creds: Vec<Credentials> = find_row(&key, handle)
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
     if creds.is_empty() {
         None
     } else {
         creds[0].key = key;
-        creds[0].value = decrypt(&base64::decode(creds[0].value.clone()).unwrap(), handle)
-            .unwrap()
-            .to_string();
+        creds[0].name = None;
+        creds[0].value = Secret::from(
+            decrypt(&base64::decode(creds[0].value.expose()).unwrap(), handle).unwrap(),
+        );
+        let cred = &mut creds[0];
+        for field in [&mut cred.otp, &mut cred.username] {
+            if let Some(encrypted) = field.take() {
+                *field = Some(Secret::from(
+                    decrypt(&base64::decode(encrypted.expose()).unwrap(), handle).unwrap(),
+                ));
+            }
+        }
+        if let Some(encrypted) = cred.url.take() {
+            cred.url = Some(decrypt(&base64::decode(encrypted).unwrap(), handle).unwrap());
+        }
         if let Some(info) = &creds[0].info {
-            creds[0].info = Some(
-                decrypt(&base64::decode(info).unwrap(), handle)
-                    .unwrap()
-                    .to_string(),
-            );
+            creds[0].info = Some(Secret::from(
+                decrypt(&base64::decode(info.expose()).unwrap(), handle).unwrap(),
+            ));
             Some(creds[0].clone())
         } else {
             Some(creds[0].clone())
@@ -32,38 +46,275 @@ pub fn give_credentials(key: String, handle: &mut GivMe) -> Option<Credentials>
 /// Prints Credential struct to a user. Mainly used to
 /// print Credential from user's query. Not for debugging.
 pub fn show_credentials(cred: &Credentials) {
-    println!("\nHere's your '{}':  {}", cred.key, cred.value);
+    println!("\nHere's your '{}':  {}", cred.key, cred.value.expose());
     if cred.info != None {
-        println!("Note: {}", cred.info.as_ref().unwrap());
+        println!("Note: {}", cred.info.as_ref().unwrap().expose());
+    }
+    if let Some(username) = &cred.username {
+        println!("Username: {}", username.expose());
+    }
+    if let Some(url) = &cred.url {
+        println!("URL: {}", url);
+    }
+    if cred.otp.is_some() {
+        println!("2FA: Run `givme otp {}` for current code", cred.key);
     }
 }
 
-/// Save credentails to Sqlite. Somewhat like a wrapper to
-/// `save_to_sql()` but this take care of all encryption and
+/// Save credentails to storage. Somewhat like a wrapper to
+/// `StorageBackend::put()` but this take care of all encryption and
 /// decryption to save anything to database.
 pub fn save_credentials(mut cred: Credentials, handle: &mut GivMe) -> Result<bool, String> {
-    cred.key = base64::encode(encrypt(cred.key, handle).unwrap());
-    match already_exist_in_sql(cred.key.clone(), handle) {
+    let name = cred.key.clone();
+    match find_row(&name, handle) {
         Ok(exist) => {
-            if exist {
+            if exist.is_some() {
                 return Err("Key Already Exist. Choose some other name.".to_string());
             } else {
-                cred.value = base64::encode(encrypt(cred.value, handle).unwrap());
-                if let Some(info) = cred.info {
-                    cred.info = Some(base64::encode(encrypt(info, handle).unwrap()));
-                }
+                set_row_name(&mut cred, &name, handle)?;
+                encrypt_fields(&mut cred, handle);
+                Ok(storage_mut(handle).put(&cred).is_ok())
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Stored row of entry `name`, still encrypted. Rows saved before names
+/// were indexed are found by their deterministically encrypted name.
+pub(crate) fn find_row(name: &str, handle: &mut GivMe) -> Result<Option<Credentials>, String> {
+    let index = name_index(name, handle)?;
+    if let Some(row) = storage(handle).get(&index)? {
+        return Ok(Some(row));
+    }
+    match encrypt_legacy(name, handle) {
+        Some(legacy_key) => storage(handle).get(&base64::encode(legacy_key)),
+        None => Ok(None),
+    }
+}
 
-                Ok(save_to_sql(cred, handle).is_ok())
+/// Plain name of a stored row
+pub(crate) fn row_name(row: &Credentials, handle: &GivMe) -> Result<String, String> {
+    match &row.name {
+        Some(sealed) => open_name(sealed, handle),
+        None => decrypt(
+            &base64::decode(&row.key).map_err(|err| err.to_string())?,
+            handle,
+        ),
+    }
+}
+
+/// Store `row` as entry `name`, under the name index and with the name
+/// sealed under a fresh nonce
+pub(crate) fn set_row_name(
+    row: &mut Credentials,
+    name: &str,
+    handle: &GivMe,
+) -> Result<(), String> {
+    row.key = name_index(name, handle)?;
+    row.name = Some(seal_name(name, handle)?);
+    Ok(())
+}
+
+/// Name of row marking that `migrate_entry_names()` is done. Listed in
+/// `RESERVED_ROWS` so it never shows up as an entry.
+const NAMES_INDEXED_ROW: &str = "names_indexed";
+
+/// Move rows saved before names were indexed under their name index,
+/// once per vault. Vault is backed up first, nothing is written once the
+/// vault is marked as migrated.
+pub fn migrate_entry_names(handle: &mut GivMe) -> Result<usize, String> {
+    if storage(handle).get(NAMES_INDEXED_ROW)?.is_some() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    if storage(handle).list()?.iter().any(|row| row.name.is_none()) {
+        auto_backup("migrate", handle)?;
+        count = upgrade_entry_names(handle)?;
+    }
+    storage_mut(handle).put(&Credentials::new(
+        String::from(NAMES_INDEXED_ROW),
+        String::from("1"),
+        String::new(),
+    ))?;
+    Ok(count)
+}
+
+/// Move rows saved before names were indexed under their name index.
+/// Modification time is kept, a newer row already saved under the index
+/// wins. Old rows are removed without a tombstone, so sync and git don't
+/// delete them from vaults not yet upgraded. Rows which don't decrypt
+/// are left for `givme check`.
+pub fn upgrade_entry_names(handle: &mut GivMe) -> Result<usize, String> {
+    let mut upgraded: Vec<(String, Credentials)> = Vec::new();
+    for row in storage(handle).list()? {
+        if row.name.is_some() {
+            continue;
+        }
+        if let Ok(name) = row_name(&row, handle) {
+            let mut new_row = row.clone();
+            set_row_name(&mut new_row, &name, handle)?;
+            new_row.modified = Some(row.modified.unwrap_or_default());
+            upgraded.push((row.key, new_row));
+        }
+    }
+    if upgraded.is_empty() {
+        return Ok(0);
+    }
+    storage_mut(handle).transaction(&mut |storage| {
+        for (old_key, new_row) in &upgraded {
+            match storage.get(&new_row.key)? {
+                Some(newer) if newer.modified >= new_row.modified => {}
+                _ => storage.put(new_row)?,
             }
+            storage.remove(old_key)?;
         }
-        Err(e) => Err(e.to_string()),
+        Ok(())
+    })?;
+    Ok(upgraded.len())
+}
+
+/// Encrypt every field of `cred` except the key, in place
+fn encrypt_fields(cred: &mut Credentials, handle: &mut GivMe) {
+    cred.value = Secret::from(base64::encode(
+        encrypt(cred.value.expose(), handle).unwrap(),
+    ));
+    for field in [&mut cred.info, &mut cred.otp, &mut cred.username] {
+        if let Some(plain) = field.take() {
+            *field = Some(Secret::from(base64::encode(
+                encrypt(plain.expose(), handle).unwrap(),
+            )));
+        }
+    }
+    if let Some(plain) = cred.url.take() {
+        cred.url = Some(base64::encode(encrypt(&plain, handle).unwrap()));
     }
 }
 
-pub fn delete_credentails(mut key: String, handle: &mut GivMe) -> Result<bool, String> {
-    key = base64::encode(encrypt(key, handle).unwrap());
-    match del_from_sql(key, handle) {
-        Ok(_r) => Ok(true),
-        Err(err) => Err(err.to_string()),
+/// Decrypt every field of `cred` including the name, in place
+pub(crate) fn decrypt_fields(cred: &mut Credentials, handle: &GivMe) -> Result<(), String> {
+    let decrypt_field = |field: &str| -> Result<String, String> {
+        decrypt(
+            &base64::decode(field).map_err(|err| err.to_string())?,
+            handle,
+        )
+    };
+    cred.key = row_name(cred, handle)?;
+    cred.name = None;
+    cred.value = Secret::from(decrypt_field(cred.value.expose())?);
+    for field in [&mut cred.info, &mut cred.otp, &mut cred.username] {
+        if let Some(encrypted) = field.take() {
+            *field = Some(Secret::from(decrypt_field(encrypted.expose())?));
+        }
+    }
+    if let Some(encrypted) = cred.url.take() {
+        cred.url = Some(decrypt_field(&encrypted)?);
+    }
+    Ok(())
+}
+
+/// Retreive and decrypt every saved entry, sorted by name. Rows which
+/// fail to decrypt are returned as errors instead of stopping.
+pub fn give_all_credentials(handle: &mut GivMe) -> Vec<Result<Credentials, String>> {
+    let mut creds: Vec<Result<Credentials, String>> = storage(handle)
+        .list()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|mut cred| decrypt_fields(&mut cred, handle).map(|_| cred))
+        .collect();
+    creds.sort_by(|a, b| match (a, b) {
+        (Ok(a), Ok(b)) => a.key.cmp(&b.key),
+        (Ok(_), Err(_)) => std::cmp::Ordering::Less,
+        (Err(_), Ok(_)) => std::cmp::Ordering::Greater,
+        (Err(_), Err(_)) => std::cmp::Ordering::Equal,
+    });
+    creds
+}
+
+/// Replace an already saved entry without asking. Unlike
+/// `save_credentials()` entry must already exist.
+pub fn overwrite_credentials(mut cred: Credentials, handle: &mut GivMe) -> Result<bool, String> {
+    let name = cred.key.clone();
+    match find_row(&name, handle) {
+        Ok(Some(row)) => {
+            set_row_name(&mut cred, &name, handle)?;
+            encrypt_fields(&mut cred, handle);
+            /* Row saved before names were indexed is replaced */
+            storage_mut(handle).transaction(&mut |storage| {
+                storage.put(&cred)?;
+                if row.key != cred.key {
+                    storage.delete(&row.key)?;
+                }
+                Ok(())
+            })?;
+            Ok(true)
+        }
+        Ok(None) => Err("Key does not exist.".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check if an entry with given name is saved
+pub fn credentials_exist(key: String, handle: &mut GivMe) -> Result<bool, String> {
+    find_row(&key, handle).map(|row| row.is_some())
+}
+
+pub fn delete_credentails(key: String, handle: &mut GivMe) -> Result<bool, String> {
+    match find_row(&key, handle)? {
+        Some(row) => storage_mut(handle).delete(&row.key),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::backup_dir;
+    use crate::utils::{lock_test_home, test_vault};
+    use std::fs;
+
+    /// Row the way versions before the name index saved entry `name`
+    fn legacy_row(name: &str, handle: &mut GivMe) -> Credentials {
+        let key = base64::encode(encrypt_legacy(name, handle).unwrap());
+        let value = base64::encode(encrypt("legacy value", handle).unwrap());
+        Credentials::new(key, value, String::new())
+    }
+
+    fn migrate_backups(handle: &GivMe) -> usize {
+        match fs::read_dir(backup_dir(handle).unwrap()) {
+            Ok(entries) => entries
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    name.to_string_lossy().ends_with("-migrate.db")
+                })
+                .count(),
+            Err(_) => 0,
+        }
+    }
+
+    #[test]
+    fn entry_names_are_migrated_once() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        /* Nothing to move in a new vault, only the marker is written */
+        assert!(storage(&handle).get(NAMES_INDEXED_ROW).unwrap().is_some());
+        assert_eq!(migrate_backups(&handle), 0);
+
+        storage_mut(&mut handle).remove(NAMES_INDEXED_ROW).unwrap();
+        let row = legacy_row("github", &mut handle);
+        storage_mut(&mut handle).put(&row).unwrap();
+        assert_eq!(migrate_entry_names(&mut handle), Ok(1));
+        assert_eq!(migrate_backups(&handle), 1);
+        assert!(storage(&handle).get(&row.key).unwrap().is_none());
+        assert!(storage(&handle).tombstones().unwrap().is_empty());
+        let cred = give_credentials(String::from("github"), &mut handle).unwrap();
+        assert_eq!(cred.value.expose(), "legacy value");
+
+        /* Marked as done, later rows are left for sync and git to move */
+        let later = legacy_row("gitlab", &mut handle);
+        storage_mut(&mut handle).put(&later).unwrap();
+        assert_eq!(migrate_entry_names(&mut handle), Ok(0));
+        assert_eq!(migrate_backups(&handle), 1);
+        assert!(storage(&handle).get(&later.key).unwrap().is_some());
     }
 }
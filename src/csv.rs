@@ -0,0 +1,95 @@
+/// Split CSV data into rows of fields (RFC 4180). Quoted fields may
+/// hold separators, doubled quotes and line breaks. Empty lines are
+/// dropped.
+pub fn parse_csv(data: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = data.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_quotes = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                row.push(std::mem::take(&mut field));
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(String::from("Unterminated quoted field"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Quote a field if it holds anything special to CSV
+pub fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_fields() {
+        let rows =
+            parse_csv("name,value\r\n\"a, b\",\"line 1\nline 2\"\n\"say \"\"hi\"\"\",\"\"\n")
+                .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["name", "value"],
+                vec!["a, b", "line 1\nline 2"],
+                vec!["say \"hi\"", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_empty_fields_and_drops_empty_lines() {
+        let rows = parse_csv("\u{feff}a,,c\n\n,\n\rlast").unwrap();
+        assert_eq!(rows, vec![vec!["a", "", "c"], vec!["", ""], vec!["last"]]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quote() {
+        assert!(parse_csv("a,\"b\nc").is_err());
+        assert!(parse_csv("\"").is_err());
+        assert!(parse_csv("\"a\"\"").is_err());
+    }
+
+    #[test]
+    fn escaped_fields_parse_back() {
+        let fields = ["plain", "a,b", "say \"hi\"", "two\nlines", "cr\r", ""];
+        let line: Vec<String> = fields.iter().map(|field| csv_escape(field)).collect();
+        let rows = parse_csv(&line.join(",")).unwrap();
+        assert_eq!(rows, vec![fields.to_vec()]);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::cred::give_all_credentials;
+use crate::json::{to_json, JsonValue};
+use crate::models::enums::EnvFormat;
+use crate::models::givme::GivMe;
+use crate::models::secret::Secret;
+
+/// Names accepted by `givme env --shell`
+pub const ENV_FORMATS: [&str; 4] = ["dotenv", "sh", "fish", "json"];
+
+pub fn parse_env_format(name: &str) -> Result<EnvFormat, String> {
+    match name {
+        "dotenv" => Ok(EnvFormat::Dotenv),
+        "sh" => Ok(EnvFormat::Sh),
+        "fish" => Ok(EnvFormat::Fish),
+        "json" => Ok(EnvFormat::Json),
+        _ => Err(format!("Unknown env format '{}'", name)),
+    }
+}
+
+/// Variable name for entry `name` in `folder`, `None` when entry is not
+/// in it or is named like the folder itself. Name is taken relative to
+/// folder unless `full_name` and put after `prefix`, then in upper case
+/// with everything but letters, digits and `_` turned to `_`.
+pub fn env_variable_name(
+    name: &str,
+    folder: &str,
+    prefix: &str,
+    full_name: bool,
+) -> Option<String> {
+    let folder = folder.trim_matches('/');
+    let relative = if folder.is_empty() {
+        name
+    } else {
+        name.strip_prefix(folder)?.strip_prefix('/')?
+    };
+    if relative.is_empty() {
+        return None;
+    }
+    let name = if full_name { name } else { relative };
+    let mut variable: String = format!("{}{}", prefix, name)
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    if variable.starts_with(|c: char| c.is_ascii_digit()) {
+        variable.insert(0, '_');
+    }
+    Some(variable)
+}
+
+/// Every entry in `folder` as variables, sorted by variable name. Fails
+/// when two entries end up with same variable name. Entries have no tags,
+/// so a selector without `/` matching no folder fails instead of giving
+/// nothing.
+pub fn folder_variables(
+    folder: &str,
+    prefix: &str,
+    full_name: bool,
+    handle: &mut GivMe,
+) -> Result<Vec<(String, Secret)>, String> {
+    let mut variables: Vec<(String, String, Secret)> = Vec::new();
+    for cred in give_all_credentials(handle) {
+        let cred = match cred {
+            Ok(cred) => cred,
+            Err(err) => {
+                eprintln!("-- Skipping an entry which failed to decrypt: {}", err);
+                continue;
+            }
+        };
+        if let Some(variable) = env_variable_name(&cred.key, folder, prefix, full_name) {
+            variables.push((variable, cred.key, cred.value));
+        }
+    }
+    if variables.is_empty() && !folder.contains('/') {
+        return Err(format!(
+            "No folder '{}'. Entries have no tags, select a folder like `{}/`",
+            folder, folder
+        ));
+    }
+    variables.sort_by(|a, b| a.0.cmp(&b.0));
+    for pair in variables.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(format!(
+                "'{}' and '{}' would both be {}, rename one of them",
+                pair[0].1, pair[1].1, pair[0].0
+            ));
+        }
+    }
+    Ok(variables
+        .into_iter()
+        .map(|(variable, _, value)| (variable, value))
+        .collect())
+}
+
+/// `variables` written in `format`, quoted so values are taken literally
+pub fn render_env(format: EnvFormat, variables: &[(String, Secret)]) -> Secret {
+    match format {
+        EnvFormat::Json => {
+            let object = variables
+                .iter()
+                .map(|(name, value)| (name.clone(), JsonValue::String(value.expose().to_string())))
+                .collect();
+            Secret::from(to_json(&JsonValue::Object(object)) + "\n")
+        }
+        EnvFormat::Dotenv => render_lines(variables, |name, value| {
+            format!("{}={}", name, dotenv_quote(value))
+        }),
+        EnvFormat::Sh => render_lines(variables, |name, value| {
+            format!("export {}='{}'", name, value.replace('\'', "'\\''"))
+        }),
+        EnvFormat::Fish => render_lines(variables, |name, value| {
+            format!(
+                "set -x {} '{}'",
+                name,
+                value.replace('\\', "\\\\").replace('\'', "\\'")
+            )
+        }),
+    }
+}
+
+/// One line per variable, as `line` writes it
+fn render_lines(variables: &[(String, Secret)], line: impl Fn(&str, &str) -> String) -> Secret {
+    let mut output = String::new();
+    for (name, value) in variables {
+        let line = line(name, value.expose());
+        output += &line;
+        output.push('\n');
+        /* Intermediate copies hold the value too */
+        drop(Secret::from(line));
+    }
+    Secret::from(output)
+}
+
+/// Single quotes when value allows, which dotenv readers take literally,
+/// otherwise double quotes with escapes
+fn dotenv_quote(value: &str) -> String {
+    if !value.contains(['\'', '\n', '\r']) {
+        return format!("'{}'", value);
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted += "\\\\",
+            '"' => quoted += "\\\"",
+            '$' => quoted += "\\$",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::save_credentials;
+    use crate::models::credentials::Credentials;
+    use crate::utils::{lock_test_home, test_vault};
+
+    #[test]
+    fn names_variables_relative_to_folder() {
+        let name = |name: &str, folder: &str| env_variable_name(name, folder, "", false);
+        assert_eq!(
+            name("app/db-password", "app"),
+            Some(String::from("DB_PASSWORD"))
+        );
+        assert_eq!(name("app/db/user", "/app/"), Some(String::from("DB_USER")));
+        assert_eq!(name("app/2fa", "app"), Some(String::from("_2FA")));
+        assert_eq!(name("other/key", "app"), None);
+        assert_eq!(name("application/key", "app"), None);
+        assert_eq!(name("app/", "app"), None);
+        assert_eq!(name("app", "app"), None);
+        assert_eq!(
+            env_variable_name("app/key", "app", "my_", true),
+            Some(String::from("MY_APP_KEY"))
+        );
+    }
+
+    #[test]
+    fn selects_folders_only() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        for name in ["dev/db-pass", "dev/aws/key", "prod/db-pass"] {
+            let cred = Credentials::new(name.to_string(), format!("{} value", name), String::new());
+            save_credentials(cred, &mut handle).unwrap();
+        }
+        let names = |folder: &str, handle: &mut GivMe| {
+            folder_variables(folder, "", false, handle).map(|variables| {
+                variables
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(names("dev", &mut handle).unwrap(), ["AWS_KEY", "DB_PASS"]);
+        assert_eq!(names("dev/", &mut handle).unwrap(), ["AWS_KEY", "DB_PASS"]);
+        assert_eq!(names("/", &mut handle).unwrap().len(), 3);
+        assert!(names("staging/", &mut handle).unwrap().is_empty());
+        assert_eq!(
+            names("backend", &mut handle),
+            Err(String::from(
+                "No folder 'backend'. Entries have no tags, select a folder like `backend/`"
+            ))
+        );
+    }
+
+    #[test]
+    fn quotes_values_for_each_format() {
+        let variables = [
+            (String::from("A"), Secret::from("it's $HOME \\")),
+            (String::from("B"), Secret::from("line\nbreak")),
+        ];
+        let render = |format| render_env(format, &variables).expose().to_string();
+        assert_eq!(
+            render(EnvFormat::Dotenv),
+            "A=\"it's \\$HOME \\\\\"\nB=\"line\\nbreak\"\n"
+        );
+        assert_eq!(
+            render(EnvFormat::Sh),
+            "export A='it'\\''s $HOME \\'\nexport B='line\nbreak'\n"
+        );
+        assert_eq!(
+            render(EnvFormat::Fish),
+            "set -x A 'it\\'s $HOME \\\\'\nset -x B 'line\nbreak'\n"
+        );
+        assert_eq!(
+            render(EnvFormat::Json),
+            "{\n  \"A\": \"it's $HOME \\\\\",\n  \"B\": \"line\\nbreak\"\n}\n"
+        );
+    }
+}
@@ -1,43 +1,237 @@
 use crate::models::credentials::Credentials;
 use crate::models::givme::GivMe;
-use crate::save_to_sql;
-use crate::{adjust_password_length, get_from_sql, io::*};
+use crate::models::secret::Secret;
+use crate::storage::{storage_mut, stored_secret_key};
+use crate::{
+    adjust_password_length,
+    io::*,
+    utils::{constant_time_eq, wipe},
+};
+use crate::{refuse_shredding_output, shred_file, write_file_atomically};
+use nettle::aead::{Aead, ChaChaPoly1305};
 use nettle::cipher::{Cipher, Des3, Twofish};
+use nettle::hash::Sha256;
+use nettle::kdf::{hkdf, pbkdf2};
+use nettle::mac::{Hmac, Mac};
+use rand::random;
 use rpassword::read_password;
 use std::io::Write;
 use std::process::exit;
 use std::{fs, io};
 
 /// Encrypt a file with 2 step encryption. File can be decrypted
-/// on same computer on which it was encrypted.
+/// on same computer on which it was encrypted. Use
+/// `encrypt_file_with_passphrase()` for files leaving this computer.
+///
+/// Output is written atomically, an existing destination is only
+/// replaced when `force` is set. With `shred` the source file is
+/// overwritten and removed once the encrypted copy is in place. Shredding
+/// is refused when output and source are the same file.
 ///
 /// ~This function do not empose any restrictions on size of file.
 /// On large files, use at own risk
-pub fn encrypt_file(in_path: String, out_path: String, handle: &mut GivMe) -> io::Result<()> {
-    fs::write(&out_path, "".to_string())?; /* Check if we have privileges to write to target dest */
-    let data = std::fs::read(&in_path)?;
-    let encrypted_data = base64::encode(encrypt(base64::encode(data), handle).unwrap());
-    fs::write(&out_path, encrypted_data)
+pub fn encrypt_file(
+    in_path: String,
+    out_path: String,
+    force: bool,
+    shred: bool,
+    handle: &mut GivMe,
+) -> io::Result<()> {
+    if shred {
+        refuse_shredding_output(&in_path, &out_path)?;
+    }
+    let data = fs::read(&in_path)?;
+    let encrypted_data = base64::encode(
+        encrypt(&base64::encode(data), handle)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+    );
+    write_file_atomically(&out_path, encrypted_data.as_bytes(), force)?;
+    if shred {
+        shred_file(&in_path)?;
+    }
+    Ok(())
 }
 
 /// Encrypt a file with 2 step decryption. File should be encrypted
 /// on same computer on which it needs to decrypted.
 ///
+/// Destination is left untouched if decryption fails. An existing
+/// destination is only replaced when `force` is set.
+///
 /// ~This function do not empose any restrictions on size of file.
 /// On large files, use at own risk
-pub fn decrypt_file(in_path: String, out_path: String, handle: &mut GivMe) -> io::Result<()> {
-    fs::write(&out_path, "".to_string())?; /* Check if we have privileges to write to target dest */
-    let data = std::fs::read(&in_path)?;
-    let decrypted_data =
-        base64::decode(decrypt(&base64::decode(data).unwrap(), handle).unwrap()).unwrap();
-    fs::write(&out_path, decrypted_data)
+pub fn decrypt_file(
+    in_path: String,
+    out_path: String,
+    force: bool,
+    handle: &mut GivMe,
+) -> io::Result<()> {
+    let data = fs::read(&in_path)?;
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+    if is_portable_file(&data) {
+        return Err(invalid(String::from(
+            "File was encrypted with a passphrase. Use --passphrase",
+        )));
+    }
+    let decoded = base64::decode(data).map_err(|err| invalid(err.to_string()))?;
+    let decrypted_data = base64::decode(decrypt(&decoded, handle).map_err(invalid)?)
+        .map_err(|err| invalid(err.to_string()))?;
+    write_file_atomically(&out_path, &decrypted_data, force)
+}
+
+/// First line of files encrypted with a standalone passphrase. Used to
+/// tell them apart from files encrypted with keys of local vault.
+pub const PORTABLE_FILE_HEADER: &str = "GIVME-PORTABLE-2";
+
+/// Header of files encrypted with a passphrase by older versions, without
+/// authentication. Still decrypted, never written.
+const PORTABLE_FILE_HEADER_V1: &str = "GIVME-PORTABLE-1";
+
+/// Prefix added to data by older versions before encryption with a
+/// passphrase, to detect a wrong passphrase
+const PORTABLE_DATA_PREFIX: &str = "givme:";
+
+const PORTABLE_SALT_SIZE: usize = 16;
+const PORTABLE_KDF_ITERATIONS: u32 = 200_000;
+
+/// Build a detached `GivMe` which does not have any Sql connection, with
+/// both keys used by `encrypt()` and `decrypt()` taken from `derived`.
+/// Needs at least 42 bytes of key material.
+pub(crate) fn handle_from_key_material(derived: &[u8]) -> GivMe {
+    /* Keys are used as strings everywhere, so keep them printable */
+    GivMe {
+        key: Some(Secret::from(base64::encode(&derived[..24]))),
+        password: Some(Secret::from(base64::encode(&derived[24..42]))),
+        sql_con: None,
+        os: None,
+        username: None,
+        storage: None,
+    }
+}
+
+/// Derive ChaCha20-Poly1305 key of portable files from a passphrase
+fn portable_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::<Sha256>(
+        passphrase.as_bytes(),
+        salt,
+        PORTABLE_KDF_ITERATIONS,
+        &mut key,
+    );
+    key
+}
+
+/// Derive both keys used by `encrypt()` and `decrypt()` from a passphrase,
+/// as files with `PORTABLE_FILE_HEADER_V1` were encrypted
+fn handle_from_passphrase(passphrase: &str, salt: &[u8]) -> GivMe {
+    let mut derived = [0u8; 42];
+    pbkdf2::<Sha256>(
+        passphrase.as_bytes(),
+        salt,
+        PORTABLE_KDF_ITERATIONS,
+        &mut derived,
+    );
+    handle_from_key_material(&derived)
+}
+
+/// Check if data was produced by `encrypt_file_with_passphrase()`
+pub fn is_portable_file(data: &[u8]) -> bool {
+    data.starts_with(PORTABLE_FILE_HEADER.as_bytes())
+        || data.starts_with(PORTABLE_FILE_HEADER_V1.as_bytes())
+}
+
+/// Encrypt bytes with a standalone passphrase into a printable blob
+/// starting with `PORTABLE_FILE_HEADER`. Blob is sealed with
+/// ChaCha20-Poly1305 and can be opened on any computer with
+/// `decrypt_with_passphrase()`.
+pub fn encrypt_with_passphrase(data: &[u8], passphrase: &str) -> Result<String, String> {
+    let salt: Vec<u8> = (0..PORTABLE_SALT_SIZE).map(|_| random::<u8>()).collect();
+    let mut key = portable_key(passphrase, &salt);
+    let encrypted_data = aead_seal(&key, PORTABLE_FILE_HEADER.as_bytes(), data);
+    wipe(&mut key);
+    let encrypted_data = encrypted_data?;
+    Ok(format!(
+        "{}\n{}\n{}",
+        PORTABLE_FILE_HEADER,
+        base64::encode(&salt),
+        base64::encode(encrypted_data)
+    ))
+}
+
+/// Open a blob produced by `encrypt_with_passphrase()`. Fails on a wrong
+/// passphrase or if blob was tampered with.
+pub fn decrypt_with_passphrase(blob: &str, passphrase: &str) -> Result<Vec<u8>, String> {
+    let mut lines = blob.lines();
+    let legacy = match lines.next() {
+        Some(PORTABLE_FILE_HEADER) => false,
+        Some(PORTABLE_FILE_HEADER_V1) => true,
+        _ => return Err(String::from("Data was not encrypted with a passphrase")),
+    };
+    let salt = base64::decode(lines.next().unwrap_or_default()).map_err(|err| err.to_string())?;
+    let encrypted_data =
+        base64::decode(lines.next().unwrap_or_default()).map_err(|err| err.to_string())?;
+
+    if !legacy {
+        let mut key = portable_key(passphrase, &salt);
+        let decrypted = aead_open(&key, PORTABLE_FILE_HEADER.as_bytes(), &encrypted_data);
+        wipe(&mut key);
+        return decrypted
+            .ok_or_else(|| String::from("Invalid Passphrase or data was tampered with"));
+    }
+    let handle = handle_from_passphrase(passphrase, &salt);
+    match decrypt(&encrypted_data, &handle) {
+        Ok(decrypted) if decrypted.starts_with(PORTABLE_DATA_PREFIX) => {
+            base64::decode(&decrypted[PORTABLE_DATA_PREFIX.len()..]).map_err(|err| err.to_string())
+        }
+        _ => Err(String::from("Invalid Passphrase")),
+    }
+}
+
+/// Encrypt a file with a standalone passphrase. Unlike `encrypt_file()`,
+/// file can be decrypted on any computer by anyone knowing the passphrase.
+///
+/// Output is written atomically, see `encrypt_file()` for `force` and `shred`.
+pub fn encrypt_file_with_passphrase(
+    in_path: String,
+    out_path: String,
+    passphrase: &str,
+    force: bool,
+    shred: bool,
+) -> io::Result<()> {
+    if shred {
+        refuse_shredding_output(&in_path, &out_path)?;
+    }
+    let data = fs::read(&in_path)?;
+    let output = encrypt_with_passphrase(&data, passphrase)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    write_file_atomically(&out_path, output.as_bytes(), force)?;
+    if shred {
+        shred_file(&in_path)?;
+    }
+    Ok(())
+}
+
+/// Decrypt a file encrypted by `encrypt_file_with_passphrase()`. Does not
+/// need local vault at all.
+///
+/// Destination is left untouched on wrong passphrase.
+pub fn decrypt_file_with_passphrase(
+    in_path: String,
+    out_path: String,
+    passphrase: &str,
+    force: bool,
+) -> io::Result<()> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+    let data = fs::read_to_string(&in_path).map_err(|err| invalid(err.to_string()))?;
+    let decrypted_data = decrypt_with_passphrase(&data, passphrase).map_err(invalid)?;
+    write_file_atomically(&out_path, &decrypted_data, force)
 }
 
 /// Retrieve secret key from the database
 /// This key is used in encryption process with one encryption standard and
 /// another key will be given by user
-pub fn get_secret_key(handle: &mut GivMe) -> String {
-    let encrypted_key = base64::decode(&get_from_sql("secret_key", handle)[0].value).unwrap();
+pub fn get_secret_key(handle: &mut GivMe) -> Secret {
+    let encrypted_key = base64::decode(stored_secret_key(handle)).unwrap();
     let decrypted_pass = decrypt(&encrypted_key, handle);
 
     if decrypted_pass.is_err() {
@@ -45,7 +239,7 @@ pub fn get_secret_key(handle: &mut GivMe) -> String {
         std::process::exit(1);
     }
     let decrypted_pass = decrypted_pass.unwrap();
-    return decrypted_pass;
+    return Secret::from(decrypted_pass);
 }
 
 /// Store / replace secret key to the database
@@ -64,21 +258,21 @@ pub fn set_secret_key(handle: &mut GivMe) {
         print!("Confirm your Secret Key: ");
         std::io::stdout().flush().unwrap();
         confirm_key = read_password().unwrap().trim().to_string();
-        if confirm_key != key {
+        if confirm_key != key.expose() {
             eprintln!("Unmatched Secret Key. Try Again...\n");
         } else {
             break;
         }
     }
 
-    if key.len() < 32 {
+    if key.expose().len() < 32 {
         println!("Secret Key provided is shorter than 32 characters");
         print!("Auto Resize? (y/n) ");
         std::io::stdout().flush().unwrap();
         std::io::stdin().read_line(&mut option).unwrap();
         if option.chars().next().is_some() {
             if option.to_lowercase().chars().next().unwrap() == 'y' {
-                confirm_key = adjust_password_length(&key, 32);
+                confirm_key = adjust_password_length(key.expose(), 32);
             } else {
                 println!("Exiting...");
                 exit(0)
@@ -89,82 +283,85 @@ pub fn set_secret_key(handle: &mut GivMe) {
         }
     }
 
-    let encrypted = encrypt(confirm_key, handle).unwrap();
+    let encrypted = encrypt(&confirm_key, handle).unwrap();
 
     let encrypted_final_key = base64::encode(encrypted);
     debug("Adding to database");
-    save_to_sql(
-        Credentials::new(
+    storage_mut(handle)
+        .put(&Credentials::new(
             String::from("secret_key"),
             encrypted_final_key,
             String::new(),
-        ),
-        handle,
-    )
-    .unwrap();
+        ))
+        .unwrap();
 }
 
+/// Marks data padded with PKCS#7 by `encrypt()`. Older versions filled
+/// data up with NULs instead, `decrypt()` still reads those.
+const PADDED_DATA_MARKER: &[u8] = b"\x00\x01";
+
 /// Encrypt given data with randomly generated string and
 /// user's master key. 2 Encryption algorithms are used
 /// TwoFish and 3DES.
-pub fn encrypt(data: String, handle: &mut GivMe) -> Result<Vec<u8>, String> {
-    let mut data_length: usize = 0;
-    let mut filler = String::new();
+///
+/// Data is prefixed with `PADDED_DATA_MARKER` and padded with PKCS#7,
+/// so `decrypt()` gives back exactly the same bytes.
+pub fn encrypt(data: &str, handle: &mut GivMe) -> Result<Vec<u8>, String> {
     if handle.key.is_none() {
         ask_pass_and_extract_key(handle).unwrap();
     }
 
-    for i in 1.. {
-        if (i * Twofish::BLOCK_SIZE) >= data.chars().count() {
-            data_length = i * Twofish::BLOCK_SIZE;
-            break;
-        }
-    }
+    let mut padded = [PADDED_DATA_MARKER, data.as_bytes()].concat();
+    let padding = Twofish::BLOCK_SIZE - padded.len() % Twofish::BLOCK_SIZE;
+    padded.extend(vec![padding as u8; padding]);
     debug(
         format!(
             "Data suppied for encryption will fit in {} bytes",
-            data_length
+            padded.len()
         )
         .as_str(),
     );
+    let encrypted = encrypt_blocks(&padded, handle);
+    wipe(&mut padded);
+    Ok(encrypted)
+}
 
-    for _ in 0..(data_length - data.chars().count()) {
-        filler = [&filler, "\x00"].join("");
+/// Encrypt like versions before `PADDED_DATA_MARKER` did, filling data
+/// up with NULs to a length counted in chars. Only used to find rows
+/// saved under names encrypted that way.
+///
+/// Those versions cut data to that length in bytes. `None` when the cut
+/// falls inside a char, as such names were never saved readable.
+pub(crate) fn encrypt_legacy(data: &str, handle: &mut GivMe) -> Option<Vec<u8>> {
+    if handle.key.is_none() {
+        ask_pass_and_extract_key(handle).unwrap();
     }
 
-    let final_data = format!("{}{}", &data, &filler);
-    debug(format!("Data to encrypt with filler: {:?}", final_data.as_bytes()).as_str());
-    let mut one_step_encrypted: Vec<u8> = Vec::with_capacity(data_length);
-    let mut two_step_encrypted: Vec<u8> = Vec::with_capacity(data_length);
-
-    for _ in 0..data_length {
-        one_step_encrypted.push(1);
-        two_step_encrypted.push(1);
+    let chars = data.chars().count();
+    let data_length = chars.div_ceil(Twofish::BLOCK_SIZE).max(1) * Twofish::BLOCK_SIZE;
+    let final_data = Secret::from(format!("{}{}", data, "\x00".repeat(data_length - chars)));
+    if !final_data.expose().is_char_boundary(data_length) {
+        return None;
     }
+    Some(encrypt_blocks(
+        &final_data.expose().as_bytes()[..data_length],
+        handle,
+    ))
+}
 
-    /*
-    println!(
-        "{} {} {}",
-        Twofish::KEY_SIZE,
-        Twofish::BLOCK_SIZE,
-        Des3::KEY_SIZE
-    );
-    println!(
-        "{:?} {:?} {:?}",
-        &one_step_encrypted[..],
-        &final_data.as_bytes(),
-        handle.key
-    );
-    */
+/// Encrypt data, already a multiple of block size, with both ciphers
+fn encrypt_blocks(data: &[u8], handle: &GivMe) -> Vec<u8> {
+    let mut one_step_encrypted: Vec<u8> = vec![1; data.len()];
+    let mut two_step_encrypted: Vec<u8> = vec![1; data.len()];
 
-    Twofish::with_encrypt_key(handle.key.clone().unwrap().as_bytes())
+    Twofish::with_encrypt_key(handle.key.as_ref().unwrap().expose().as_bytes())
         .unwrap()
-        .encrypt(&mut one_step_encrypted[..], &final_data.as_bytes());
-    Des3::with_encrypt_key(&handle.password.clone().unwrap().as_bytes())
+        .encrypt(&mut one_step_encrypted[..], data);
+    Des3::with_encrypt_key(handle.password.as_ref().unwrap().expose().as_bytes())
         .unwrap()
         .encrypt(&mut two_step_encrypted[..], &one_step_encrypted);
-    debug(format!("{:?}", &two_step_encrypted[..]).as_str());
-    Ok(two_step_encrypted)
+    wipe(&mut one_step_encrypted);
+    two_step_encrypted
 }
 
 /// Decrypt given data with randomly generated string and
@@ -190,15 +387,261 @@ pub fn decrypt(data: &[u8], handle: &GivMe) -> Result<String, String> {
 
     //println!("{}{}", Twofish::KEY_SIZE, Twofish::BLOCK_SIZE);
 
-    Des3::with_decrypt_key(handle.password.clone().unwrap().as_bytes())
+    Des3::with_decrypt_key(handle.password.as_ref().unwrap().expose().as_bytes())
         .unwrap()
         .decrypt(&mut one_step_decrypted[..], data);
-    Twofish::with_decrypt_key(handle.key.clone().unwrap().as_bytes())
+    Twofish::with_decrypt_key(handle.key.as_ref().unwrap().expose().as_bytes())
         .unwrap()
         .decrypt(&mut two_step_decrypted[..], &one_step_decrypted);
-    debug(format!("{:?}", &two_step_decrypted[..]).as_str());
-    match std::str::from_utf8(&two_step_decrypted) {
-        Ok(v) => Ok(v.trim_matches(char::from(0)).to_string()),
-        Err(e) => Err(e.to_string()),
+    let decrypted = match unpad(&two_step_decrypted) {
+        Some(data) => std::str::from_utf8(data)
+            .map(String::from)
+            .map_err(|err| err.to_string()),
+        /* Written before PKCS#7 padding, NULs were only ever filler */
+        None => match std::str::from_utf8(&two_step_decrypted) {
+            Ok(v) => Ok(v.trim_matches(char::from(0)).to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+    };
+    wipe(&mut one_step_decrypted);
+    wipe(&mut two_step_decrypted);
+    decrypted
+}
+
+/// Data padded by `encrypt()`, `None` if it was not padded that way
+fn unpad(padded: &[u8]) -> Option<&[u8]> {
+    let data = padded.strip_prefix(PADDED_DATA_MARKER)?;
+    let padding = *data.last()? as usize;
+    if padding == 0 || padding > Twofish::BLOCK_SIZE || padding > data.len() {
+        return None;
+    }
+    let (data, filler) = data.split_at(data.len() - padding);
+    if filler.iter().all(|byte| *byte as usize == padding) {
+        Some(data)
+    } else {
+        None
+    }
+}
+
+/// Derive a 32 byte subkey of vault keys for one purpose, named by `info`
+fn vault_subkey(handle: &GivMe, info: &str) -> Result<[u8; 32], String> {
+    let (key, password) = match (&handle.key, &handle.password) {
+        (Some(key), Some(password)) => (key, password),
+        _ => return Err(String::from("Vault is locked")),
+    };
+    let material = Secret::from(format!("{}{}", key.expose(), password.expose()));
+    let mut subkey = [0u8; 32];
+    hkdf::<Sha256>(
+        material.expose().as_bytes(),
+        &[],
+        info.as_bytes(),
+        &mut subkey,
+    );
+    Ok(subkey)
+}
+
+/// Key of row holding entry `name`. HMAC of the name under a key derived
+/// from vault keys, so same name always finds same row while the name
+/// itself can be encrypted with a random nonce.
+pub fn name_index(name: &str, handle: &GivMe) -> Result<String, String> {
+    let mut key = vault_subkey(handle, "givme name index")?;
+    let mut hmac = Hmac::<Sha256>::with_key(&key);
+    wipe(&mut key);
+    hmac.update(name.as_bytes());
+    let mut digest = vec![0u8; hmac.mac_size()];
+    hmac.digest(&mut digest).map_err(|err| err.to_string())?;
+    Ok(base64::encode(digest))
+}
+
+/// Encrypt `data` with ChaCha20-Poly1305 under a fresh random nonce.
+/// Output is nonce, ciphertext and tag. `ad` is authenticated too but
+/// not included in output.
+pub(crate) fn aead_seal(key: &[u8; 32], ad: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce: Vec<u8> = (0..ChaChaPoly1305::NONCE_SIZE)
+        .map(|_| random::<u8>())
+        .collect();
+    let mut aead =
+        ChaChaPoly1305::with_key_and_nonce(key, &nonce).map_err(|err| err.to_string())?;
+    aead.update(ad);
+    let mut sealed = vec![0u8; data.len()];
+    aead.encrypt(&mut sealed, data);
+    let mut digest = vec![0u8; ChaChaPoly1305::DIGEST_SIZE];
+    aead.digest(&mut digest);
+    Ok([nonce, sealed, digest].concat())
+}
+
+/// Data encrypted by `aead_seal()` with same `key` and `ad`. `None` when
+/// tag does not match, data was tampered with or key is wrong.
+pub(crate) fn aead_open(key: &[u8; 32], ad: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    let overhead = ChaChaPoly1305::NONCE_SIZE + ChaChaPoly1305::DIGEST_SIZE;
+    if data.len() < overhead {
+        return None;
+    }
+    let (nonce, rest) = data.split_at(ChaChaPoly1305::NONCE_SIZE);
+    let (sealed, digest) = rest.split_at(rest.len() - ChaChaPoly1305::DIGEST_SIZE);
+    let mut aead = ChaChaPoly1305::with_key_and_nonce(key, nonce).ok()?;
+    aead.update(ad);
+    let mut opened = vec![0u8; sealed.len()];
+    aead.decrypt(&mut opened, sealed);
+    let mut expected = vec![0u8; ChaChaPoly1305::DIGEST_SIZE];
+    aead.digest(&mut expected);
+    if !constant_time_eq(&expected, digest) {
+        wipe(&mut opened);
+        return None;
+    }
+    Some(opened)
+}
+
+/// Encrypt entry name with ChaCha20-Poly1305 under a random nonce. Same
+/// name gives different output every time.
+pub fn seal_name(name: &str, handle: &GivMe) -> Result<String, String> {
+    let mut key = vault_subkey(handle, "givme name")?;
+    let sealed = aead_seal(&key, &[], name.as_bytes());
+    wipe(&mut key);
+    Ok(base64::encode(sealed?))
+}
+
+/// Entry name encrypted by `seal_name()`. Fails if it was tampered with.
+pub fn open_name(sealed: &str, handle: &GivMe) -> Result<String, String> {
+    let data = base64::decode(sealed).map_err(|err| err.to_string())?;
+    let mut key = vault_subkey(handle, "givme name")?;
+    let name = aead_open(&key, &[], &data);
+    wipe(&mut key);
+    match name {
+        Some(name) => String::from_utf8(name).map_err(|err| err.to_string()),
+        None => Err(String::from("Name was tampered with")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_handle() -> GivMe {
+        handle_from_key_material(&[7u8; 42])
+    }
+
+    /// Row as versions before `PADDED_DATA_MARKER` wrote it
+    fn legacy_row(data: &str, handle: &GivMe) -> Vec<u8> {
+        let length = data.len().div_ceil(Twofish::BLOCK_SIZE).max(1) * Twofish::BLOCK_SIZE;
+        let mut filled = data.as_bytes().to_vec();
+        filled.resize(length, 0);
+        encrypt_blocks(&filled, handle)
+    }
+
+    #[test]
+    fn values_round_trip_exactly() {
+        let mut handle = test_handle();
+        let values = [
+            "",
+            "\0",
+            "\0leading",
+            "trailing\0\0",
+            "\0both\0",
+            "\u{1}\0",
+            "exactly 14 b\0\u{1}",
+            "fifteen bytes!!",
+            "sixteen bytes!!!",
+            "P\u{159}\u{ed}li\u{161} \u{17e}lu\u{165}ou\u{10d}k\u{fd} k\u{16f}\u{148}",
+            "\u{1f511} emoji \u{1f510}",
+        ];
+        for value in values {
+            let encrypted = encrypt(value, &mut handle).unwrap();
+            assert_eq!(encrypted.len() % Twofish::BLOCK_SIZE, 0);
+            assert_eq!(decrypt(&encrypted, &handle).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn legacy_rows_still_decrypt() {
+        let handle = test_handle();
+        for value in ["", "key", "exactly 16 bytes", "a bit more than 16 bytes"] {
+            assert_eq!(
+                decrypt(&legacy_row(value, &handle), &handle).unwrap(),
+                value
+            );
+        }
+        /* Filled up with NULs, so not mistaken for PKCS#7 padding */
+        let marked = "\0\u{1}padded\u{2}\u{2}";
+        assert_eq!(
+            decrypt(&legacy_row(marked, &handle), &handle).unwrap(),
+            marked.trim_matches('\0')
+        );
+        assert!(decrypt(&[0u8; 15], &handle).is_err());
+    }
+
+    #[test]
+    fn legacy_names_encrypt_like_older_versions() {
+        let mut handle = test_handle();
+        for name in ["github", "exactly 16 bytes", "a bit more than 16 bytes"] {
+            let legacy = encrypt_legacy(name, &mut handle).unwrap();
+            assert_eq!(legacy, legacy_row(name, &handle));
+            assert_eq!(decrypt(&legacy, &handle).unwrap(), name);
+        }
+        /* Filler absorbs extra bytes of short non ASCII names */
+        let legacy = encrypt_legacy("ab\u{20ac}", &mut handle).unwrap();
+        assert_eq!(decrypt(&legacy, &handle).unwrap(), "ab\u{20ac}");
+        /* Cut of 16 bytes would split last char */
+        let split = format!("a{}", "\u{e9}".repeat(15));
+        assert!(encrypt_legacy(&split, &mut handle).is_none());
+    }
+
+    #[test]
+    fn passphrase_blobs_are_authenticated() {
+        let data = b"\0binary \xff data";
+        let blob = encrypt_with_passphrase(data, "correct horse").unwrap();
+        assert!(blob.starts_with("GIVME-PORTABLE-2\n"));
+        assert!(is_portable_file(blob.as_bytes()));
+        assert_eq!(
+            decrypt_with_passphrase(&blob, "correct horse").unwrap(),
+            data
+        );
+        assert_ne!(
+            blob,
+            encrypt_with_passphrase(data, "correct horse").unwrap()
+        );
+
+        let invalid = Err(String::from("Invalid Passphrase or data was tampered with"));
+        assert_eq!(decrypt_with_passphrase(&blob, "wrong horse"), invalid);
+        let lines: Vec<&str> = blob.lines().collect();
+        let mut encrypted = base64::decode(lines[2]).unwrap();
+        encrypted[ChaChaPoly1305::NONCE_SIZE] ^= 1;
+        let tampered = format!("{}\n{}\n{}", lines[0], lines[1], base64::encode(encrypted));
+        assert_eq!(decrypt_with_passphrase(&tampered, "correct horse"), invalid);
+        /* Header is authenticated, so blob can't pass as another version */
+        let relabeled = blob.replacen("GIVME-PORTABLE-2", "GIVME-PORTABLE-1", 1);
+        assert!(decrypt_with_passphrase(&relabeled, "correct horse").is_err());
+    }
+
+    #[test]
+    fn legacy_passphrase_blobs_still_decrypt() {
+        let salt = [5u8; PORTABLE_SALT_SIZE];
+        let mut handle = handle_from_passphrase("correct horse", &salt);
+        let encrypted = encrypt(
+            &format!("{}{}", PORTABLE_DATA_PREFIX, base64::encode("old data")),
+            &mut handle,
+        )
+        .unwrap();
+        let blob = format!(
+            "GIVME-PORTABLE-1\n{}\n{}",
+            base64::encode(salt),
+            base64::encode(encrypted)
+        );
+        assert!(is_portable_file(blob.as_bytes()));
+        assert_eq!(
+            decrypt_with_passphrase(&blob, "correct horse").unwrap(),
+            b"old data"
+        );
+        assert!(decrypt_with_passphrase(&blob, "wrong horse").is_err());
+    }
+
+    #[test]
+    fn unpad_checks_marker_and_padding() {
+        assert_eq!(unpad(b"\x00\x01data\x02\x02"), Some(&b"data"[..]));
+        assert_eq!(unpad(b"\x00\x01\x01"), Some(&b""[..]));
+        assert_eq!(unpad(b"data\x02\x02"), None);
+        assert_eq!(unpad(b"\x00\x01data\x01\x02"), None);
+        assert_eq!(unpad(b"\x00\x01data\x00"), None);
+        assert_eq!(unpad(b"\x00\x01"), None);
     }
 }
@@ -0,0 +1,264 @@
+use crate::csv::csv_escape;
+use crate::json::{to_json, JsonValue};
+use crate::models::{credentials::Credentials, enums::ExportFormat, secret::exposed};
+use crate::xml::xml_escape;
+use rand::random;
+
+/// Names accepted by `givme export --format`
+pub const EXPORT_FORMATS: [&str; 3] = ["json", "csv", "keepass-xml"];
+
+/// Version written to JSON exports, checked again on import
+pub const EXPORT_JSON_VERSION: f64 = 1.0;
+
+/// Parse name given to `--format`
+pub fn parse_export_format(name: &str) -> Result<ExportFormat, String> {
+    match name {
+        "json" => Ok(ExportFormat::Json),
+        "csv" => Ok(ExportFormat::Csv),
+        "keepass-xml" => Ok(ExportFormat::KeepassXml),
+        _ => Err(format!("Unknown export format '{}'", name)),
+    }
+}
+
+pub(crate) fn optional_json(value: Option<&str>) -> JsonValue {
+    match value {
+        Some(value) => JsonValue::String(value.to_string()),
+        None => JsonValue::Null,
+    }
+}
+
+/// Every entry as JSON, readable by `givme import --format givme-json`
+fn export_json(creds: &[Credentials]) -> String {
+    let entries = creds
+        .iter()
+        .map(|cred| {
+            JsonValue::Object(vec![
+                (String::from("name"), JsonValue::String(cred.key.clone())),
+                (
+                    String::from("password"),
+                    JsonValue::String(cred.value.expose().to_string()),
+                ),
+                (
+                    String::from("username"),
+                    optional_json(exposed(&cred.username)),
+                ),
+                (String::from("url"), optional_json(cred.url.as_deref())),
+                (String::from("notes"), optional_json(exposed(&cred.info))),
+                (String::from("otp"), optional_json(exposed(&cred.otp))),
+            ])
+        })
+        .collect();
+    to_json(&JsonValue::Object(vec![
+        (
+            String::from("givme_export"),
+            JsonValue::Number(EXPORT_JSON_VERSION),
+        ),
+        (String::from("entries"), JsonValue::Array(entries)),
+    ]))
+}
+
+/// Every entry as CSV with a header row, readable by `givme import`
+/// and most other password managers
+fn export_csv(creds: &[Credentials]) -> String {
+    let mut output = String::from("name,username,password,url,notes,totp\n");
+    for cred in creds {
+        let fields = [
+            cred.key.as_str(),
+            exposed(&cred.username).unwrap_or_default(),
+            cred.value.expose(),
+            cred.url.as_deref().unwrap_or_default(),
+            exposed(&cred.info).unwrap_or_default(),
+            exposed(&cred.otp).unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_escape(field)).collect();
+        output.push_str(&row.join(","));
+        output.push('\n');
+    }
+    output
+}
+
+/// Random UUID in the base64 form KeePass uses
+fn keepass_uuid() -> String {
+    base64::encode(random::<[u8; 16]>())
+}
+
+/// Folder tree built from `/` in entry names
+#[derive(Default)]
+struct KeepassGroup {
+    name: String,
+    entries: Vec<(String, Credentials)>,
+    groups: Vec<KeepassGroup>,
+}
+
+impl KeepassGroup {
+    fn insert(&mut self, path: &[&str], title: &str, cred: &Credentials) {
+        match path.split_first() {
+            None => self.entries.push((title.to_string(), cred.clone())),
+            Some((first, rest)) => {
+                let position = match self.groups.iter().position(|group| group.name == *first) {
+                    Some(position) => position,
+                    None => {
+                        self.groups.push(KeepassGroup {
+                            name: first.to_string(),
+                            ..KeepassGroup::default()
+                        });
+                        self.groups.len() - 1
+                    }
+                };
+                self.groups[position].insert(rest, title, cred);
+            }
+        }
+    }
+
+    fn write(&self, indent: usize, output: &mut String) {
+        let pad = "\t".repeat(indent);
+        output.push_str(&format!(
+            "{}<Group>\n{}\t<UUID>{}</UUID>\n{}\t<Name>{}</Name>\n",
+            pad,
+            pad,
+            keepass_uuid(),
+            pad,
+            xml_escape(&self.name)
+        ));
+        for (title, cred) in &self.entries {
+            output.push_str(&format!(
+                "{}\t<Entry>\n{}\t\t<UUID>{}</UUID>\n",
+                pad,
+                pad,
+                keepass_uuid()
+            ));
+            let strings = [
+                ("Title", Some(title.as_str())),
+                ("UserName", exposed(&cred.username)),
+                ("Password", Some(cred.value.expose())),
+                ("URL", cred.url.as_deref()),
+                ("Notes", exposed(&cred.info)),
+                ("otp", exposed(&cred.otp)),
+            ];
+            for (key, value) in strings.iter() {
+                if let Some(value) = value {
+                    output.push_str(&format!(
+                        "{}\t\t<String>\n{}\t\t\t<Key>{}</Key>\n{}\t\t\t<Value{}>{}</Value>\n{}\t\t</String>\n",
+                        pad,
+                        pad,
+                        key,
+                        pad,
+                        if *key == "Password" { " ProtectInMemory=\"True\"" } else { "" },
+                        xml_escape(value),
+                        pad
+                    ));
+                }
+            }
+            output.push_str(&format!("{}\t</Entry>\n", pad));
+        }
+        for group in &self.groups {
+            group.write(indent + 1, output);
+        }
+        output.push_str(&format!("{}</Group>\n", pad));
+    }
+}
+
+/// Every entry as KeePass 2 XML. Parts of names before `/` become groups.
+fn export_keepass_xml(creds: &[Credentials]) -> String {
+    let mut root = KeepassGroup {
+        name: String::from("givme"),
+        ..KeepassGroup::default()
+    };
+    for cred in creds {
+        let parts: Vec<&str> = cred.key.split('/').collect();
+        let (title, path) = parts.split_last().unwrap();
+        root.insert(path, title, cred);
+    }
+    let mut output = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<KeePassFile>\n\t<Meta>\n\t\t<Generator>givme</Generator>\n\t</Meta>\n\t<Root>\n",
+    );
+    root.write(2, &mut output);
+    output.push_str("\t</Root>\n</KeePassFile>\n");
+    output
+}
+
+/// Write `creds` in given format. Output is plain text, encrypt it
+/// with `encrypt_with_passphrase()` if it leaves this computer.
+pub fn export_credentials(format: ExportFormat, creds: &[Credentials]) -> String {
+    match format {
+        ExportFormat::Json => export_json(creds),
+        ExportFormat::Csv => export_csv(creds),
+        ExportFormat::KeepassXml => export_keepass_xml(creds),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::read_import;
+    use crate::models::enums::ImportFormat;
+    use crate::models::secret::Secret;
+
+    /// Entries with every field and characters each format has to escape
+    fn awkward_entries() -> Vec<Credentials> {
+        let mut full = Credentials::new(
+            String::from("work/mail/\"quoted\", <b>&co</b>"),
+            String::from("p,a\"s\ns<w>&rd ünï"),
+            String::from("first line\nsecond, \"line\" <3"),
+        );
+        full.username = Some(Secret::from("me@example.com"));
+        full.url = Some(String::from("https://example.com/?a=1&b=2"));
+        full.otp = Some(Secret::from(
+            "otpauth://totp/mail?secret=JBSWY3DPEHPK3PXP&period=30&algorithm=SHA1&digits=6",
+        ));
+        let bare = Credentials::new(String::from("bare"), String::from("x"), String::new());
+        vec![full, bare]
+    }
+
+    fn fields(cred: &Credentials) -> [Option<String>; 6] {
+        [
+            Some(cred.key.clone()),
+            Some(cred.value.expose().to_string()),
+            exposed(&cred.username).map(String::from),
+            cred.url.clone(),
+            exposed(&cred.info).map(String::from),
+            exposed(&cred.otp).map(String::from),
+        ]
+    }
+
+    fn round_trip(export: ExportFormat, import: ImportFormat) {
+        let creds = awkward_entries();
+        let exported = export_credentials(export, &creds);
+        let imported: Vec<Credentials> = read_import(import, &exported)
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let mut expected: Vec<_> = creds.iter().map(fields).collect();
+        let mut found: Vec<_> = imported.iter().map(fields).collect();
+        /* KeePass lists entries of a group before its subgroups */
+        expected.sort();
+        found.sort();
+        assert_eq!(found, expected, "{:?}", export);
+    }
+
+    #[test]
+    fn exports_read_back_unchanged() {
+        round_trip(ExportFormat::Json, ImportFormat::GivmeJson);
+        round_trip(ExportFormat::Csv, ImportFormat::Csv);
+        round_trip(ExportFormat::KeepassXml, ImportFormat::KeepassXml);
+    }
+
+    #[test]
+    fn keepass_groups_follow_folders() {
+        let creds: Vec<Credentials> = ["a/b/one", "a/two", "a/b/three", "top"]
+            .iter()
+            .map(|name| Credentials::new(name.to_string(), String::from("x"), String::new()))
+            .collect();
+        let xml = export_credentials(ExportFormat::KeepassXml, &creds);
+        assert_eq!(xml.matches("<Group>").count(), 3);
+        assert_eq!(xml.matches("<Name>a</Name>").count(), 1);
+        assert_eq!(xml.matches("<Name>b</Name>").count(), 1);
+        assert!(xml.contains("<Value ProtectInMemory=\"True\">x</Value>"));
+        assert_eq!(
+            parse_export_format("keepass-xml"),
+            Ok(ExportFormat::KeepassXml)
+        );
+        assert!(parse_export_format("xml").is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use crate::backup::auto_backup;
+use crate::cred::{row_name, upgrade_entry_names};
+use crate::encryption::decrypt;
+use crate::json::{parse_json, to_json, JsonValue};
+use crate::models::{
+    credentials::Credentials, enums::SyncPolicy, givme::GivMe, sync_summary::SyncSummary,
+};
+use crate::os::vault_path;
+use crate::storage::{
+    credentials_from_json, credentials_to_json, storage, storage_mut, tombstone_from_json,
+    tombstone_to_json, MemoryBackend,
+};
+use crate::sync::merge_vaults;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory inside git working copy holding one file per entry
+const ENTRIES_DIR: &str = "entries";
+
+/// File holding encrypted master key row. Lets `givme git pull` refuse
+/// a remote made from a different vault.
+const SECRET_FILE: &str = "secret_key";
+
+/// One file of `ENTRIES_DIR`, either a saved or a deleted entry
+enum GitRecord {
+    Entry(Credentials),
+    Tombstone(String, u64),
+}
+
+/// Identity of commits and merges made by givme, so git does not need
+/// one configured by user
+const GIT_IDENTITY: [&str; 4] = ["-c", "user.name=givme", "-c", "user.email=givme@localhost"];
+
+/// Git working copy of vault, next to default vault and config file
+pub fn git_dir(handle: &GivMe) -> Result<PathBuf, String> {
+    let vault = vault_path(handle)?;
+    Ok(Path::new(&vault)
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("git"))
+}
+
+/// Run git in `dir`, returning its output or what it complained about
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(GIT_IDENTITY)
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Can't run git: {}", err))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Working copy of an initialized repository
+fn initialized_dir(handle: &GivMe) -> Result<PathBuf, String> {
+    let dir = git_dir(handle)?;
+    if !dir.join(".git").exists() {
+        return Err(String::from("No git repository yet, run `givme git init`"));
+    }
+    Ok(dir)
+}
+
+/// File name of an entry. Keys are base64, made safe for file names.
+fn entry_file_name(key: &str) -> String {
+    format!(
+        "{}.json",
+        key.trim_end_matches('=')
+            .replace('/', "_")
+            .replace('+', "-")
+    )
+}
+
+/// Key of an entry from its file name, reverse of `entry_file_name()`
+fn key_of_file_name(name: &str) -> Option<String> {
+    let stem = Path::new(name)
+        .file_name()?
+        .to_str()?
+        .strip_suffix(".json")?;
+    let key = stem.replace('_', "/").replace('-', "+");
+    let padding = (4 - key.len() % 4) % 4;
+    Some(format!("{}{}", key, "=".repeat(padding)))
+}
+
+fn entry_to_json(cred: &Credentials) -> String {
+    to_json(&credentials_to_json(cred)) + "\n"
+}
+
+fn record_from_json(data: &str) -> Result<GitRecord, String> {
+    let record = parse_json(data)?;
+    if record.get("key").and_then(JsonValue::as_str).is_none() {
+        return Err(String::from("Entry file has no key"));
+    }
+    if let Some((key, deleted)) = tombstone_from_json(&record) {
+        return Ok(GitRecord::Tombstone(key, deleted));
+    }
+    let cred = credentials_from_json(&record)?;
+    Ok(GitRecord::Entry(cred))
+}
+
+/// Encrypted master key row of vault behind `handle`
+fn stored_secret(handle: &GivMe) -> Result<String, String> {
+    storage(handle)
+        .get("secret_key")?
+        .map(|row| row.value.expose().to_string())
+        .ok_or_else(|| String::from("Vault has no master key"))
+}
+
+/// Mirror storage of vault into working copy, whatever kind it is.
+/// Entries stay encrypted exactly as stored. Sealed storage has to be
+/// unlocked to be read, and its entries are then written one file each
+/// like any other.
+fn write_vault_files(dir: &Path, handle: &GivMe) -> Result<(), String> {
+    let entries_dir = dir.join(ENTRIES_DIR);
+    fs::create_dir_all(&entries_dir).map_err(|err| err.to_string())?;
+    fs::write(dir.join(SECRET_FILE), stored_secret(handle)? + "\n")
+        .map_err(|err| err.to_string())?;
+
+    let mut written: HashSet<String> = HashSet::new();
+    for cred in storage(handle).list()? {
+        let name = entry_file_name(&cred.key);
+        fs::write(entries_dir.join(&name), entry_to_json(&cred)).map_err(|err| err.to_string())?;
+        written.insert(name);
+    }
+    for (key, deleted) in storage(handle).tombstones()? {
+        let name = entry_file_name(&key);
+        if written.insert(name.clone()) {
+            fs::write(
+                entries_dir.join(&name),
+                to_json(&tombstone_to_json(&key, deleted)) + "\n",
+            )
+            .map_err(|err| err.to_string())?;
+        }
+    }
+    for file in fs::read_dir(&entries_dir).map_err(|err| err.to_string())? {
+        let file = file.map_err(|err| err.to_string())?;
+        if !written.contains(file.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(file.path()).map_err(|err| err.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Commit current state of vault, returns `false` if nothing changed
+fn commit_vault(dir: &Path, message: &str, handle: &GivMe) -> Result<bool, String> {
+    write_vault_files(dir, handle)?;
+    git(dir, &["add", "-A"])?;
+    if git(dir, &["status", "--porcelain"])?.trim().is_empty() {
+        return Ok(false);
+    }
+    git(dir, &["commit", "-q", "-m", message])?;
+    Ok(true)
+}
+
+/// Create git working copy of vault as asked by `givme git init`,
+/// optionally with a remote to push to. Returns its directory.
+pub fn git_init(remote: Option<&str>, handle: &GivMe) -> Result<PathBuf, String> {
+    let dir = git_dir(handle)?;
+    if dir.join(".git").exists() {
+        return Err(format!("'{}' is already a git repository", dir.display()));
+    }
+    fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+    git(&dir, &["init", "-q"])?;
+    if let Some(remote) = remote {
+        git(&dir, &["remote", "add", "origin", remote])?;
+    }
+    commit_vault(&dir, "Initialize givme vault", handle)?;
+    Ok(dir)
+}
+
+/// Commit vault and push it as asked by `givme git push`
+pub fn git_push(handle: &GivMe) -> Result<(), String> {
+    let dir = initialized_dir(handle)?;
+    commit_vault(&dir, "Update vault", handle)?;
+    git(&dir, &["push", "-q", "-u", "origin", "HEAD"]).map_err(|err| {
+        format!(
+            "{}\nIf remote has changes from another computer, run `givme git pull` first",
+            err
+        )
+    })?;
+    Ok(())
+}
+
+/// Remote branch tracked by working copy, `None` if remote is empty
+fn upstream(dir: &Path) -> Result<Option<String>, String> {
+    if let Ok(upstream) = git(dir, &["rev-parse", "--abbrev-ref", "@{u}"]) {
+        return Ok(Some(upstream.trim().to_string()));
+    }
+    let branch = git(dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let remote_branch = format!("origin/{}", branch.trim());
+    Ok(git(dir, &["rev-parse", "--verify", "-q", &remote_branch])
+        .ok()
+        .map(|_| remote_branch))
+}
+
+/// Fetch and merge changes of other computers as asked by `givme git
+/// pull`. Entries are merged like `givme sync` does, git never merges
+/// the files itself. Vault is backed up before it is changed.
+pub fn git_pull(policy: SyncPolicy, handle: &mut GivMe) -> Result<SyncSummary, String> {
+    let dir = initialized_dir(handle)?;
+    commit_vault(&dir, "Update vault", handle)?;
+    git(&dir, &["fetch", "-q", "origin"])?;
+    let upstream = match upstream(&dir)? {
+        Some(upstream) => upstream,
+        None => return Ok(SyncSummary::default()),
+    };
+    if git(&dir, &["merge-base", "--is-ancestor", &upstream, "HEAD"]).is_ok() {
+        return Ok(SyncSummary::default());
+    }
+
+    let remote_secret = git(&dir, &["show", &format!("{}:{}", upstream, SECRET_FILE)])?;
+    if remote_secret.trim() != stored_secret(handle)? {
+        return Err(String::from(
+            "Remote vault does not share Master Key of this vault",
+        ));
+    }
+    let mut remote = GivMe {
+        key: handle.key.clone(),
+        password: handle.password.clone(),
+        sql_con: None,
+        os: handle.os,
+        username: handle.username.clone(),
+        storage: Some(Box::new(MemoryBackend::new())),
+    };
+    let files = git(
+        &dir,
+        &["ls-tree", "-r", "--name-only", &upstream, ENTRIES_DIR],
+    )?;
+    for file in files.lines() {
+        let data = git(&dir, &["show", &format!("{}:{}", upstream, file)])?;
+        match record_from_json(&data).map_err(|err| format!("{}: {}", file, err))? {
+            GitRecord::Entry(cred) => storage_mut(&mut remote).put(&cred),
+            GitRecord::Tombstone(key, deleted) => {
+                storage_mut(&mut remote).record_tombstone(&key, deleted)
+            }
+        }?;
+    }
+
+    auto_backup("git-pull", handle)?;
+    let summary = merge_vaults(&mut remote, policy, handle)?;
+    /* Remote may still hold entries pushed before names were indexed */
+    upgrade_entry_names(handle)?;
+
+    if git(&dir, &["merge-base", "--is-ancestor", "HEAD", &upstream]).is_ok() {
+        git(&dir, &["merge", "-q", "--ff-only", &upstream])?;
+        commit_vault(&dir, "Update vault", handle)?;
+    } else {
+        /* Tree of merge commit is the merged vault, not what git would make of it */
+        git(
+            &dir,
+            &[
+                "merge",
+                "-q",
+                "--no-commit",
+                "--allow-unrelated-histories",
+                "-s",
+                "ours",
+                &upstream,
+            ],
+        )?;
+        write_vault_files(&dir, handle)?;
+        git(&dir, &["add", "-A"])?;
+        git(
+            &dir,
+            &[
+                "commit",
+                "-q",
+                "-m",
+                &format!("Merge vault from {}", upstream),
+            ],
+        )?;
+    }
+    Ok(summary)
+}
+
+/// Decrypted name of entry `file` changed by `commit`. Indexed entries
+/// only carry their name in the file, for a deleted one it is read from
+/// the commit before.
+fn logged_entry_name(dir: &Path, commit: &str, file: &str, handle: &GivMe) -> Option<String> {
+    for revision in [commit.to_string(), format!("{}^", commit)] {
+        let data = match git(dir, &["show", &format!("{}:{}", revision, file)]) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        if let Ok(GitRecord::Entry(cred)) = record_from_json(&data) {
+            return row_name(&cred, handle).ok();
+        }
+    }
+    /* Tombstone of an entry saved before names were indexed */
+    key_of_file_name(file)
+        .and_then(|key| base64::decode(key).ok())
+        .and_then(|key| decrypt(&key, handle).ok())
+}
+
+/// History of vault as asked by `givme git log`, with names of changed
+/// entries decrypted. Shows last `limit` commits.
+pub fn git_log(limit: usize, handle: &GivMe) -> Result<String, String> {
+    let dir = initialized_dir(handle)?;
+    let log = git(
+        &dir,
+        &[
+            "log",
+            &format!("-{}", limit),
+            "--date=short",
+            "--format=%x01%h %ad %s",
+            "--name-status",
+            "--",
+            ENTRIES_DIR,
+        ],
+    )?;
+    let mut output = String::new();
+    let mut commit = String::new();
+    for line in log.lines().filter(|line| !line.is_empty()) {
+        if let Some(header) = line.strip_prefix('\u{1}') {
+            commit = header.split(' ').next().unwrap_or_default().to_string();
+            output.push_str(header);
+            output.push('\n');
+            continue;
+        }
+        let (status, file) = line.split_once('\t').unwrap_or(("?", line));
+        let name =
+            logged_entry_name(&dir, &commit, file, handle).unwrap_or_else(|| file.to_string());
+        output.push_str(&format!("    {} {}\n", status, name));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::{delete_credentails, give_credentials, save_credentials};
+    use crate::encryption::name_index;
+    use crate::utils::{lock_test_home, test_dir, test_vault};
+
+    fn save(name: &str, value: &str, handle: &mut GivMe) {
+        let cred = Credentials::new(name.to_string(), value.to_string(), String::new());
+        assert!(save_credentials(cred, handle).unwrap());
+    }
+
+    #[test]
+    fn pushes_and_pulls_through_bare_repository() {
+        let _home = lock_test_home();
+        let remote = format!("{}/vault.git", test_dir("remote"));
+        git(Path::new("."), &["init", "-q", "--bare", &remote]).unwrap();
+
+        let mut laptop = test_vault("sqlite", None);
+        save("kept", "laptop value", &mut laptop);
+        save("gone", "deleted later", &mut laptop);
+        git_init(Some(&remote), &laptop).unwrap();
+        assert!(delete_credentails(String::from("gone"), &mut laptop).unwrap());
+        git_push(&laptop).unwrap();
+
+        /* Commits don't need an identity configured by user */
+        let laptop_dir = git_dir(&laptop).unwrap();
+        assert_eq!(
+            git(&laptop_dir, &["log", "-1", "--format=%an <%ae>"])
+                .unwrap()
+                .trim(),
+            "givme <givme@localhost>"
+        );
+
+        let mut desktop = test_vault("sealed", Some(&laptop));
+        save("mine", "desktop value", &mut desktop);
+        git_init(Some(&remote), &desktop).unwrap();
+        let summary = git_pull(SyncPolicy::Newest, &mut desktop).unwrap();
+        assert_eq!(summary.pulled, 1);
+
+        let kept = give_credentials(String::from("kept"), &mut desktop).unwrap();
+        assert_eq!(kept.value.expose(), "laptop value");
+        assert!(give_credentials(String::from("gone"), &mut desktop).is_none());
+        assert!(give_credentials(String::from("mine"), &mut desktop).is_some());
+        assert!(storage(&desktop)
+            .tombstones()
+            .unwrap()
+            .iter()
+            .any(|(key, _)| key == &name_index("gone", &desktop).unwrap()));
+
+        git_push(&desktop).unwrap();
+        let log = git_log(10, &desktop).unwrap();
+        assert!(log.contains("Merge vault from origin/"));
+        assert!(log.contains(" mine\n"));
+        assert!(log.contains(" kept\n"));
+    }
+}
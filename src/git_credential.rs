@@ -0,0 +1,316 @@
+use crate::backup::auto_backup;
+use crate::cred::{
+    credentials_exist, delete_credentails, give_all_credentials, overwrite_credentials,
+    save_credentials,
+};
+use crate::models::credentials::Credentials;
+use crate::models::git_request::GitRequest;
+use crate::models::givme::GivMe;
+use crate::models::secret::{exposed, Secret};
+
+/// Folder new entries from `givme git-credential store` are saved in
+pub const GIT_CREDENTIAL_FOLDER: &str = "git";
+
+/// Read attributes git wrote to stdin. Unknown ones are ignored, as git
+/// may send more in newer versions.
+pub fn parse_git_credential(input: &str) -> Result<GitRequest, String> {
+    let mut request = GitRequest::default();
+    for line in input.lines() {
+        if line.is_empty() {
+            break;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' is not key=value", line))?;
+        let value = value.to_string();
+        match key {
+            "protocol" => request.protocol = Some(value),
+            "host" => request.host = Some(value),
+            "path" => request.path = Some(value),
+            "username" => request.username = Some(value),
+            "password" => request.password = Some(Secret::from(value)),
+            "url" => {
+                let (protocol, host, path) = split_url(&value);
+                request.protocol = protocol.map(String::from);
+                request.host = Some(host.to_string());
+                request.path = path.map(String::from);
+            }
+            _ => {}
+        }
+    }
+    if request.host.is_none() {
+        return Err(String::from("git gave no host"));
+    }
+    Ok(request)
+}
+
+/// Entries whose URL and username fit `request`, sorted by name.
+/// Username is compared only when git asks for one.
+pub fn matching_git_credentials(request: &GitRequest, handle: &mut GivMe) -> Vec<Credentials> {
+    give_all_credentials(handle)
+        .into_iter()
+        .flatten()
+        .filter(|cred| {
+            same_url(cred, request) && (request.username.is_none() || same_username(cred, request))
+        })
+        .collect()
+}
+
+/// Entries `store` and `erase` may change: those under
+/// `GIT_CREDENTIAL_FOLDER` with exactly the URL and username of `request`
+fn stored_git_credentials(request: &GitRequest, handle: &mut GivMe) -> Vec<Credentials> {
+    let folder = format!("{}/", GIT_CREDENTIAL_FOLDER);
+    give_all_credentials(handle)
+        .into_iter()
+        .flatten()
+        .filter(|cred| {
+            cred.key.starts_with(&folder) && same_url(cred, request) && same_username(cred, request)
+        })
+        .collect()
+}
+
+/// `username=` and `password=` lines answering `get`
+pub fn format_git_credential(cred: &Credentials) -> Result<Secret, String> {
+    let password = cred.value.expose();
+    if password.contains(['\n', '\0']) {
+        return Err(format!("Value of '{}' can't be given to git", cred.key));
+    }
+    let mut answer = String::new();
+    if let Some(username) = &cred.username {
+        answer += &format!("username={}\n", username.expose());
+    }
+    answer += "password=";
+    answer += password;
+    answer.push('\n');
+    Ok(Secret::from(answer))
+}
+
+/// Save credential git says worked. Value of the entry under
+/// `GIT_CREDENTIAL_FOLDER` for same URL and username is updated,
+/// otherwise a new entry is added there. Returns name of the entry.
+pub fn store_git_credential(request: &GitRequest, handle: &mut GivMe) -> Result<String, String> {
+    let password = request
+        .password
+        .as_ref()
+        .ok_or_else(|| String::from("git gave no password to store"))?;
+    if let Some(mut cred) = stored_git_credentials(request, handle).into_iter().next() {
+        if cred.value != *password {
+            cred.value = password.clone();
+            overwrite_credentials(cred.clone(), handle)?;
+        }
+        return Ok(cred.key);
+    }
+
+    let host = request.host.as_deref().unwrap_or_default();
+    let mut url = format!(
+        "{}://{}",
+        request.protocol.as_deref().unwrap_or("https"),
+        host
+    );
+    let mut name = format!("{}/{}", GIT_CREDENTIAL_FOLDER, host);
+    if let Some(path) = request.path.as_deref().map(clean_path) {
+        url = format!("{}/{}", url, path);
+        name = format!("{}/{}", name, path);
+    }
+    if let Some(username) = &request.username {
+        name = format!("{}/{}", name, username);
+    }
+    /* Same name may be taken by another protocol or a missing username */
+    let mut unique = name.clone();
+    for number in 2.. {
+        if !credentials_exist(unique.clone(), handle)? {
+            break;
+        }
+        unique = format!("{}-{}", name, number);
+    }
+    let mut cred = Credentials::new(unique.clone(), password.expose().to_string(), String::new());
+    cred.username = request.username.as_deref().map(Secret::from);
+    cred.url = Some(url);
+    if !save_credentials(cred, handle)? {
+        return Err(format!("Can't save '{}'", unique));
+    }
+    Ok(unique)
+}
+
+/// Delete entries under `GIT_CREDENTIAL_FOLDER` holding credential git
+/// says was rejected. Only entries still holding the rejected password
+/// go, so a newer password saved meanwhile is kept. Returns names of
+/// deleted entries.
+pub fn erase_git_credential(
+    request: &GitRequest,
+    handle: &mut GivMe,
+) -> Result<Vec<String>, String> {
+    let password = request
+        .password
+        .as_ref()
+        .ok_or_else(|| String::from("git gave no password to erase"))?;
+    let doomed: Vec<String> = stored_git_credentials(request, handle)
+        .into_iter()
+        .filter(|cred| cred.value == *password)
+        .map(|cred| cred.key)
+        .collect();
+    if doomed.is_empty() {
+        return Ok(doomed);
+    }
+    auto_backup("git-credential erase", handle)?;
+    for name in &doomed {
+        delete_credentails(name.clone(), handle)?;
+    }
+    Ok(doomed)
+}
+
+/// Whether URL of `cred` is the one git asks about. Missing protocol
+/// means https, path has to be same on both sides.
+fn same_url(cred: &Credentials, request: &GitRequest) -> bool {
+    let url = match &cred.url {
+        Some(url) => url.trim(),
+        None => return false,
+    };
+    let (protocol, host, path) = split_url(url);
+    let protocol = protocol.unwrap_or("https");
+    let request_protocol = request.protocol.as_deref().unwrap_or("https");
+    request
+        .host
+        .as_deref()
+        .is_some_and(|request_host| request_host.eq_ignore_ascii_case(host))
+        && protocol.eq_ignore_ascii_case(request_protocol)
+        && clean_path(path.unwrap_or_default())
+            == clean_path(request.path.as_deref().unwrap_or_default())
+}
+
+/// Whether username field of `cred` is exactly what git gave, both
+/// missing counts as same
+fn same_username(cred: &Credentials, request: &GitRequest) -> bool {
+    exposed(&cred.username) == request.username.as_deref()
+}
+
+/// Repository path without surrounding slashes and `.git` suffix
+fn clean_path(path: &str) -> &str {
+    path.trim_matches('/').trim_end_matches(".git")
+}
+
+/// Protocol, host and path of an URL like git writes it. User info in
+/// the URL is dropped, empty path is `None`.
+fn split_url(url: &str) -> (Option<&str>, &str, Option<&str>) {
+    let (protocol, rest) = match url.split_once("://") {
+        Some((protocol, rest)) => (Some(protocol), rest),
+        None => (None, url),
+    };
+    let (authority, path) = match rest.split_once('/') {
+        Some((authority, path)) => (authority, Some(path)),
+        None => (rest, None),
+    };
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let path = path
+        .map(|path| path.split(['?', '#']).next().unwrap_or_default())
+        .filter(|path| !path.is_empty());
+    (protocol, host, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::give_credentials;
+    use crate::utils::{lock_test_home, test_vault};
+
+    fn request(lines: &str) -> GitRequest {
+        parse_git_credential(lines).unwrap()
+    }
+
+    fn found(lines: &str, handle: &mut GivMe) -> Vec<String> {
+        matching_git_credentials(&request(lines), handle)
+            .into_iter()
+            .map(|cred| cred.key)
+            .collect()
+    }
+
+    fn value(name: &str, handle: &mut GivMe) -> Option<String> {
+        give_credentials(name.to_string(), handle).map(|cred| cred.value.expose().to_string())
+    }
+
+    #[test]
+    fn url_without_protocol_or_path_is_no_wildcard() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        let mut cred = Credentials::new(String::from("github"), String::from("old"), String::new());
+        cred.url = Some(String::from("github.com"));
+        cred.username = Some(Secret::from("me"));
+        save_credentials(cred, &mut handle).unwrap();
+
+        assert_eq!(
+            found("protocol=https\nhost=github.com\n", &mut handle),
+            ["github"]
+        );
+        assert_eq!(
+            found("url=https://me@github.com/\nusername=me\n", &mut handle),
+            ["github"]
+        );
+        assert!(found("protocol=http\nhost=github.com\n", &mut handle).is_empty());
+        assert!(found(
+            "protocol=https\nhost=github.com\npath=org/repo.git\n",
+            &mut handle
+        )
+        .is_empty());
+        assert!(found(
+            "protocol=https\nhost=github.com\nusername=you\n",
+            &mut handle
+        )
+        .is_empty());
+        assert!(found("protocol=https\nhost=gitlab.com\n", &mut handle).is_empty());
+    }
+
+    #[test]
+    fn store_and_erase_only_touch_own_entries() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        let mut cred = Credentials::new(String::from("github"), String::from("old"), String::new());
+        cred.url = Some(String::from("https://github.com"));
+        cred.username = Some(Secret::from("me"));
+        save_credentials(cred, &mut handle).unwrap();
+
+        let me = "protocol=https\nhost=github.com\nusername=me\npassword=";
+        let name = store_git_credential(&request(&format!("{}first\n", me)), &mut handle).unwrap();
+        assert_eq!(name, "git/github.com/me");
+        assert_eq!(value("github", &mut handle).as_deref(), Some("old"));
+        let again =
+            store_git_credential(&request(&format!("{}second\n", me)), &mut handle).unwrap();
+        assert_eq!(again, name);
+        assert_eq!(value(&name, &mut handle).as_deref(), Some("second"));
+
+        let other = "protocol=https\nhost=github.com\nusername=other\npassword=second\n";
+        assert_eq!(
+            store_git_credential(&request(other), &mut handle).unwrap(),
+            "git/github.com/other"
+        );
+        let repo = "protocol=https\nhost=github.com\npath=org/repo.git\nusername=me\npassword=x\n";
+        assert_eq!(
+            store_git_credential(&request(repo), &mut handle).unwrap(),
+            "git/github.com/org/repo/me"
+        );
+
+        assert!(erase_git_credential(
+            &request("protocol=https\nhost=github.com\nusername=me\n"),
+            &mut handle
+        )
+        .is_err());
+        assert!(
+            erase_git_credential(&request(&format!("{}first\n", me)), &mut handle)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            erase_git_credential(&request(&format!("{}second\n", me)), &mut handle).unwrap(),
+            [name.as_str()]
+        );
+        assert_eq!(value(&name, &mut handle), None);
+        assert_eq!(value("github", &mut handle).as_deref(), Some("old"));
+        assert_eq!(
+            value("git/github.com/other", &mut handle).as_deref(),
+            Some("second")
+        );
+        assert_eq!(
+            value("git/github.com/org/repo/me", &mut handle).as_deref(),
+            Some("x")
+        );
+    }
+}
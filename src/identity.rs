@@ -0,0 +1,486 @@
+use crate::cred::give_credentials;
+use crate::encryption::{aead_open, aead_seal, decrypt, encrypt, handle_from_key_material};
+use crate::export::export_credentials;
+use crate::import::read_import;
+use crate::io::debug;
+use crate::models::{
+    credentials::Credentials,
+    enums::{ExportFormat, ImportFormat, SharedPayload},
+    givme::GivMe,
+    secret::Secret,
+};
+use crate::os::{refuse_shredding_output, shred_file, write_file_atomically};
+use crate::storage::{storage, storage_mut};
+use crate::utils::wipe;
+use nettle::curve25519::{self, CURVE25519_SIZE};
+use nettle::hash::Sha256;
+use nettle::kdf::hkdf;
+use nettle::random::{Random, Yarrow};
+use std::{fs, io, path::Path};
+
+/// First line of every blob sealed for recipients
+pub const SHARED_BLOB_HEADER: &str = "GIVME-SHARE-2";
+
+/// Header of blobs sealed by older versions, without authentication.
+/// Still opened, never written.
+const SHARED_BLOB_HEADER_V1: &str = "GIVME-SHARE-1";
+
+/// Name of row holding X25519 private key of this vault. Listed
+/// in `RESERVED_ROWS` so it never shows up as an entry.
+const IDENTITY_ROW: &str = "identity_key";
+
+/// Prefix added to data before sealing by older versions, to detect
+/// that a blob was not meant for us
+const SHARED_DATA_PREFIX: &str = "givme:";
+
+/// X25519 keypair of a vault. Private part never leaves the
+/// vault unencrypted.
+pub struct Identity {
+    pub private: Vec<u8>,
+    pub public: Vec<u8>,
+}
+
+/// Retrieve identity keypair from the database. A new keypair is
+/// generated and stored on first use.
+pub fn get_identity(handle: &mut GivMe) -> Result<Identity, String> {
+    let row = storage(handle).get(IDENTITY_ROW)?;
+    let private = if let Some(row) = row {
+        let encrypted = base64::decode(row.value.expose()).map_err(|err| err.to_string())?;
+        base64::decode(decrypt(&encrypted, handle)?).map_err(|err| err.to_string())?
+    } else {
+        debug("No identity found, generating new keypair");
+        let private = curve25519::private_key(&mut Yarrow::default()).to_vec();
+        let encrypted = encrypt(&base64::encode(&private), handle)?;
+        storage_mut(handle).put(&Credentials::new(
+            String::from(IDENTITY_ROW),
+            base64::encode(encrypted),
+            String::new(),
+        ))?;
+        private
+    };
+
+    let mut public = vec![0u8; CURVE25519_SIZE];
+    curve25519::mul_g(&mut public, &private).map_err(|err| err.to_string())?;
+    Ok(Identity { private, public })
+}
+
+/// Public key of this vault in the form expected by `--to` and `--recipient`
+pub fn export_public_key(handle: &mut GivMe) -> Result<String, String> {
+    Ok(base64::encode(get_identity(handle)?.public))
+}
+
+/// Parse a recipient given on command line. Accepts either the
+/// exported public key itself or path to a file containing it.
+pub fn parse_recipient(recipient: &str) -> Result<Vec<u8>, String> {
+    let encoded = if Path::new(recipient).is_file() {
+        let content = fs::read_to_string(recipient).map_err(|err| err.to_string())?;
+        content
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    } else {
+        recipient.trim().to_string()
+    };
+    match base64::decode(&encoded) {
+        Ok(public) if public.len() == CURVE25519_SIZE => Ok(public),
+        _ => Err(format!("'{}' is not a valid public key", recipient)),
+    }
+}
+
+/// Derive key wrapping the content key for one recipient from
+/// Diffie-Hellman between `private` and `public`. Both ephemeral and
+/// recipient public keys are mixed in.
+fn shared_key(
+    private: &[u8],
+    public: &[u8],
+    ephemeral_public: &[u8],
+    recipient: &[u8],
+    header: &str,
+    derived: &mut [u8],
+) -> Result<(), String> {
+    let mut shared = vec![0u8; CURVE25519_SIZE];
+    curve25519::mul(&mut shared, private, public).map_err(|err| err.to_string())?;
+    hkdf::<Sha256>(
+        &shared,
+        &[ephemeral_public, recipient].concat(),
+        header.as_bytes(),
+        derived,
+    );
+    wipe(&mut shared);
+    Ok(())
+}
+
+/// Seal `data` so that only holders of private keys matching
+/// `recipients` can open it.
+///
+/// Data is sealed once with ChaCha20-Poly1305 under a random content
+/// key, which is then wrapped the same way for every recipient under a
+/// key agreed with an ephemeral X25519 key.
+pub fn seal_for_recipients(data: &str, recipients: &[Vec<u8>]) -> Result<String, String> {
+    if recipients.is_empty() {
+        return Err(String::from("At least one recipient is needed"));
+    }
+    let mut rng = Yarrow::default();
+    let mut content_key = [0u8; 32];
+    rng.random(&mut content_key);
+    let result = seal_with_content_key(data, recipients, &content_key, &mut rng);
+    wipe(&mut content_key);
+    result
+}
+
+fn seal_with_content_key(
+    data: &str,
+    recipients: &[Vec<u8>],
+    content_key: &[u8; 32],
+    rng: &mut Yarrow,
+) -> Result<String, String> {
+    let mut blob = vec![SHARED_BLOB_HEADER.to_string()];
+    for recipient in recipients {
+        let ephemeral_private = curve25519::private_key(rng);
+        let mut ephemeral_public = vec![0u8; CURVE25519_SIZE];
+        curve25519::mul_g(&mut ephemeral_public, &ephemeral_private)
+            .map_err(|err| err.to_string())?;
+        let mut wrapping_key = [0u8; 32];
+        shared_key(
+            &ephemeral_private,
+            recipient,
+            &ephemeral_public,
+            recipient,
+            SHARED_BLOB_HEADER,
+            &mut wrapping_key,
+        )?;
+        let wrapped_key = aead_seal(&wrapping_key, SHARED_BLOB_HEADER.as_bytes(), content_key);
+        wipe(&mut wrapping_key);
+        let wrapped_key = wrapped_key?;
+        blob.push(format!(
+            "{} {} {}",
+            base64::encode(recipient),
+            base64::encode(ephemeral_public),
+            base64::encode(wrapped_key)
+        ));
+    }
+
+    let encrypted = aead_seal(content_key, SHARED_BLOB_HEADER.as_bytes(), data.as_bytes())?;
+    blob.push(String::new());
+    blob.push(base64::encode(encrypted));
+    Ok(blob.join("\n"))
+}
+
+/// Open a blob produced by `seal_for_recipients()` with identity of
+/// this vault. Fails if blob was tampered with.
+pub fn open_sealed(blob: &str, handle: &mut GivMe) -> Result<String, String> {
+    let mut lines = blob.lines();
+    let legacy = match lines.next().map(|line| line.trim()) {
+        Some(SHARED_BLOB_HEADER) => false,
+        Some(SHARED_BLOB_HEADER_V1) => true,
+        _ => return Err(String::from("Not a shared blob")),
+    };
+    let damaged = || String::from("Blob is damaged or not meant for you");
+    let identity = get_identity(handle)?;
+    let our_key = base64::encode(&identity.public);
+
+    let mut content_key: Option<Secret> = None;
+    for line in lines.by_ref() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            break;
+        }
+        if parts.len() != 3 || parts[0] != our_key || content_key.is_some() {
+            continue;
+        }
+        let ephemeral_public = base64::decode(parts[1]).map_err(|err| err.to_string())?;
+        let wrapped_key = base64::decode(parts[2]).map_err(|err| err.to_string())?;
+        content_key = Some(if legacy {
+            open_legacy_key(&identity, &ephemeral_public, &wrapped_key)?
+        } else {
+            let mut unwrapping_key = [0u8; 32];
+            shared_key(
+                &identity.private,
+                &ephemeral_public,
+                &ephemeral_public,
+                &identity.public,
+                SHARED_BLOB_HEADER,
+                &mut unwrapping_key,
+            )?;
+            let key = aead_open(&unwrapping_key, SHARED_BLOB_HEADER.as_bytes(), &wrapped_key);
+            wipe(&mut unwrapping_key);
+            match key {
+                Some(key) if key.len() == 32 => Secret::from(base64::encode(key)),
+                _ => return Err(damaged()),
+            }
+        });
+    }
+
+    let content_key =
+        content_key.ok_or_else(|| String::from("Blob was not shared with this vault"))?;
+    let mut content_key = base64::decode(content_key.expose()).map_err(|_| damaged())?;
+    let encrypted =
+        base64::decode(lines.next().unwrap_or_default().trim()).map_err(|err| err.to_string())?;
+    let data = match content_key.len() {
+        42 if legacy => decrypt(&encrypted, &handle_from_key_material(&content_key))
+            .ok()
+            .filter(|data| data.starts_with(SHARED_DATA_PREFIX))
+            .map(|data| data[SHARED_DATA_PREFIX.len()..].to_string()),
+        32 if !legacy => {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&content_key);
+            let data = aead_open(&key, SHARED_BLOB_HEADER.as_bytes(), &encrypted);
+            wipe(&mut key);
+            data.and_then(|data| String::from_utf8(data).ok())
+        }
+        _ => None,
+    };
+    wipe(&mut content_key);
+    data.ok_or_else(damaged)
+}
+
+/// Content key wrapped for us in a blob with `SHARED_BLOB_HEADER_V1`,
+/// base64 encoded
+fn open_legacy_key(
+    identity: &Identity,
+    ephemeral_public: &[u8],
+    wrapped_key: &[u8],
+) -> Result<Secret, String> {
+    let mut derived = [0u8; 42];
+    shared_key(
+        &identity.private,
+        ephemeral_public,
+        ephemeral_public,
+        &identity.public,
+        SHARED_BLOB_HEADER_V1,
+        &mut derived,
+    )?;
+    let unwrapping_handle = handle_from_key_material(&derived);
+    wipe(&mut derived);
+    match decrypt(wrapped_key, &unwrapping_handle) {
+        Ok(key) if key.starts_with(SHARED_DATA_PREFIX) => {
+            Ok(Secret::from(&key[SHARED_DATA_PREFIX.len()..]))
+        }
+        _ => Err(String::from("Blob is damaged or not meant for you")),
+    }
+}
+
+/// Check if data was produced by `seal_for_recipients()`
+pub fn is_shared_blob(data: &[u8]) -> bool {
+    data.starts_with(SHARED_BLOB_HEADER.as_bytes())
+        || data.starts_with(SHARED_BLOB_HEADER_V1.as_bytes())
+}
+
+/// Seal a stored entry for recipients. Every field is shared, written
+/// as a JSON export of that one entry.
+pub fn share_credentials(
+    key: String,
+    recipients: &[Vec<u8>],
+    handle: &mut GivMe,
+) -> Result<String, String> {
+    let cred =
+        give_credentials(key.clone(), handle).ok_or_else(|| format!("'{}' not found!", key))?;
+    let export = Secret::from(export_credentials(ExportFormat::Json, &[cred]));
+    seal_for_recipients(
+        &format!("json:{}", base64::encode(export.expose())),
+        recipients,
+    )
+}
+
+/// Open a blob produced by `givme share` or `--enc-file --recipient`
+pub fn receive_blob(blob: &str, handle: &mut GivMe) -> Result<SharedPayload, String> {
+    let data = open_sealed(blob, handle)?;
+    let fields: Vec<&str> = data.split(':').collect();
+    let decode = |field: &str| -> Result<Vec<u8>, String> {
+        base64::decode(field).map_err(|err| err.to_string())
+    };
+    let decode_string = |field: &str| -> Result<String, String> {
+        String::from_utf8(decode(field)?).map_err(|err| err.to_string())
+    };
+    match fields.as_slice() {
+        ["json", export] => {
+            let export = Secret::from(decode_string(export)?);
+            match read_import(ImportFormat::GivmeJson, export.expose())?.pop() {
+                Some(cred) => Ok(SharedPayload::Entry(cred?)),
+                None => Err(String::from("Shared blob holds no entry")),
+            }
+        }
+        /* Written by older versions, name, value and note only */
+        ["entry", key, value, info] => Ok(SharedPayload::Entry(Credentials::new(
+            decode_string(key)?,
+            decode_string(value)?,
+            decode_string(info)?,
+        ))),
+        ["file", data] => Ok(SharedPayload::File(decode(data)?)),
+        _ => Err(String::from("Unknown content in shared blob")),
+    }
+}
+
+/// Encrypt a file so that only `recipients` can decrypt it on their own
+/// computers with `givme receive` or `--dec-file`.
+///
+/// Output is written atomically, see `encrypt_file()` for `force` and `shred`.
+pub fn encrypt_file_for_recipients(
+    in_path: String,
+    out_path: String,
+    recipients: &[Vec<u8>],
+    force: bool,
+    shred: bool,
+) -> io::Result<()> {
+    if shred {
+        refuse_shredding_output(&in_path, &out_path)?;
+    }
+    let data = fs::read(&in_path)?;
+    let blob = seal_for_recipients(&format!("file:{}", base64::encode(data)), recipients)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    write_file_atomically(&out_path, blob.as_bytes(), force)?;
+    if shred {
+        shred_file(&in_path)?;
+    }
+    Ok(())
+}
+
+/// Decrypt a file encrypted for this vault with
+/// `encrypt_file_for_recipients()`
+pub fn decrypt_shared_file(
+    in_path: String,
+    out_path: String,
+    force: bool,
+    handle: &mut GivMe,
+) -> io::Result<()> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+    let blob = fs::read_to_string(&in_path).map_err(|err| invalid(err.to_string()))?;
+    match receive_blob(&blob, handle).map_err(invalid)? {
+        SharedPayload::File(data) => write_file_atomically(&out_path, &data, force),
+        SharedPayload::Entry(_) => Err(invalid(String::from(
+            "Blob holds an entry, not a file. Use `givme receive`",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::save_credentials;
+    use crate::models::secret::exposed;
+    use crate::twofactor::{otp_to_uri, parse_otp};
+    use crate::utils::{lock_test_home, test_vault};
+
+    fn public_key(handle: &mut GivMe) -> Vec<u8> {
+        get_identity(handle).unwrap().public
+    }
+
+    /// Blob with its base64 line `line` changed by `change`
+    fn tampered(blob: &str, line: usize, change: impl Fn(&mut Vec<u8>)) -> String {
+        let mut lines: Vec<String> = blob.lines().map(String::from).collect();
+        let mut parts: Vec<String> = lines[line].split(' ').map(String::from).collect();
+        let last = parts.last_mut().unwrap();
+        let mut data = base64::decode(&last).unwrap();
+        change(&mut data);
+        *last = base64::encode(data);
+        lines[line] = parts.join(" ");
+        lines.join("\n")
+    }
+
+    #[test]
+    fn sealed_blobs_open_for_recipients_only() {
+        let _home = lock_test_home();
+        let mut alice = test_vault("sqlite", None);
+        let mut bob = test_vault("json", None);
+        let mut carol = test_vault("sqlite", None);
+        let recipients = [public_key(&mut alice), public_key(&mut bob)];
+
+        let blob = seal_for_recipients("shared data", &recipients).unwrap();
+        assert!(blob.starts_with("GIVME-SHARE-2\n"));
+        assert!(is_shared_blob(blob.as_bytes()));
+        assert_eq!(open_sealed(&blob, &mut alice).unwrap(), "shared data");
+        assert_eq!(open_sealed(&blob, &mut bob).unwrap(), "shared data");
+        assert_eq!(
+            open_sealed(&blob, &mut carol),
+            Err(String::from("Blob was not shared with this vault"))
+        );
+
+        let damaged = Err(String::from("Blob is damaged or not meant for you"));
+        let last = blob.lines().count() - 1;
+        let content = tampered(&blob, last, |data| data[20] ^= 1);
+        assert_eq!(open_sealed(&content, &mut alice), damaged);
+        let truncated = tampered(&blob, last, |data| data.truncate(data.len() - 1));
+        assert_eq!(open_sealed(&truncated, &mut bob), damaged);
+        let wrapped_key = tampered(&blob, 1, |data| data[15] ^= 1);
+        assert_eq!(open_sealed(&wrapped_key, &mut alice), damaged);
+        assert_eq!(open_sealed(&wrapped_key, &mut bob).unwrap(), "shared data");
+    }
+
+    #[test]
+    fn legacy_blobs_still_open() {
+        let _home = lock_test_home();
+        let mut bob = test_vault("sqlite", None);
+        let recipient = public_key(&mut bob);
+
+        let mut rng = Yarrow::default();
+        let ephemeral_private = curve25519::private_key(&mut rng);
+        let mut ephemeral_public = vec![0u8; CURVE25519_SIZE];
+        curve25519::mul_g(&mut ephemeral_public, &ephemeral_private).unwrap();
+        let mut derived = [0u8; 42];
+        shared_key(
+            &ephemeral_private,
+            &recipient,
+            &ephemeral_public,
+            &recipient,
+            SHARED_BLOB_HEADER_V1,
+            &mut derived,
+        )
+        .unwrap();
+        let content_key = [9u8; 42];
+        let wrapped_key = encrypt(
+            &format!("{}{}", SHARED_DATA_PREFIX, base64::encode(content_key)),
+            &mut handle_from_key_material(&derived),
+        )
+        .unwrap();
+        let encrypted = encrypt(
+            &format!("{}old data", SHARED_DATA_PREFIX),
+            &mut handle_from_key_material(&content_key),
+        )
+        .unwrap();
+        let blob = format!(
+            "GIVME-SHARE-1\n{} {} {}\n\n{}",
+            base64::encode(&recipient),
+            base64::encode(&ephemeral_public),
+            base64::encode(wrapped_key),
+            base64::encode(encrypted)
+        );
+        assert!(is_shared_blob(blob.as_bytes()));
+        assert_eq!(open_sealed(&blob, &mut bob).unwrap(), "old data");
+    }
+
+    #[test]
+    fn shared_entries_keep_every_field() {
+        let _home = lock_test_home();
+        let mut alice = test_vault("sqlite", None);
+        let mut bob = test_vault("sealed", None);
+        let mut cred = Credentials::new(
+            String::from("work/mail"),
+            String::from("p4ss:word"),
+            String::from("first line\nsecond line"),
+        );
+        cred.username = Some(Secret::from("alice@example.com"));
+        cred.url = Some(String::from("https://mail.example.com"));
+        let otp = otp_to_uri(&parse_otp("JBSWY3DPEHPK3PXP", "work/mail").unwrap());
+        cred.otp = Some(Secret::from(otp.as_str()));
+        save_credentials(cred, &mut alice).unwrap();
+
+        let blob = share_credentials(
+            String::from("work/mail"),
+            &[public_key(&mut bob)],
+            &mut alice,
+        )
+        .unwrap();
+        let received = match receive_blob(&blob, &mut bob).unwrap() {
+            SharedPayload::Entry(cred) => cred,
+            SharedPayload::File(_) => panic!("Expected an entry"),
+        };
+        assert_eq!(received.key, "work/mail");
+        assert_eq!(received.value.expose(), "p4ss:word");
+        assert_eq!(exposed(&received.info), Some("first line\nsecond line"));
+        assert_eq!(exposed(&received.username), Some("alice@example.com"));
+        assert_eq!(received.url.as_deref(), Some("https://mail.example.com"));
+        assert_eq!(exposed(&received.otp), Some(otp.as_str()));
+    }
+}
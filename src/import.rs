@@ -0,0 +1,569 @@
+use crate::cred::{credentials_exist, overwrite_credentials, save_credentials};
+use crate::csv::parse_csv;
+use crate::export::EXPORT_JSON_VERSION;
+use crate::json::{parse_json, JsonValue};
+use crate::models::{
+    credentials::Credentials,
+    enums::{DuplicatePolicy, ImportFormat},
+    givme::GivMe,
+    import_summary::ImportSummary,
+    secret::Secret,
+};
+use crate::twofactor::{otp_to_uri, parse_otp};
+use crate::xml::{parse_xml, XmlNode};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Names accepted by `--format`, in same order as `ImportFormat`
+pub const IMPORT_FORMATS: [&str; 7] = [
+    "csv",
+    "chrome-csv",
+    "firefox-csv",
+    "bitwarden-json",
+    "keepass-xml",
+    "givme-json",
+    "pass-dir",
+];
+
+/// Parse name given to `--format`
+pub fn parse_import_format(name: &str) -> Result<ImportFormat, String> {
+    match name {
+        "csv" => Ok(ImportFormat::Csv),
+        "chrome-csv" => Ok(ImportFormat::ChromeCsv),
+        "firefox-csv" => Ok(ImportFormat::FirefoxCsv),
+        "bitwarden-json" => Ok(ImportFormat::BitwardenJson),
+        "keepass-xml" => Ok(ImportFormat::KeepassXml),
+        "givme-json" => Ok(ImportFormat::GivmeJson),
+        "pass-dir" => Ok(ImportFormat::PassDir),
+        _ => Err(format!("Unknown import format '{}'", name)),
+    }
+}
+
+/// Parse name given to `--on-duplicate`
+pub fn parse_duplicate_policy(name: &str) -> Result<DuplicatePolicy, String> {
+    match name {
+        "skip" => Ok(DuplicatePolicy::Skip),
+        "overwrite" => Ok(DuplicatePolicy::Overwrite),
+        "rename" => Ok(DuplicatePolicy::Rename),
+        _ => Err(format!("Unknown duplicate policy '{}'", name)),
+    }
+}
+
+/// Everything an imported row can carry before it is
+/// turned into `Credentials`
+#[derive(Default)]
+struct ImportedRow {
+    folder: Vec<String>,
+    name: String,
+    password: String,
+    username: String,
+    url: String,
+    notes: Vec<String>,
+    otp: String,
+}
+
+/// Host part of an URL, used as name when export has none
+fn host_of(url: &str) -> &str {
+    let without_scheme = url.split("://").nth(1).unwrap_or(url);
+    without_scheme
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default()
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+}
+
+impl ImportedRow {
+    fn into_credentials(self) -> Result<Credentials, String> {
+        let mut name = self.name.trim().to_string();
+        if name.is_empty() {
+            name = host_of(self.url.trim()).to_string();
+        }
+        if name.is_empty() {
+            name = self.username.trim().to_string();
+        }
+        if name.is_empty() {
+            return Err(String::from("Row has no name, url or username"));
+        }
+        let notes: Vec<String> = self
+            .notes
+            .into_iter()
+            .filter(|note| !note.trim().is_empty())
+            .collect();
+        if self.password.is_empty() && notes.is_empty() && self.otp.trim().is_empty() {
+            return Err(format!("'{}' has nothing to store", name));
+        }
+
+        let folder: Vec<&str> = self
+            .folder
+            .iter()
+            .map(|part| part.trim())
+            .filter(|part| !part.is_empty())
+            .collect();
+        if !folder.is_empty() {
+            name = format!("{}/{}", folder.join("/"), name);
+        }
+
+        let mut cred = Credentials::new(name, self.password, notes.join("\n"));
+        if !self.username.trim().is_empty() {
+            cred.username = Some(Secret::from(self.username.trim()));
+        }
+        if !self.url.trim().is_empty() {
+            cred.url = Some(self.url.trim().to_string());
+        }
+        if !self.otp.trim().is_empty() {
+            cred.otp = Some(Secret::from(otp_to_uri(&parse_otp(&self.otp, &cred.key)?)));
+        }
+        Ok(cred)
+    }
+}
+
+/// Read any CSV export whose first row names the columns. Covers
+/// generic, Chrome, Firefox and Bitwarden CSV exports.
+fn read_csv(data: &str) -> Result<Vec<Result<Credentials, String>>, String> {
+    let rows = parse_csv(data)?;
+    let header: Vec<String> = match rows.first() {
+        Some(header) => header
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect(),
+        None => return Ok(Vec::new()),
+    };
+    let column = |names: &[&str]| {
+        header
+            .iter()
+            .position(|name| names.contains(&name.as_str()))
+    };
+    let name_column = column(&["name", "title", "key"]);
+    let password_column = column(&["password", "value", "login_password"]);
+    let username_column = column(&["username", "user", "login", "login_username"]);
+    let url_column = column(&["url", "uri", "website", "login_uri"]);
+    let notes_column = column(&["note", "notes", "info", "extra", "comments"]);
+    let folder_column = column(&["folder", "group", "grouping", "path"]);
+    let otp_column = column(&["totp", "otp", "login_totp"]);
+    if password_column.is_none() {
+        return Err(String::from("CSV has no password column"));
+    }
+
+    Ok(rows[1..]
+        .iter()
+        .map(|row| {
+            let field = |index: Option<usize>| {
+                index
+                    .and_then(|index| row.get(index))
+                    .cloned()
+                    .unwrap_or_default()
+            };
+            ImportedRow {
+                folder: field(folder_column)
+                    .split(['/', '\\'])
+                    .map(String::from)
+                    .collect(),
+                name: field(name_column),
+                password: field(password_column),
+                username: field(username_column),
+                url: field(url_column),
+                notes: vec![field(notes_column)],
+                otp: field(otp_column),
+            }
+            .into_credentials()
+        })
+        .collect())
+}
+
+/// Read unencrypted JSON export of Bitwarden
+fn read_bitwarden_json(data: &str) -> Result<Vec<Result<Credentials, String>>, String> {
+    let export = parse_json(data)?;
+    if export.get("encrypted") == Some(&JsonValue::Bool(true)) {
+        return Err(String::from(
+            "Encrypted Bitwarden exports are not supported. Export as unencrypted JSON",
+        ));
+    }
+    let folders: Vec<(&str, &str)> = export
+        .get("folders")
+        .map(JsonValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|folder| Some((folder.get("id")?.as_str()?, folder.get("name")?.as_str()?)))
+        .collect();
+    let text = |value: Option<&JsonValue>| {
+        value
+            .and_then(JsonValue::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    Ok(export
+        .get("items")
+        .map(JsonValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .map(|item| {
+            let name = text(item.get("name"));
+            match item.get("type") {
+                Some(JsonValue::Number(kind)) if *kind == 1.0 || *kind == 2.0 => {}
+                _ => return Err(format!("'{}' is not a login or secure note", name)),
+            }
+            let folder_id = text(item.get("folderId"));
+            let folder = folders
+                .iter()
+                .find(|(id, _)| *id == folder_id)
+                .map(|(_, name)| name.to_string())
+                .unwrap_or_default();
+            let login = item.get("login");
+            let mut notes = vec![text(item.get("notes"))];
+            for custom in item
+                .get("fields")
+                .map(JsonValue::as_array)
+                .unwrap_or_default()
+            {
+                notes.push(format!(
+                    "{}: {}",
+                    text(custom.get("name")),
+                    text(custom.get("value"))
+                ));
+            }
+            ImportedRow {
+                folder: folder.split('/').map(String::from).collect(),
+                name,
+                password: text(login.and_then(|login| login.get("password"))),
+                username: text(login.and_then(|login| login.get("username"))),
+                url: login
+                    .and_then(|login| login.get("uris"))
+                    .map(JsonValue::as_array)
+                    .unwrap_or_default()
+                    .first()
+                    .map(|uri| text(uri.get("uri")))
+                    .unwrap_or_default(),
+                notes,
+                otp: text(login.and_then(|login| login.get("totp"))),
+            }
+            .into_credentials()
+        })
+        .collect())
+}
+
+/// Read JSON written by `givme export --format json`. Encrypted exports
+/// must be decrypted with `decrypt_with_passphrase()` first.
+fn read_givme_json(data: &str) -> Result<Vec<Result<Credentials, String>>, String> {
+    let export = parse_json(data)?;
+    match export.get("givme_export") {
+        Some(JsonValue::Number(version)) if *version <= EXPORT_JSON_VERSION => {}
+        Some(_) => return Err(String::from("Export was made by a newer givme")),
+        None => return Err(String::from("Not a givme export")),
+    }
+    let text = |entry: &JsonValue, key: &str| {
+        entry
+            .get(key)
+            .and_then(JsonValue::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    Ok(export
+        .get("entries")
+        .map(JsonValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .map(|entry| {
+            ImportedRow {
+                name: text(entry, "name"),
+                password: text(entry, "password"),
+                username: text(entry, "username"),
+                url: text(entry, "url"),
+                notes: vec![text(entry, "notes")],
+                otp: text(entry, "otp"),
+                ..ImportedRow::default()
+            }
+            .into_credentials()
+        })
+        .collect())
+}
+
+/// Walk a KeePass group and its subgroups, collecting entries. Entry
+/// history is ignored.
+fn read_keepass_group(
+    group: &XmlNode,
+    path: &[String],
+    recycle_bin: &str,
+    records: &mut Vec<Result<Credentials, String>>,
+) {
+    for entry in group.children_named("Entry") {
+        let mut row = ImportedRow {
+            folder: path.to_vec(),
+            ..ImportedRow::default()
+        };
+        for string in entry.children_named("String") {
+            let key = string
+                .child("Key")
+                .map(|key| key.text.as_str())
+                .unwrap_or_default();
+            let value = string
+                .child("Value")
+                .map(|value| value.text.clone())
+                .unwrap_or_default();
+            match key {
+                "Title" => row.name = value,
+                "Password" => row.password = value,
+                "UserName" => row.username = value,
+                "URL" => row.url = value,
+                "Notes" => row.notes.insert(0, value),
+                "otp" | "TOTP Seed" => row.otp = value,
+                _ if !value.is_empty() => row.notes.push(format!("{}: {}", key, value)),
+                _ => {}
+            }
+        }
+        records.push(row.into_credentials());
+    }
+    for subgroup in group.children_named("Group") {
+        let uuid = subgroup.child("UUID").map(|uuid| uuid.text.trim());
+        if !recycle_bin.is_empty() && uuid == Some(recycle_bin) {
+            continue;
+        }
+        let mut subpath = path.to_vec();
+        subpath.push(
+            subgroup
+                .child("Name")
+                .map(|name| name.text.clone())
+                .unwrap_or_default(),
+        );
+        read_keepass_group(subgroup, &subpath, recycle_bin, records);
+    }
+}
+
+/// Read XML export of KeePass 2 / KeePassXC. Name of top level
+/// group is not used as folder.
+fn read_keepass_xml(data: &str) -> Result<Vec<Result<Credentials, String>>, String> {
+    let document = parse_xml(data)?;
+    if document.name != "KeePassFile" {
+        return Err(String::from("Not a KeePass XML export"));
+    }
+    let recycle_bin = document
+        .child("Meta")
+        .filter(|meta| {
+            meta.child("RecycleBinEnabled")
+                .map(|enabled| enabled.text.trim() == "True")
+                .unwrap_or_default()
+        })
+        .and_then(|meta| meta.child("RecycleBinUUID"))
+        .map(|uuid| uuid.text.trim().to_string())
+        .unwrap_or_default();
+    let mut records = Vec::new();
+    if let Some(root) = document.child("Root") {
+        for group in root.children_named("Group") {
+            read_keepass_group(group, &[], &recycle_bin, &mut records);
+        }
+    }
+    Ok(records)
+}
+
+/// Read one `pass` entry. First line is the password, `key: value`
+/// lines fill matching fields and everything else becomes the note.
+/// `path` is the entry name inside the store, like `work/github`.
+pub fn read_pass_entry(path: &str, data: &str) -> Result<Credentials, String> {
+    let mut parts: Vec<String> = path
+        .trim_end_matches(".gpg")
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty())
+        .map(String::from)
+        .collect();
+    let mut row = ImportedRow {
+        name: parts.pop().unwrap_or_default(),
+        folder: parts,
+        ..ImportedRow::default()
+    };
+    let mut lines = data.lines();
+    row.password = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        /* pass-otp keeps the whole otpauth URI on its own line */
+        if line.trim_start().starts_with("otpauth://") {
+            row.otp = line.trim().to_string();
+            continue;
+        }
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+            None => {
+                row.notes.push(line.to_string());
+                continue;
+            }
+        };
+        match key.as_str() {
+            "user" | "username" | "login" | "email" if row.username.is_empty() => {
+                row.username = value.to_string()
+            }
+            "url" | "website" | "site" if row.url.is_empty() => row.url = value.to_string(),
+            "otp" | "totp" if row.otp.is_empty() => row.otp = value.to_string(),
+            _ => row.notes.push(line.to_string()),
+        }
+    }
+    let notes = row.notes.join("\n");
+    row.notes = vec![notes.trim_matches('\n').to_string()];
+    row.into_credentials()
+}
+
+/// Collect files below `dir` in sorted order. Hidden files and
+/// directories, like `.git` and `.gpg-id`, are left out.
+fn collect_pass_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> Result<(), String> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map_err(|err| format!("{}: {}", dir.display(), err))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            !path
+                .file_name()
+                .map(|name| name.to_string_lossy().starts_with('.'))
+                .unwrap_or(true)
+        })
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_pass_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Read a `pass` store whose files were already decrypted. Path of
+/// each file relative to `dir` becomes folder and name of the entry.
+pub fn read_pass_dir(dir: &str) -> Result<Vec<Result<Credentials, String>>, String> {
+    let root = Path::new(dir);
+    if !root.is_dir() {
+        return Err(format!("'{}' is not a directory", dir));
+    }
+    let mut files = Vec::new();
+    collect_pass_files(root, &mut files)?;
+    Ok(files
+        .iter()
+        .map(|file| {
+            let name = file
+                .strip_prefix(root)
+                .unwrap_or(file)
+                .to_string_lossy()
+                .to_string();
+            match fs::read(file) {
+                Ok(data) => match String::from_utf8(data) {
+                    Ok(data) => read_pass_entry(&name, &data),
+                    Err(_) => Err(format!(
+                        "'{}' is not text, decrypt it with pass first",
+                        name
+                    )),
+                },
+                Err(err) => Err(format!("'{}': {}", name, err)),
+            }
+        })
+        .collect())
+}
+
+/// Turn an export into `Credentials`. Each row either becomes
+/// `Credentials` or a reason why it could not be read.
+pub fn read_import(
+    format: ImportFormat,
+    data: &str,
+) -> Result<Vec<Result<Credentials, String>>, String> {
+    match format {
+        /* Chrome and Firefox name their columns, so the generic reader handles them */
+        ImportFormat::Csv | ImportFormat::ChromeCsv | ImportFormat::FirefoxCsv => read_csv(data),
+        ImportFormat::BitwardenJson => read_bitwarden_json(data),
+        ImportFormat::KeepassXml => read_keepass_xml(data),
+        ImportFormat::GivmeJson => read_givme_json(data),
+        /* A directory is read by `read_pass_dir()`, here data is output of `pass show` */
+        ImportFormat::PassDir => Err(String::from(
+            "pass-dir imports need a directory path, not stdin",
+        )),
+    }
+}
+
+/// Check if `name` is saved in vault or used by an earlier row
+/// of the same import
+fn name_taken(name: &str, planned: &HashSet<String>, handle: &mut GivMe) -> bool {
+    planned.contains(name) || credentials_exist(name.to_string(), handle).unwrap_or(true)
+}
+
+/// Save rows produced by `read_import()` according to `policy`.
+/// Nothing is written with `dry_run`, only what would happen is printed.
+pub fn import_credentials(
+    records: Vec<Result<Credentials, String>>,
+    policy: DuplicatePolicy,
+    dry_run: bool,
+    handle: &mut GivMe,
+) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+    /* Names taken by earlier rows of this import, needed while nothing is saved */
+    let mut planned: HashSet<String> = HashSet::new();
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+
+    for (index, record) in records.into_iter().enumerate() {
+        let row = index + 1;
+        let mut cred = match record {
+            Ok(cred) => cred,
+            Err(err) => {
+                eprintln!("-- Row {}: {}", row, err);
+                summary.failed.push((row, err));
+                continue;
+            }
+        };
+
+        let duplicate = name_taken(&cred.key, &planned, handle);
+        if duplicate && policy == DuplicatePolicy::Skip {
+            println!("{}Skipping '{}', already exists", prefix, cred.key);
+            summary.skipped += 1;
+            continue;
+        }
+        if duplicate && policy == DuplicatePolicy::Rename {
+            let original = cred.key.clone();
+            let mut suffix = 2;
+            while name_taken(&format!("{} ({})", original, suffix), &planned, handle) {
+                suffix += 1;
+            }
+            cred.key = format!("{} ({})", original, suffix);
+            println!("{}Renaming '{}' to '{}'", prefix, original, cred.key);
+        } else if duplicate {
+            println!("{}Overwriting '{}'", prefix, cred.key);
+        } else if dry_run {
+            println!("{}Importing '{}'", prefix, cred.key);
+        }
+
+        let key = cred.key.clone();
+        let result = if dry_run {
+            Ok(true)
+        } else if duplicate && policy == DuplicatePolicy::Overwrite {
+            overwrite_credentials(cred, handle)
+        } else {
+            save_credentials(cred, handle)
+        };
+        match result {
+            Ok(_) => {
+                match (duplicate, policy) {
+                    (false, _) => summary.imported += 1,
+                    (true, DuplicatePolicy::Overwrite) => summary.overwritten += 1,
+                    _ => summary.renamed += 1,
+                }
+                planned.insert(key);
+            }
+            Err(err) => {
+                eprintln!("-- Row {}: {}", row, err);
+                summary.failed.push((row, err));
+            }
+        }
+    }
+    summary
+}
+
+/// Prints totals of an import to user
+pub fn show_import_summary(summary: &ImportSummary, dry_run: bool) {
+    if dry_run {
+        println!("\nDry run, nothing was saved");
+    }
+    println!(
+        "\nImported: {}  Overwritten: {}  Renamed: {}  Skipped: {}  Failed: {}",
+        summary.imported,
+        summary.overwritten,
+        summary.renamed,
+        summary.skipped,
+        summary.failed.len()
+    );
+}
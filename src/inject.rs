@@ -0,0 +1,231 @@
+use crate::cred::give_credentials;
+use crate::models::credentials::Credentials;
+use crate::models::givme::GivMe;
+use crate::models::secret::Secret;
+use crate::twofactor::generate_otp;
+use std::collections::BTreeMap;
+
+/// Fields a template placeholder can ask for, `password` when none is given
+const TEMPLATE_FIELDS: [&str; 6] = ["password", "value", "username", "url", "note", "otp"];
+
+/// Replace every `{{ givme "entry" "field" }}` in `template` with that
+/// field of the entry. Other `{{ }}` are left alone for other template
+/// languages. Fails listing every reference which can't be resolved.
+pub fn render_template(template: &str, handle: &mut GivMe) -> Result<Secret, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut creds: BTreeMap<String, Option<Credentials>> = BTreeMap::new();
+    let mut errors = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let line = template[..template.len() - rest.len() + start]
+            .matches('\n')
+            .count()
+            + 1;
+        let inner = &rest[start + 2..];
+        let is_ours = inner
+            .trim_start()
+            .strip_prefix("givme")
+            .is_some_and(|args| args.starts_with(char::is_whitespace));
+        if !is_ours {
+            rendered.push_str("{{");
+            rest = inner;
+            continue;
+        }
+        let end = match inner.find("}}") {
+            Some(end) => end,
+            None => {
+                errors.push(format!("line {}: `{{{{ givme` is never closed", line));
+                rest = "";
+                break;
+            }
+        };
+        rest = &inner[end + 2..];
+        let args = inner[..end].trim_start().trim_start_matches("givme");
+        let value = parse_placeholder(args).and_then(|(name, field)| {
+            let cred = creds
+                .entry(name.clone())
+                .or_insert_with(|| give_credentials(name.clone(), handle));
+            match cred {
+                Some(cred) => entry_field(cred, &field, handle),
+                None => Err(format!("'{}' not found!", name)),
+            }
+        });
+        match value {
+            Ok(value) => rendered.push_str(value.expose()),
+            Err(err) => errors.push(format!("line {}: {}", line, err)),
+        }
+    }
+    rendered.push_str(rest);
+    let rendered = Secret::from(rendered);
+    if errors.is_empty() {
+        Ok(rendered)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// Entry name and field of a placeholder, from quoted arguments after
+/// `givme`. `\"` and `\\` escape inside quotes.
+fn parse_placeholder(args: &str) -> Result<(String, String), String> {
+    let mut words = Vec::new();
+    let mut chars = args.trim().chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c != '"' {
+            return Err(format!("Expected a quoted argument, found '{}'", c));
+        }
+        let mut word = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some(escaped) => word.push(escaped),
+                    None => return Err(String::from("Unfinished escape")),
+                },
+                Some(c) => word.push(c),
+                None => return Err(String::from("Unclosed quote")),
+            }
+        }
+        words.push(word);
+    }
+    match words.as_slice() {
+        [name] => Ok((name.clone(), String::from("password"))),
+        [name, field] => Ok((name.clone(), field.clone())),
+        _ => Err(String::from(
+            "Expected {{ givme \"entry\" \"field\" }} with field being optional",
+        )),
+    }
+}
+
+/// One field of an entry, failing when entry does not have it
+fn entry_field(cred: &Credentials, field: &str, handle: &mut GivMe) -> Result<Secret, String> {
+    let value = match field {
+        "password" | "value" => Some(cred.value.clone()),
+        "username" => cred.username.clone(),
+        "url" => cred.url.clone().map(Secret::from),
+        "note" => cred.info.clone(),
+        "otp" => Some(Secret::from(generate_otp(cred.key.clone(), handle)?.0)),
+        _ => {
+            return Err(format!(
+                "Unknown field '{}', use one of {}",
+                field,
+                TEMPLATE_FIELDS.join(", ")
+            ))
+        }
+    };
+    value.ok_or_else(|| format!("'{}' has no {}", cred.key, field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::save_credentials;
+    use crate::utils::{lock_test_home, test_vault};
+
+    #[test]
+    fn parses_quoted_placeholder_arguments() {
+        let parsed = |args: &str| parse_placeholder(args);
+        let pair = |name: &str, field: &str| Ok((name.to_string(), field.to_string()));
+        assert_eq!(parsed(r#" "db" "#), pair("db", "password"));
+        assert_eq!(parsed(r#""db"   "username""#), pair("db", "username"));
+        assert_eq!(parsed(r#""prod/my db" "url""#), pair("prod/my db", "url"));
+        assert_eq!(parsed(r#""say \"hi\"" "a\\b""#), pair("say \"hi\"", "a\\b"));
+        assert_eq!(parsed(r#""{{ }}""#), pair("{{ }}", "password"));
+
+        assert_eq!(
+            parsed("db"),
+            Err(String::from("Expected a quoted argument, found 'd'"))
+        );
+        assert_eq!(parsed(r#""db"#), Err(String::from("Unclosed quote")));
+        assert_eq!(parsed(r#""db\"#), Err(String::from("Unfinished escape")));
+        assert!(parsed("").is_err());
+        assert!(parsed(r#""a" "b" "c""#).is_err());
+    }
+
+    #[test]
+    fn renders_fields_and_leaves_other_templates_alone() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        let mut cred = Credentials::new(
+            String::from("prod/db"),
+            String::from("s3cr\"et"),
+            String::from("line one\nline two"),
+        );
+        cred.username = Some(Secret::from("admin"));
+        cred.url = Some(String::from("postgres://db.example.com"));
+        save_credentials(cred, &mut handle).unwrap();
+
+        let template = "user={{ givme \"prod/db\" \"username\" }}\n\
+                        pass={{givme \"prod/db\"}} again={{ givme \"prod/db\" \"value\" }}\n\
+                        url={{ givme \"prod/db\" \"url\" }}\n\
+                        note={{ givme \"prod/db\" \"note\" }}\n\
+                        other={{ .Values.password }} {{givmeNot}} {{ givme";
+        assert_eq!(
+            render_template(template, &mut handle).unwrap().expose(),
+            "user=admin\n\
+             pass=s3cr\"et again=s3cr\"et\n\
+             url=postgres://db.example.com\n\
+             note=line one\nline two\n\
+             other={{ .Values.password }} {{givmeNot}} {{ givme"
+        );
+        assert_eq!(
+            render_template("no placeholders {{ here }}", &mut handle)
+                .unwrap()
+                .expose(),
+            "no placeholders {{ here }}"
+        );
+    }
+
+    #[test]
+    fn reports_every_error_at_once() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        let cred = Credentials::new(String::from("plain"), String::from("value"), String::new());
+        save_credentials(cred, &mut handle).unwrap();
+
+        let template = "a={{ givme \"missing\" }}\n\
+                        b={{ givme \"plain\" \"colour\" }}\n\
+                        c={{ givme \"plain\" \"username\" }}\n\
+                        d={{ givme plain }}\n\
+                        e={{ givme \"plain\" }}\n\
+                        f={{ givme \"plain\"";
+        assert_eq!(
+            render_template(template, &mut handle).map(|secret| secret.expose().to_string()),
+            Err([
+                "line 1: 'missing' not found!",
+                "line 2: Unknown field 'colour', use one of password, value, username, url, note, otp",
+                "line 3: 'plain' has no username",
+                "line 4: Expected a quoted argument, found 'p'",
+                "line 6: `{{ givme` is never closed",
+            ]
+            .join("\n"))
+        );
+    }
+
+    #[test]
+    fn otp_field_moves_hotp_counter() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        let mut cred = Credentials::new(String::from("vpn"), String::from("pw"), String::new());
+        /* RFC 4226 test secret, codes for counters 0 and 1 */
+        cred.otp = Some(Secret::from(
+            "otpauth://hotp/vpn?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0",
+        ));
+        save_credentials(cred, &mut handle).unwrap();
+
+        let template = "code={{ givme \"vpn\" \"otp\" }}";
+        assert_eq!(
+            render_template(template, &mut handle).unwrap().expose(),
+            "code=755224"
+        );
+        assert_eq!(
+            render_template(template, &mut handle).unwrap().expose(),
+            "code=287082"
+        );
+        let stored = give_credentials(String::from("vpn"), &mut handle).unwrap();
+        assert!(stored.otp.unwrap().expose().contains("counter=2"));
+    }
+}
@@ -1,48 +1,142 @@
+use crate::agent::agent_password;
+use crate::cred::migrate_entry_names;
+use crate::dotenv::ENV_FORMATS;
+use crate::export::EXPORT_FORMATS;
+use crate::import::IMPORT_FORMATS;
 use crate::models::credentials::Credentials;
-use crate::sql::get_from_sql;
+use crate::models::secret::Secret;
+use crate::storage::stored_secret_key;
 use crate::utils::*;
 use crate::{encryption::decrypt, models::givme::GivMe};
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 use lazy_static::lazy_static;
-use rpassword::read_password;
-use std::{env, io::Write};
+use rpassword::{read_password, read_password_from_tty};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, io::Read, io::Write};
 
 lazy_static! {
     pub static ref DEBUG: bool = env::var("GIVME_DEBUG").is_ok();
 }
 
+/// Set once data was read from stdin, after that secrets are read
+/// from terminal instead
+static STDIN_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Read all of stdin as data, like `pass show` output piped to
+/// `givme import -`
+pub fn take_stdin() -> Result<String, std::io::Error> {
+    let mut data = String::new();
+    std::io::stdin().read_to_string(&mut data)?;
+    STDIN_TAKEN.store(true, Ordering::SeqCst);
+    Ok(data)
+}
+
+/// Read a secret without echoing it
+fn read_secret() -> Result<String, std::io::Error> {
+    if STDIN_TAKEN.load(Ordering::SeqCst) {
+        read_password_from_tty(None)
+    } else {
+        read_password()
+    }
+}
+
 /// Authenticate User by verifying user entered password
 /// with decrypted password.
 ///
 /// This function also does some random string decryption as dual
 /// security measure.
 pub fn ask_pass_and_extract_key(handle: &mut GivMe) -> Result<bool, bool> {
-    eprint!("Enter your Master Key: ");
-    std::io::stdout().flush().unwrap();
-    let user_entered_pass = read_password().unwrap().trim().to_string();
-    let proper_length_password = adjust_password_length(&user_entered_pass, 24);
-    let encrypted_key = base64::decode(&get_from_sql("secret_key", handle)[0].value).unwrap();
-    let decrypted_pass = decrypt(
-        &encrypted_key,
+    let encrypted_key = base64::decode(stored_secret_key(handle)).unwrap();
+    /* Agent keeps the adjusted Master Key, it is checked just like a typed one */
+    let cached = agent_password(handle).and_then(|password| {
+        open_master_key(&encrypted_key, &password).map(|decrypted| (password, decrypted))
+    });
+    let (proper_length_password, decrypted_pass) = match cached {
+        Some(keys) => {
+            debug("Master Key given by agent");
+            keys
+        }
+        None => {
+            eprint!("Enter your Master Key: ");
+            std::io::stdout().flush().unwrap();
+            let user_entered_pass = Secret::from(read_secret().unwrap().trim());
+            let proper_length_password =
+                Secret::from(adjust_password_length(user_entered_pass.expose(), 24));
+            match open_master_key(&encrypted_key, &proper_length_password) {
+                Some(decrypted) => (proper_length_password, decrypted),
+                None => {
+                    eprintln!("Error: Invalid Password");
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+
+    if let Err(err) = apply_master_key(proper_length_password, decrypted_pass, handle) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+    Ok(true)
+}
+
+/// Unlock vault with a Master Key which was not typed on terminal, like
+/// one sent to `givme serve`
+pub fn unlock_with_master_key(master_key: &str, handle: &mut GivMe) -> Result<(), String> {
+    let encrypted_key = base64::decode(stored_secret_key(handle)).map_err(|err| err.to_string())?;
+    let proper_length_password = Secret::from(adjust_password_length(master_key.trim(), 24));
+    let decrypted_pass = open_master_key(&encrypted_key, &proper_length_password)
+        .ok_or_else(|| String::from("Invalid Password"))?;
+    apply_master_key(proper_length_password, decrypted_pass, handle)
+}
+
+/// Keep keys opened by Master Key in `handle` and unlock storage with them
+fn apply_master_key(password: Secret, key: Secret, handle: &mut GivMe) -> Result<(), String> {
+    handle.key = Some(key);
+    handle.password = Some(password);
+    if let Some(storage) = handle.storage.as_mut() {
+        let key = handle.key.as_ref().unwrap().expose();
+        let password = handle.password.as_ref().unwrap().expose();
+        storage.unlock(key, password)?;
+    }
+    if handle.storage.is_some() {
+        /* Old rows are still found, so the vault stays usable if this fails */
+        match migrate_entry_names(handle) {
+            Ok(0) => {}
+            Ok(count) => eprintln!("Moved {} entries to encrypted name index", count),
+            Err(err) => {
+                eprintln!("-- Error in moving entries to encrypted name index");
+                eprintln!("{}", err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Wipe keys opened by Master Key, also those storage keeps
+pub fn lock_vault(handle: &mut GivMe) {
+    handle.key = None;
+    handle.password = None;
+    if let Some(storage) = handle.storage.as_mut() {
+        storage.lock();
+    }
+}
+
+/// Vault key hidden in Master Key row, `None` if `password` can't open it
+fn open_master_key(encrypted_key: &[u8], password: &Secret) -> Option<Secret> {
+    let decrypted = decrypt(
+        encrypted_key,
         &GivMe {
-            key: Some(proper_length_password.clone()),
+            key: Some(password.clone()),
             sql_con: None,
-            password: Some(proper_length_password.clone()),
+            password: Some(password.clone()),
             os: None,
             username: None,
+            storage: None,
         },
-    );
-
-    if decrypted_pass.is_err() {
-        eprintln!("Error: Invalid Password");
-        std::process::exit(1);
-    }
-    let decrypted_pass = decrypted_pass.unwrap();
-
-    debug(format!("Decrypted pass: {}", decrypted_pass).as_str());
-    handle.key = Some((&decrypted_pass)[4..32].to_string());
-    handle.password = Some(proper_length_password);
-    Ok(true)
+    )
+    .ok()
+    .map(Secret::from)?;
+    decrypted.expose().get(4..32).map(Secret::from)
 }
 
 /// Ask for value and info to construct `Credentials`
@@ -61,6 +155,58 @@ pub fn ask_user_for_value(key: &str) -> Result<Credentials, std::io::Error> {
     Ok(Credentials::new(key.to_string(), password, info))
 }
 
+/// Ask for one time password seed of an entry. Either a base32
+/// secret or a full `otpauth://` URI as shown in 2FA QR codes.
+pub fn ask_user_for_otp(key: &str) -> Result<String, std::io::Error> {
+    print!("2FA secret or otpauth:// URI for '{}': ", key);
+    std::io::stdout().flush()?;
+    Ok(read_password()?.trim().to_string())
+}
+
+/// Ask user to confirm something risky by typing `yes`
+pub fn ask_for_confirmation(question: &str) -> bool {
+    eprint!("{} Type 'yes' to continue: ", question);
+    std::io::stderr().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).unwrap();
+    answer.trim().eq_ignore_ascii_case("yes")
+}
+
+/// Ask for a passphrase used to encrypt files portable to other
+/// computers. Asks twice when `confirm` is set.
+pub fn ask_passphrase(confirm: bool) -> Result<String, std::io::Error> {
+    loop {
+        eprint!("Enter Passphrase: ");
+        std::io::stderr().flush()?;
+        let passphrase = read_secret()?.trim().to_string();
+        if passphrase.is_empty() {
+            eprintln!("Passphrase can't be empty. Try Again...\n");
+            continue;
+        }
+        if !confirm {
+            return Ok(passphrase);
+        }
+        eprint!("Confirm Passphrase: ");
+        std::io::stderr().flush()?;
+        if read_secret()?.trim() == passphrase {
+            return Ok(passphrase);
+        }
+        eprintln!("Unmatched Passphrase. Try Again...\n");
+    }
+}
+
+/// Read passphrase from first line of a key file shared out of band.
+pub fn read_key_file(path: &str) -> Result<String, std::io::Error> {
+    let content = std::fs::read_to_string(path)?;
+    match content.lines().next().map(|line| line.trim()) {
+        Some(passphrase) if !passphrase.is_empty() => Ok(passphrase.to_string()),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Key file '{}' is empty", path),
+        )),
+    }
+}
+
 /// Print debug messages
 pub fn debug(msg: &str) {
     if *DEBUG {
@@ -80,6 +226,14 @@ pub fn parse_args() -> clap::App<'static, 'static> {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("otp")
+                .long("otp")
+                .required(false)
+                .takes_value(false)
+                .requires("store")
+                .help("Also asks for 2FA secret or otpauth:// URI while storing"),
+        )
         .arg(
             Arg::with_name("key")
                 .help("Key to query")
@@ -94,6 +248,13 @@ pub fn parse_args() -> clap::App<'static, 'static> {
                 .takes_value(false)
                 .help("Outputs only value for a key"),
         )
+        .arg(
+            Arg::with_name("copy")
+                .short("c")
+                .long("copy")
+                .conflicts_with("raw")
+                .help("Copies value to clipboard instead of printing it"),
+        )
         .arg(
             Arg::with_name("delete")
                 .short("d")
@@ -121,6 +282,39 @@ pub fn parse_args() -> clap::App<'static, 'static> {
                 .number_of_values(2)
                 .help("Decrypts specified encrypted file"),
         )
+        .arg(
+            Arg::with_name("force")
+                .short("f")
+                .long("force")
+                .required(false)
+                .takes_value(false)
+                .help("Overwrite destination file if it already exists"),
+        )
+        .arg(
+            Arg::with_name("shred")
+                .long("shred")
+                .required(false)
+                .takes_value(false)
+                .requires("encrypt-file")
+                .help("Overwrite and remove source file after encryption"),
+        )
+        .arg(
+            Arg::with_name("passphrase")
+                .short("p")
+                .long("passphrase")
+                .required(false)
+                .takes_value(false)
+                .help("Use a passphrase instead of vault keys so file can be decrypted on other computers"),
+        )
+        .arg(
+            Arg::with_name("key-file")
+                .long("key-file")
+                .value_name("FILE")
+                .required(false)
+                .takes_value(true)
+                .conflicts_with("passphrase")
+                .help("Same as --passphrase but reads passphrase from first line of FILE"),
+        )
         .arg(
             Arg::with_name("get-secret-key")
                 .required(false)
@@ -137,6 +331,447 @@ pub fn parse_args() -> clap::App<'static, 'static> {
                 .number_of_values(1)
                 .help("Sets the secret key of one encryption standard"),
         )
+        .arg(
+            Arg::with_name("recipient")
+                .long("recipient")
+                .value_name("PUBKEY")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("encrypt-file")
+                .conflicts_with_all(&["passphrase", "key-file"])
+                .help("Encrypt file for owner of public key (or file containing it). Can be repeated"),
+        )
+        .subcommand(
+            SubCommand::with_name("otp")
+                .about("Prints current one time password of a key")
+                .arg(
+                    Arg::with_name("key")
+                        .help("Key to generate code for")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("raw")
+                        .short("r")
+                        .help("Outputs only the code"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Imports entries exported from other password managers")
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .help("Exported file or pass directory to import, - reads stdin")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .help("Entry name for `pass show` output read from stdin"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .takes_value(true)
+                        .possible_values(&IMPORT_FORMATS)
+                        .default_value("csv")
+                        .help("Format of exported file"),
+                )
+                .arg(
+                    Arg::with_name("on-duplicate")
+                        .long("on-duplicate")
+                        .value_name("POLICY")
+                        .takes_value(true)
+                        .possible_values(&["skip", "overwrite", "rename"])
+                        .default_value("skip")
+                        .help("What to do when an entry with same name exists"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Only shows what would be imported"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("env")
+                .about("Prints entries of a folder as environment variables")
+                .arg(
+                    Arg::with_name("folder")
+                        .value_name("FOLDER")
+                        .required(true)
+                        .help("Folder like `dev/`, `/` for whole vault. Entries have no tags"),
+                )
+                .arg(
+                    Arg::with_name("shell")
+                        .long("shell")
+                        .value_name("FORMAT")
+                        .takes_value(true)
+                        .possible_values(&ENV_FORMATS)
+                        .default_value("dotenv")
+                        .help("Format of variables, `sh` and `fish` can be given to eval"),
+                )
+                .arg(
+                    Arg::with_name("prefix")
+                        .long("prefix")
+                        .value_name("PREFIX")
+                        .takes_value(true)
+                        .help("Put PREFIX before every variable name"),
+                )
+                .arg(
+                    Arg::with_name("full-name")
+                        .long("full-name")
+                        .help("Name variables after whole entry name, folder included"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Exports every entry of vault")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .takes_value(true)
+                        .possible_values(&EXPORT_FORMATS)
+                        .default_value("json")
+                        .help("Format of exported data"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Writes export to FILE instead of stdout"),
+                )
+                .arg(
+                    Arg::with_name("encrypt")
+                        .long("encrypt")
+                        .help("Protects JSON export with a separate passphrase"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .help("Does not ask before writing unencrypted passwords"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Overwrite output file if it already exists"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Saves a copy of encrypted vault")
+                .arg(
+                    Arg::with_name("dest")
+                        .value_name("DEST")
+                        .help("File or directory for backup, defaults to backups next to vault")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Replaces vault with a backup after checking its Master Key")
+                .arg(
+                    Arg::with_name("backup")
+                        .value_name("BACKUP")
+                        .help("Backup made by `givme backup`")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Verifies every row of vault can be decrypted and looked up")
+                .arg(
+                    Arg::with_name("quarantine")
+                        .long("quarantine")
+                        .help("Moves bad rows to cred_quarantine table after a backup"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Merges another copy of this vault both ways")
+                .arg(
+                    Arg::with_name("other")
+                        .value_name("OTHER")
+                        .help("Vault file copied from another computer")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("policy")
+                        .long("policy")
+                        .value_name("POLICY")
+                        .takes_value(true)
+                        .possible_values(&["ask", "newest", "keep-both"])
+                        .default_value("ask")
+                        .help("What to do with entries changed in both vaults"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("git")
+                .about("Keeps vault history in git and syncs it through a git remote")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("init")
+                        .about("Creates git repository holding one encrypted file per entry")
+                        .arg(
+                            Arg::with_name("remote")
+                                .long("remote")
+                                .value_name("URL")
+                                .takes_value(true)
+                                .help("Git remote to push to and pull from"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("push").about("Commits vault and pushes it to remote"),
+                )
+                .subcommand(
+                    SubCommand::with_name("pull")
+                        .about("Merges changes pushed from other computers")
+                        .arg(
+                            Arg::with_name("policy")
+                                .long("policy")
+                                .value_name("POLICY")
+                                .takes_value(true)
+                                .possible_values(&["ask", "newest", "keep-both"])
+                                .default_value("ask")
+                                .help("What to do with entries changed on both sides"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("log")
+                        .about("Shows history of vault")
+                        .arg(
+                            Arg::with_name("count")
+                                .short("n")
+                                .value_name("COUNT")
+                                .takes_value(true)
+                                .default_value("20")
+                                .help("Number of commits to show"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("agent")
+                .about("Keeps vault unlocked in background so Master Key is asked once")
+                .arg(
+                    Arg::with_name("idle")
+                        .long("idle")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .default_value("900")
+                        .help("Forget Master Key after this long without use"),
+                )
+                .arg(
+                    Arg::with_name("lifetime")
+                        .long("lifetime")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .default_value("14400")
+                        .help("Forget Master Key after this long, even if in use"),
+                )
+                .arg(
+                    Arg::with_name("foreground")
+                        .long("foreground")
+                        .help("Stays in foreground instead of detaching"),
+                )
+                .arg(Arg::with_name("serve").long("serve").hidden(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("lock").about("Stops agent, wiping cached Master Key"),
+        )
+        .subcommand(
+            SubCommand::with_name("shell")
+                .about("Unlocks vault once and runs commands on it interactively")
+                .arg(
+                    Arg::with_name("idle")
+                        .long("idle")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .default_value("300")
+                        .help("Locks vault after this long without input"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tui").about("Browses vault in a full screen terminal UI"),
+        )
+        .subcommand(
+            SubCommand::with_name("clear-clipboard")
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name("after")
+                        .long("after")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs a command with entries set as environment variables")
+                .arg(
+                    Arg::with_name("env")
+                        .long("env")
+                        .short("e")
+                        .value_name("NAME=ENTRY")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true)
+                        .help("Sets variable NAME to value of ENTRY. Can be repeated"),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .value_name("COMMAND")
+                        .multiple(true)
+                        .required(true)
+                        .last(true)
+                        .help("Command to run, after --"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inject")
+                .about("Fills {{ givme \"entry\" \"field\" }} placeholders of a template")
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .short("i")
+                        .value_name("TEMPLATE")
+                        .takes_value(true)
+                        .help("Template to render, `-` or none reads stdin"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Writes result to FILE, readable only by you, instead of stdout"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Overwrite output file if it already exists"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("git-credential")
+                .about("Credential helper for git, matching entries by URL and username")
+                .arg(
+                    Arg::with_name("action")
+                        .value_name("ACTION")
+                        .required(true)
+                        .help("get, store or erase, given by git"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serves a JSON API on localhost for other tools")
+                .arg(
+                    Arg::with_name("bind")
+                        .long("bind")
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:7878")
+                        .help("Loopback address and port to listen on"),
+                )
+                .arg(
+                    Arg::with_name("token-file")
+                        .long("token-file")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Where to write bearer token, `serve-token` next to vault by default"),
+                )
+                .arg(
+                    Arg::with_name("unlock")
+                        .long("unlock")
+                        .help("Ask Master Key now instead of waiting for POST /unlock"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("identity")
+                .about("Manage X25519 identity of this vault")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Prints public key others can share secrets with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("share")
+                .about("Encrypts a stored entry for one or more recipients")
+                .arg(
+                    Arg::with_name("key")
+                        .help("Key to share")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .short("t")
+                        .value_name("PUBKEY")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Public key (or file containing it) of recipient. Can be repeated"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Writes blob to FILE instead of stdout"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Overwrite output file if it already exists"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("receive")
+                .about("Opens a blob shared with this vault")
+                .arg(
+                    Arg::with_name("blob")
+                        .value_name("FILE")
+                        .help("File holding the blob. Reads stdin when omitted")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("save")
+                        .long("save")
+                        .help("Stores received entry in this vault"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Where to write a received file"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Overwrite output file if it already exists"),
+                ),
+        )
     // .subcommand(
     //     SubCommand::with_name("test")
     //         .about("controls testing features")
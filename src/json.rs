@@ -0,0 +1,353 @@
+/// Minimal JSON value. Just enough to read exports of other
+/// password managers without pulling in a dependency.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Keys are kept in order of appearance
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Value of `key` if this is an object having it
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(pairs) => pairs
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// String content, `None` for every other type
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Items if this is an array, empty slice otherwise
+    pub fn as_array(&self) -> &[JsonValue] {
+        match self {
+            JsonValue::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Quote and escape a string for JSON
+fn json_escape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len() + 2);
+    output.push('"');
+    for c in input.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+fn write_json(value: &JsonValue, indent: usize, output: &mut String) {
+    let padding = "  ".repeat(indent + 1);
+    match value {
+        JsonValue::Null => output.push_str("null"),
+        JsonValue::Bool(b) => output.push_str(if *b { "true" } else { "false" }),
+        JsonValue::Number(n) => output.push_str(&n.to_string()),
+        JsonValue::String(s) => output.push_str(&json_escape(s)),
+        JsonValue::Array(items) if items.is_empty() => output.push_str("[]"),
+        JsonValue::Object(pairs) if pairs.is_empty() => output.push_str("{}"),
+        JsonValue::Array(items) => {
+            output.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                output.push_str(&padding);
+                write_json(item, indent + 1, output);
+                output.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            output.push_str(&"  ".repeat(indent));
+            output.push(']');
+        }
+        JsonValue::Object(pairs) => {
+            output.push_str("{\n");
+            for (i, (key, item)) in pairs.iter().enumerate() {
+                output.push_str(&padding);
+                output.push_str(&json_escape(key));
+                output.push_str(": ");
+                write_json(item, indent + 1, output);
+                output.push_str(if i + 1 < pairs.len() { ",\n" } else { "\n" });
+            }
+            output.push_str(&"  ".repeat(indent));
+            output.push('}');
+        }
+    }
+}
+
+/// Serialize a value as indented JSON
+pub fn to_json(value: &JsonValue) -> String {
+    let mut output = String::new();
+    write_json(value, 0, &mut output);
+    output
+}
+
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> JsonParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.chars.peek() {
+            if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("Expected '{}' but found '{}'", expected, c)),
+            None => Err(format!("Expected '{}' but input ended", expected)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some('t') => self.parse_literal("true", JsonValue::Bool(true)),
+            Some('f') => self.parse_literal("false", JsonValue::Bool(false)),
+            Some('n') => self.parse_literal("null", JsonValue::Null),
+            Some(_) => self.parse_number(),
+            None => Err(String::from("Unexpected end of JSON")),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, String> {
+        for expected in literal.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("Invalid literal, expected '{}'", literal));
+            }
+        }
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let mut number = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_digit() || "+-.eE".contains(c) {
+                number.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        number
+            .parse()
+            .map(JsonValue::Number)
+            .map_err(|_| format!("Invalid number '{}'", number))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+        if hex.len() != 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid unicode escape '{}'", hex));
+        }
+        u32::from_str_radix(&hex, 16).map_err(|_| format!("Invalid unicode escape '{}'", hex))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut output = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(output),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => output.push('\n'),
+                    Some('t') => output.push('\t'),
+                    Some('r') => output.push('\r'),
+                    Some('b') => output.push('\u{8}'),
+                    Some('f') => output.push('\u{c}'),
+                    Some('u') => {
+                        let mut code = self.parse_hex4()?;
+                        /* Surrogate pair */
+                        if (0xD800..0xDC00).contains(&code) {
+                            if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                                return Err(String::from("Unpaired surrogate in string"));
+                            }
+                            let low = self.parse_hex4()?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(String::from("Unpaired surrogate in string"));
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        output.push(
+                            std::char::from_u32(code)
+                                .ok_or_else(|| String::from("Invalid unicode escape"))?,
+                        );
+                    }
+                    Some(c) => output.push(c),
+                    None => return Err(String::from("Unterminated string")),
+                },
+                Some(c) => output.push(c),
+                None => return Err(String::from("Unterminated string")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(JsonValue::Array(items)),
+                _ => return Err(String::from("Expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, String> {
+        self.expect('{')?;
+        let mut pairs = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(JsonValue::Object(pairs));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            pairs.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(JsonValue::Object(pairs)),
+                _ => return Err(String::from("Expected ',' or '}' in object")),
+            }
+        }
+    }
+}
+
+/// Parse a complete JSON document
+pub fn parse_json(data: &str) -> Result<JsonValue, String> {
+    let mut parser = JsonParser {
+        chars: data.trim_start_matches('\u{feff}').chars().peekable(),
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.chars.next().is_some() {
+        return Err(String::from("Trailing data after JSON document"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_document() {
+        let value = parse_json(
+            "\u{feff} {\"items\": [1, -2.5e1, true, false, null, \"x\"], \"empty\": {}, \"none\": []}",
+        )
+        .unwrap();
+        assert_eq!(
+            value.get("items").unwrap().as_array(),
+            &[
+                JsonValue::Number(1.0),
+                JsonValue::Number(-25.0),
+                JsonValue::Bool(true),
+                JsonValue::Bool(false),
+                JsonValue::Null,
+                JsonValue::String(String::from("x")),
+            ]
+        );
+        assert_eq!(value.get("empty"), Some(&JsonValue::Object(Vec::new())));
+        assert_eq!(value.get("none"), Some(&JsonValue::Array(Vec::new())));
+        assert_eq!(value.get("missing"), None);
+    }
+
+    #[test]
+    fn decodes_escapes_and_surrogate_pairs() {
+        let value = parse_json(r#""a\"b\\c\/d\n\t\u00e9\ud83d\ude00""#).unwrap();
+        assert_eq!(value.as_str(), Some("a\"b\\c/d\n\t\u{e9}\u{1f600}"));
+        let value = parse_json(r#""\uD834\uDD1E""#).unwrap();
+        assert_eq!(value.as_str(), Some("\u{1d11e}"));
+    }
+
+    #[test]
+    fn rejects_broken_surrogates() {
+        assert!(parse_json(r#""\ud83d""#).is_err());
+        assert!(parse_json(r#""\ud83dx""#).is_err());
+        assert!(parse_json(r#""\ud83d\u0041""#).is_err());
+        assert!(parse_json(r#""\ud83d\ud83d""#).is_err());
+        assert!(parse_json(r#""\ude00""#).is_err());
+        assert!(parse_json(r#""\u12""#).is_err());
+        assert!(parse_json(r#""\u+123""#).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_and_truncated_input() {
+        for input in [
+            "",
+            "{",
+            "{\"a\"",
+            "{\"a\":",
+            "{\"a\": 1",
+            "{\"a\" 1}",
+            "{a: 1}",
+            "[1, 2",
+            "[1 2]",
+            "\"open",
+            "\"escape\\",
+            "tru",
+            "nul",
+            "-",
+            "1 2",
+            "{} x",
+        ]
+        .iter()
+        {
+            assert!(parse_json(input).is_err(), "{:?} was accepted", input);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_to_json() {
+        let value = JsonValue::Object(vec![
+            (
+                String::from("name"),
+                JsonValue::String(String::from("quote \" slash \\ line\n\u{1}")),
+            ),
+            (
+                String::from("list"),
+                JsonValue::Array(vec![JsonValue::Number(3.0), JsonValue::Null]),
+            ),
+        ]);
+        assert_eq!(parse_json(&to_json(&value)).unwrap(), value);
+    }
+}
@@ -1,20 +1,89 @@
+mod agent;
+pub use agent::*;
+
+mod backup;
+pub use backup::*;
+
+mod check;
+pub use check::*;
+
+mod clipboard;
+pub use clipboard::*;
+
+mod settings;
+pub use settings::*;
+
 mod cred;
 pub use cred::*;
 
+mod csv;
+pub use csv::*;
+
+mod dotenv;
+pub use dotenv::*;
+
 mod encryption;
 pub use encryption::*;
 
+mod export;
+pub use export::*;
+
+mod git;
+pub use git::*;
+
+mod git_credential;
+pub use git_credential::*;
+
+mod identity;
+pub use identity::*;
+
+mod import;
+pub use import::*;
+
+mod inject;
+pub use inject::*;
+
 mod io;
 pub use io::*;
 
+mod json;
+pub use json::*;
+
 mod os;
 pub use os::*;
 
+mod run;
+pub use run::*;
+
+mod serve;
+pub use serve::*;
+
+mod shell;
+pub use shell::*;
+
 mod sql;
 pub use sql::*;
 
+mod storage;
+pub use storage::*;
+
+mod sync;
+pub use sync::*;
+
+mod term;
+pub use term::*;
+
+mod tui;
+pub use tui::*;
+
+mod twofactor;
+pub use twofactor::*;
+
 mod models;
 pub use models::*;
 
 mod utils;
 pub use utils::*;
+
+mod xml;
+pub use xml::*;
@@ -1,9 +1,29 @@
-use std::{io, io::Write};
+use std::{fs, io, io::Read, io::Write};
 
+use clap::ArgMatches;
 use givme::{
-    ask_pass_and_extract_key, ask_user_for_value, decrypt_file, delete_credentails, encrypt_file,
-    get_os_and_username, get_secret_key, get_sql_con, give_credentials, givme::GivMe, is_first_run,
-    parse_args, run_setup, save_credentials, show_credentials,
+    agent_running, ask_for_confirmation, ask_pass_and_extract_key, ask_passphrase,
+    ask_user_for_otp, ask_user_for_value, auto_backup, backup_vault, check_vault,
+    clear_clipboard_later, connect_storage, copy_to_clipboard,
+    credentials::Credentials,
+    debug, decrypt_file, decrypt_file_with_passphrase, decrypt_shared_file,
+    decrypt_with_passphrase, delete_credentails, encrypt_file, encrypt_file_for_recipients,
+    encrypt_file_with_passphrase, encrypt_with_passphrase,
+    enums::{DuplicatePolicy, ExportFormat, ImportFormat, SharedPayload},
+    erase_git_credential, export_credentials, export_public_key, folder_variables,
+    format_git_credential, generate_otp, get_os_and_username, get_secret_key, git_init, git_log,
+    git_pull, git_push, give_all_credentials, give_credentials,
+    givme::GivMe,
+    import_credentials, is_first_run, is_portable_file, is_shared_blob, lock_agent,
+    matching_git_credentials, otp_to_uri, parse_args, parse_duplicate_policy, parse_env_format,
+    parse_env_mapping, parse_export_format, parse_git_credential, parse_import_format, parse_otp,
+    parse_recipient, parse_sync_policy, read_import, read_key_file, read_pass_dir, read_pass_entry,
+    receive_agent_password, receive_blob, render_env, render_template, require_sqlite,
+    restore_vault, run_setup, run_shell, run_tui, run_with_secrets, save_credentials,
+    secret::Secret,
+    serve_agent, serve_api, serve_token_path, share_credentials, show_check_report,
+    show_credentials, show_import_summary, show_sync_summary, start_agent, store_git_credential,
+    sync_vaults, take_stdin, write_file_atomically,
 };
 
 fn main() {
@@ -12,60 +32,139 @@ fn main() {
     let mut arg_hit = false;
     let mut handle = GivMe::new();
     get_os_and_username(&mut handle);
+    /* Restore has to work even when vault is lost */
+    if let ("restore", Some(restore_args)) = args.subcommand() {
+        let backup = restore_args.value_of("backup").unwrap();
+        match restore_vault(backup, &mut handle) {
+            Ok(_) => println!("Vault restored from {}", backup),
+            Err(err) => {
+                eprintln!("-- Error in restoring '{}'", backup);
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
     if is_first_run(&handle).unwrap() {
         run_setup(&mut handle).unwrap();
         std::process::exit(0);
-    } else {
-        get_sql_con(&mut handle);
+    } else if let Err(err) = connect_storage(&mut handle) {
+        eprintln!("-- Error in opening vault");
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
 
     if args.is_present("encrypt-file") {
         arg_hit = true;
-        if ask_pass_and_extract_key(&mut handle).unwrap() {
-            let paths: Vec<&str> = args.values_of("encrypt-file").unwrap().collect();
-            let in_path = paths[0];
-            let out_path = paths[1];
-            match encrypt_file(in_path.to_string(), out_path.to_string(), &mut handle) {
-                Ok(_) => {
-                    println!("{} Encrypted Successfully to {}", in_path, out_path);
-                }
-                Err(err) => {
-                    eprintln!("-- Error in Encryption of file '{}'", in_path);
-                    if err.kind() == io::ErrorKind::InvalidData {
-                        eprintln!("[!>] Only pure text files can be encrypted");
-                    }
-                    eprintln!("{:?}", err);
+        let paths: Vec<&str> = args.values_of("encrypt-file").unwrap().collect();
+        let in_path = paths[0];
+        let out_path = paths[1];
+        let result = if let Some(recipients) = args.values_of("recipient") {
+            match recipients
+                .map(parse_recipient)
+                .collect::<Result<Vec<Vec<u8>>, String>>()
+            {
+                Ok(recipients) => encrypt_file_for_recipients(
+                    in_path.to_string(),
+                    out_path.to_string(),
+                    &recipients,
+                    args.is_present("force"),
+                    args.is_present("shred"),
+                ),
+                Err(err) => Err(io::Error::new(io::ErrorKind::InvalidInput, err)),
+            }
+        } else if let Some(passphrase) = portable_passphrase(&args, true) {
+            encrypt_file_with_passphrase(
+                in_path.to_string(),
+                out_path.to_string(),
+                &passphrase,
+                args.is_present("force"),
+                args.is_present("shred"),
+            )
+        } else {
+            ask_pass_and_extract_key(&mut handle).unwrap();
+            encrypt_file(
+                in_path.to_string(),
+                out_path.to_string(),
+                args.is_present("force"),
+                args.is_present("shred"),
+                &mut handle,
+            )
+        };
+        match result {
+            Ok(_) => {
+                println!("{} Encrypted Successfully to {}", in_path, out_path);
+            }
+            Err(err) => {
+                eprintln!("-- Error in Encryption of file '{}'", in_path);
+                if err.kind() == io::ErrorKind::InvalidData {
+                    eprintln!("[!>] Only pure text files can be encrypted");
+                } else if err.kind() == io::ErrorKind::AlreadyExists {
+                    eprintln!("[!>] Use --force to overwrite '{}'", out_path);
                 }
-            };
-        }
+                eprintln!("{:?}", err);
+            }
+        };
     }
 
     if args.is_present("decrypt-file") {
         arg_hit = true;
-        if ask_pass_and_extract_key(&mut handle).unwrap() {
-            let paths: Vec<&str> = args.values_of("decrypt-file").unwrap().collect();
-            let in_path = paths[0];
-            let out_path = paths[1];
-            match decrypt_file(in_path.to_string(), out_path.to_string(), &mut handle) {
-                Ok(_) => {
-                    println!("{} Decrypted Successfully to {}", in_path, out_path);
+        let paths: Vec<&str> = args.values_of("decrypt-file").unwrap().collect();
+        let in_path = paths[0];
+        let out_path = paths[1];
+        let result = match portable_passphrase(&args, false) {
+            Some(passphrase) => decrypt_file_with_passphrase(
+                in_path.to_string(),
+                out_path.to_string(),
+                &passphrase,
+                args.is_present("force"),
+            ),
+            None => {
+                ask_pass_and_extract_key(&mut handle).unwrap();
+                if is_shared_blob(&fs::read(in_path).unwrap_or_default()) {
+                    decrypt_shared_file(
+                        in_path.to_string(),
+                        out_path.to_string(),
+                        args.is_present("force"),
+                        &mut handle,
+                    )
+                } else {
+                    decrypt_file(
+                        in_path.to_string(),
+                        out_path.to_string(),
+                        args.is_present("force"),
+                        &mut handle,
+                    )
                 }
-                Err(err) => {
-                    eprintln!("-- Error in Decryption of file '{}'", in_path);
-                    eprintln!("{}", err);
+            }
+        };
+        match result {
+            Ok(_) => {
+                println!("{} Decrypted Successfully to {}", in_path, out_path);
+            }
+            Err(err) => {
+                eprintln!("-- Error in Decryption of file '{}'", in_path);
+                if err.kind() == io::ErrorKind::AlreadyExists {
+                    eprintln!("[!>] Use --force to overwrite '{}'", out_path);
                 }
-            };
-        }
+                eprintln!("{}", err);
+            }
+        };
     }
 
     if args.is_present("delete") {
         arg_hit = true;
         if ask_pass_and_extract_key(&mut handle).unwrap() {
             let key_to_delete = args.value_of("delete").unwrap().to_string();
-            match delete_credentails(key_to_delete.clone(), &mut handle) {
-                Ok(_) => {
+            match auto_backup("delete", &handle)
+                .and_then(|_| delete_credentails(key_to_delete.clone(), &mut handle))
+            {
+                Ok(true) => {
                     println!("'{}' deleted successfully", key_to_delete);
                 }
+                Ok(false) => {
+                    eprintln!("'{}' not found!", key_to_delete);
+                }
                 Err(err) => {
                     eprintln!("-- Error in deleting '{}'", key_to_delete);
                     eprintln!("{}", err);
@@ -76,7 +175,18 @@ fn main() {
     if args.is_present("store") {
         arg_hit = true;
         if ask_pass_and_extract_key(&mut handle).unwrap() {
-            let cred = ask_user_for_value(args.value_of("store").unwrap()).unwrap();
+            let mut cred = ask_user_for_value(args.value_of("store").unwrap()).unwrap();
+            if args.is_present("otp") {
+                let input = ask_user_for_otp(&cred.key).unwrap();
+                match parse_otp(&input, &cred.key) {
+                    Ok(otp) => cred.otp = Some(Secret::from(otp_to_uri(&otp))),
+                    Err(err) => {
+                        eprintln!("-- Error in 2FA secret");
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
             match save_credentials(cred, &mut handle) {
                 Ok(_) => {
                     println!("Saved Successfully");
@@ -93,8 +203,20 @@ fn main() {
             match give_credentials(args.value_of("key").unwrap().to_string(), &mut handle) {
                 Some(cred) => {
                     if args.is_present("raw") {
-                        print!("{}", cred.value);
+                        print!("{}", cred.value.expose());
                         io::stdout().flush().unwrap();
+                    } else if args.is_present("copy") {
+                        match copy_to_clipboard(cred.value.expose(), &handle) {
+                            Ok(0) => println!("'{}' copied to clipboard", cred.key),
+                            Ok(seconds) => println!(
+                                "'{}' copied to clipboard, cleared in {} seconds",
+                                cred.key, seconds
+                            ),
+                            Err(err) => {
+                                eprintln!("-- Error in copying '{}'", cred.key);
+                                eprintln!("{}", err);
+                            }
+                        }
                     } else {
                         show_credentials(&cred);
                     }
@@ -110,14 +232,620 @@ fn main() {
         arg_hit = true;
         if ask_pass_and_extract_key(&mut handle).unwrap() {
             let secret_key = get_secret_key(&mut handle);
-            println!("{}", secret_key);
+            println!("{}", secret_key.expose());
         }
     } else if args.is_present("set-secret-key") {
         arg_hit = true;
         if ask_pass_and_extract_key(&mut handle).unwrap() {}
     }
 
+    match args.subcommand() {
+        ("otp", Some(otp_args)) => {
+            arg_hit = true;
+            let key = otp_args.value_of("key").unwrap().to_string();
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                match generate_otp(key.clone(), &mut handle) {
+                    Ok((code, _)) if otp_args.is_present("raw") => {
+                        print!("{}", code);
+                        io::stdout().flush().unwrap();
+                    }
+                    Ok((code, Some(remaining))) => {
+                        println!("\nHere's your code for '{}':  {}", key, code);
+                        println!("Valid for {} more seconds", remaining);
+                    }
+                    Ok((code, None)) => {
+                        println!("\nHere's your code for '{}':  {}", key, code);
+                    }
+                    Err(err) => {
+                        eprintln!("-- Error: {}", err);
+                    }
+                }
+            }
+        }
+        ("import", Some(import_args)) => {
+            arg_hit = true;
+            let path = import_args.value_of("file").unwrap();
+            let format = parse_import_format(import_args.value_of("format").unwrap()).unwrap();
+            let policy =
+                parse_duplicate_policy(import_args.value_of("on-duplicate").unwrap()).unwrap();
+            let dry_run = import_args.is_present("dry-run");
+            let records = if format == ImportFormat::PassDir && path != "-" {
+                read_pass_dir(path)
+            } else {
+                read_input(path).and_then(|data| read_records(format, &data, import_args))
+            };
+            match records {
+                Ok(records) => {
+                    if ask_pass_and_extract_key(&mut handle).unwrap() {
+                        if !dry_run && policy == DuplicatePolicy::Overwrite {
+                            if let Err(err) = auto_backup("import", &handle) {
+                                eprintln!("-- Error in backing up vault before import");
+                                eprintln!("{}", err);
+                                std::process::exit(1);
+                            }
+                        }
+                        let summary = import_credentials(records, policy, dry_run, &mut handle);
+                        show_import_summary(&summary, dry_run);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("-- Error in reading '{}'", path);
+                    eprintln!("{}", err);
+                }
+            }
+        }
+        ("backup", Some(backup_args)) => {
+            arg_hit = true;
+            match backup_vault(backup_args.value_of("dest"), &handle) {
+                Ok(path) => println!("Vault backed up to {}", path),
+                Err(err) => {
+                    eprintln!("-- Error in backing up vault");
+                    eprintln!("{}", err);
+                }
+            }
+        }
+        ("check", Some(check_args)) => {
+            arg_hit = true;
+            if let Err(err) = require_sqlite("Check", &handle) {
+                eprintln!("-- Error in checking vault");
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            let report = check_vault(check_args.is_present("quarantine"), &mut handle);
+            show_check_report(&report);
+            if report.problems.len() > report.quarantined.len() {
+                std::process::exit(1);
+            }
+        }
+        ("sync", Some(sync_args)) => {
+            arg_hit = true;
+            let other = sync_args.value_of("other").unwrap();
+            let policy = parse_sync_policy(sync_args.value_of("policy").unwrap()).unwrap();
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                match sync_vaults(other, policy, &mut handle) {
+                    Ok(summary) => show_sync_summary(&summary),
+                    Err(err) => {
+                        eprintln!("-- Error in syncing with '{}'", other);
+                        eprintln!("{}", err);
+                    }
+                }
+            }
+        }
+        ("git", Some(git_args)) => {
+            arg_hit = true;
+            match git_args.subcommand() {
+                ("init", Some(init_args)) => {
+                    /* Sealed storage can only be read once unlocked */
+                    ask_pass_and_extract_key(&mut handle).unwrap();
+                    match git_init(init_args.value_of("remote"), &handle) {
+                        Ok(dir) => println!("Vault history kept in {}", dir.display()),
+                        Err(err) => {
+                            eprintln!("-- Error in creating git repository");
+                            eprintln!("{}", err);
+                        }
+                    }
+                }
+                ("push", Some(_)) => {
+                    ask_pass_and_extract_key(&mut handle).unwrap();
+                    match git_push(&handle) {
+                        Ok(_) => println!("Vault pushed"),
+                        Err(err) => {
+                            eprintln!("-- Error in pushing vault");
+                            eprintln!("{}", err);
+                        }
+                    }
+                }
+                ("pull", Some(pull_args)) => {
+                    let policy = parse_sync_policy(pull_args.value_of("policy").unwrap()).unwrap();
+                    if ask_pass_and_extract_key(&mut handle).unwrap() {
+                        match git_pull(policy, &mut handle) {
+                            Ok(summary) => show_sync_summary(&summary),
+                            Err(err) => {
+                                eprintln!("-- Error in pulling vault");
+                                eprintln!("{}", err);
+                            }
+                        }
+                    }
+                }
+                ("log", Some(log_args)) => {
+                    let count = match log_args.value_of("count").unwrap().parse() {
+                        Ok(count) => count,
+                        Err(_) => {
+                            eprintln!("-- Error: COUNT must be a number");
+                            std::process::exit(1);
+                        }
+                    };
+                    if ask_pass_and_extract_key(&mut handle).unwrap() {
+                        match git_log(count, &handle) {
+                            Ok(log) => print!("{}", log),
+                            Err(err) => {
+                                eprintln!("-- Error in reading vault history");
+                                eprintln!("{}", err);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        ("agent", Some(agent_args)) => {
+            arg_hit = true;
+            let seconds = |name| agent_args.value_of(name).unwrap().parse::<u64>();
+            let (idle, lifetime) = match (seconds("idle"), seconds("lifetime")) {
+                (Ok(idle), Ok(lifetime)) => (idle, lifetime),
+                _ => {
+                    eprintln!("-- Error: SECONDS must be a number");
+                    std::process::exit(1);
+                }
+            };
+            let result = if agent_args.is_present("serve") {
+                receive_agent_password()
+                    .and_then(|password| serve_agent(password, idle, lifetime, &handle))
+            } else if agent_running(&handle) {
+                Err(String::from(
+                    "Agent is already running, `givme lock` stops it",
+                ))
+            } else if ask_pass_and_extract_key(&mut handle).unwrap() {
+                let password = handle.password.clone().unwrap();
+                if agent_args.is_present("foreground") {
+                    println!("Agent running, Ctrl-C or `givme lock` stops it");
+                    serve_agent(password, idle, lifetime, &handle)
+                } else {
+                    start_agent(&password, idle, lifetime, &handle)
+                        .map(|_| println!("Agent started, `givme lock` stops it"))
+                }
+            } else {
+                Ok(())
+            };
+            if let Err(err) = result {
+                eprintln!("-- Error in running agent");
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        ("lock", Some(_)) => {
+            arg_hit = true;
+            match lock_agent(&handle) {
+                Ok(true) => println!("Agent stopped, Master Key wiped"),
+                Ok(false) => println!("No agent running"),
+                Err(err) => {
+                    eprintln!("-- Error in stopping agent");
+                    eprintln!("{}", err);
+                }
+            }
+        }
+        ("shell", Some(shell_args)) => {
+            arg_hit = true;
+            let idle = match shell_args.value_of("idle").unwrap().parse() {
+                Ok(idle) => idle,
+                Err(_) => {
+                    eprintln!("-- Error: SECONDS must be a number");
+                    std::process::exit(1);
+                }
+            };
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                run_shell(idle, &mut handle);
+            }
+        }
+        ("tui", Some(_)) => {
+            arg_hit = true;
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                if let Err(err) = run_tui(&mut handle) {
+                    eprintln!("-- Error in running TUI");
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("clear-clipboard", Some(clear_args)) => {
+            arg_hit = true;
+            if let Ok(seconds) = clear_args.value_of("after").unwrap().parse() {
+                if let Err(err) = clear_clipboard_later(seconds, &handle) {
+                    eprintln!("-- Error in clearing clipboard");
+                    eprintln!("{}", err);
+                }
+            }
+        }
+        ("run", Some(run_args)) => {
+            arg_hit = true;
+            let env = match run_args
+                .values_of("env")
+                .unwrap()
+                .map(parse_env_mapping)
+                .collect::<Result<Vec<(String, String)>, String>>()
+            {
+                Ok(env) => env,
+                Err(err) => {
+                    eprintln!("-- Error: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let command: Vec<&str> = run_args.values_of("command").unwrap().collect();
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                match run_with_secrets(&command, &env, &mut handle) {
+                    Ok(code) => std::process::exit(code),
+                    Err(err) => {
+                        eprintln!("-- Error in running '{}'", command[0]);
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        ("inject", Some(inject_args)) => {
+            arg_hit = true;
+            let input = inject_args.value_of("input").unwrap_or("-");
+            let template = match read_input(input) {
+                Ok(template) => template,
+                Err(err) => {
+                    eprintln!("-- Error in reading '{}'", input);
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            };
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                let rendered = match render_template(&template, &mut handle) {
+                    Ok(rendered) => rendered,
+                    Err(err) => {
+                        eprintln!("-- Error in rendering '{}'", input);
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                };
+                match inject_args.value_of("output") {
+                    Some(out_path) => {
+                        match write_file_atomically(
+                            out_path,
+                            rendered.expose().as_bytes(),
+                            inject_args.is_present("force"),
+                        ) {
+                            Ok(_) => eprintln!("Rendered to {}", out_path),
+                            Err(err) => {
+                                eprintln!("-- Error in writing '{}'", out_path);
+                                eprintln!("{}", err);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    None => {
+                        print!("{}", rendered.expose());
+                        io::stdout().flush().unwrap();
+                    }
+                }
+            }
+        }
+        ("env", Some(env_args)) => {
+            arg_hit = true;
+            let format = parse_env_format(env_args.value_of("shell").unwrap()).unwrap();
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                match folder_variables(
+                    env_args.value_of("folder").unwrap(),
+                    env_args.value_of("prefix").unwrap_or_default(),
+                    env_args.is_present("full-name"),
+                    &mut handle,
+                ) {
+                    Ok(variables) => {
+                        if variables.is_empty() {
+                            eprintln!("No entries in '{}'", env_args.value_of("folder").unwrap());
+                        }
+                        print!("{}", render_env(format, &variables).expose());
+                        io::stdout().flush().unwrap();
+                    }
+                    Err(err) => {
+                        eprintln!("-- Error in listing variables");
+                        eprintln!("{}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        ("git-credential", Some(credential_args)) => {
+            arg_hit = true;
+            let action = credential_args.value_of("action").unwrap();
+            /* Git expects helpers to ignore actions they don't know */
+            if !["get", "store", "erase"].contains(&action) {
+                return;
+            }
+            let request = match take_stdin()
+                .map_err(|err| err.to_string())
+                .and_then(|input| parse_git_credential(&input))
+            {
+                Ok(request) => request,
+                Err(err) => {
+                    eprintln!("-- Error in reading request of git");
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            };
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                let result = match action {
+                    "get" => match matching_git_credentials(&request, &mut handle).first() {
+                        Some(cred) => format_git_credential(cred).map(|answer| {
+                            print!("{}", answer.expose());
+                            io::stdout().flush().unwrap();
+                        }),
+                        None => Ok(()),
+                    },
+                    "store" => store_git_credential(&request, &mut handle)
+                        .map(|name| debug(&format!("git credential saved as '{}'", name))),
+                    _ => erase_git_credential(&request, &mut handle).map(|names| {
+                        for name in names {
+                            debug(&format!("'{}' rejected by git, deleted", name));
+                        }
+                    }),
+                };
+                if let Err(err) = result {
+                    eprintln!("-- Error in git credential {}", action);
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ("serve", Some(serve_args)) => {
+            arg_hit = true;
+            if serve_args.is_present("unlock") && !ask_pass_and_extract_key(&mut handle).unwrap() {
+                return;
+            }
+            let result = match serve_args.value_of("token-file") {
+                Some(path) => Ok(std::path::PathBuf::from(path)),
+                None => serve_token_path(&handle),
+            }
+            .and_then(|token_path| {
+                serve_api(
+                    serve_args.value_of("bind").unwrap(),
+                    &token_path,
+                    &mut handle,
+                )
+            });
+            if let Err(err) = result {
+                eprintln!("-- Error in serving API");
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        ("export", Some(export_args)) => {
+            arg_hit = true;
+            let format = parse_export_format(export_args.value_of("format").unwrap()).unwrap();
+            let encrypted = export_args.is_present("encrypt");
+            if encrypted && format != ExportFormat::Json {
+                eprintln!("-- Error: Only json exports can be encrypted");
+                std::process::exit(1);
+            }
+            if ask_pass_and_extract_key(&mut handle).unwrap() {
+                export(format, export_args, &mut handle);
+            }
+        }
+        ("identity", Some(identity_args)) => {
+            arg_hit = true;
+            if identity_args.subcommand_matches("export").is_some()
+                && ask_pass_and_extract_key(&mut handle).unwrap()
+            {
+                match export_public_key(&mut handle) {
+                    Ok(public_key) => println!("{}", public_key),
+                    Err(err) => {
+                        eprintln!("-- Error in exporting identity");
+                        eprintln!("{}", err);
+                    }
+                }
+            }
+        }
+        ("share", Some(share_args)) => {
+            arg_hit = true;
+            let key = share_args.value_of("key").unwrap().to_string();
+            let recipients = share_args
+                .values_of("to")
+                .unwrap()
+                .map(parse_recipient)
+                .collect::<Result<Vec<Vec<u8>>, String>>();
+            match recipients {
+                Ok(recipients) => {
+                    if ask_pass_and_extract_key(&mut handle).unwrap() {
+                        match share_credentials(key.clone(), &recipients, &mut handle) {
+                            Ok(blob) => match share_args.value_of("output") {
+                                Some(out_path) => {
+                                    match write_file_atomically(
+                                        out_path,
+                                        blob.as_bytes(),
+                                        share_args.is_present("force"),
+                                    ) {
+                                        Ok(_) => println!("'{}' shared to {}", key, out_path),
+                                        Err(err) => {
+                                            eprintln!("-- Error in writing '{}'", out_path);
+                                            eprintln!("{}", err);
+                                        }
+                                    }
+                                }
+                                None => println!("{}", blob),
+                            },
+                            Err(err) => {
+                                eprintln!("-- Error in sharing '{}'", key);
+                                eprintln!("{}", err);
+                            }
+                        }
+                    }
+                }
+                Err(err) => {
+                    eprintln!("-- Error: {}", err);
+                }
+            }
+        }
+        ("receive", Some(receive_args)) => {
+            arg_hit = true;
+            let blob = match receive_args.value_of("blob") {
+                Some(path) => fs::read_to_string(path),
+                None => {
+                    let mut blob = String::new();
+                    io::stdin().read_to_string(&mut blob).map(|_| blob)
+                }
+            };
+            match blob {
+                Ok(blob) => {
+                    if ask_pass_and_extract_key(&mut handle).unwrap() {
+                        receive(&blob, receive_args, &mut handle);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("-- Error in reading blob");
+                    eprintln!("{}", err);
+                }
+            }
+        }
+        _ => {}
+    }
+
     if !arg_hit {
         app.print_help().unwrap();
     }
 }
+
+/// Read file given on command line, `-` reads stdin
+fn read_input(path: &str) -> Result<String, String> {
+    if path == "-" {
+        return take_stdin().map_err(|err| err.to_string());
+    }
+    fs::read_to_string(path).map_err(|err| err.to_string())
+}
+
+/// Turn data given to `givme import` into entries, asking for
+/// passphrase of encrypted exports
+fn read_records(
+    format: ImportFormat,
+    data: &str,
+    import_args: &ArgMatches,
+) -> Result<Vec<Result<Credentials, String>>, String> {
+    if format == ImportFormat::PassDir {
+        return match import_args.value_of("name") {
+            Some(name) => Ok(vec![read_pass_entry(name, data)]),
+            None => Err(String::from(
+                "--name is needed to import `pass show` output",
+            )),
+        };
+    }
+    if !is_portable_file(data.as_bytes()) {
+        return read_import(format, data);
+    }
+    /* Only `givme export --encrypt` writes encrypted exports */
+    let passphrase = ask_passphrase(false).map_err(|err| err.to_string())?;
+    let decrypted = decrypt_with_passphrase(data, &passphrase)?;
+    let data = String::from_utf8(decrypted).map_err(|err| err.to_string())?;
+    read_import(ImportFormat::GivmeJson, &data)
+}
+
+/// Write every entry as asked by `givme export`
+fn export(format: ExportFormat, export_args: &ArgMatches, handle: &mut GivMe) {
+    let mut creds = Vec::new();
+    for cred in give_all_credentials(handle) {
+        match cred {
+            Ok(cred) => creds.push(cred),
+            Err(err) => eprintln!("-- Skipping an entry which failed to decrypt: {}", err),
+        }
+    }
+
+    let mut output = export_credentials(format, &creds);
+    if export_args.is_present("encrypt") {
+        let passphrase = ask_passphrase(true).unwrap();
+        output = encrypt_with_passphrase(output.as_bytes(), &passphrase).unwrap();
+    } else if !export_args.is_present("yes")
+        && !ask_for_confirmation(&format!(
+            "This writes {} passwords without any encryption.",
+            creds.len()
+        ))
+    {
+        println!("Exiting...");
+        return;
+    }
+
+    match export_args.value_of("output") {
+        Some(out_path) => {
+            match write_file_atomically(
+                out_path,
+                output.as_bytes(),
+                export_args.is_present("force"),
+            ) {
+                Ok(_) => println!("{} entries exported to {}", creds.len(), out_path),
+                Err(err) => {
+                    eprintln!("-- Error in writing '{}'", out_path);
+                    eprintln!("{}", err);
+                }
+            }
+        }
+        None => println!("{}", output),
+    }
+}
+
+/// Show or store what `givme receive` got in a blob
+fn receive(blob: &str, receive_args: &ArgMatches, handle: &mut GivMe) {
+    match receive_blob(blob, handle) {
+        Ok(SharedPayload::Entry(cred)) => {
+            show_credentials(&cred);
+            if receive_args.is_present("save") {
+                let key = cred.key.clone();
+                match save_credentials(cred, handle) {
+                    Ok(_) => println!("'{}' Saved Successfully", key),
+                    Err(err) => {
+                        eprintln!("-- Error in saving '{}'", key);
+                        eprintln!("{}", err);
+                    }
+                }
+            }
+        }
+        Ok(SharedPayload::File(data)) => match receive_args.value_of("output") {
+            Some(out_path) => {
+                match write_file_atomically(out_path, &data, receive_args.is_present("force")) {
+                    Ok(_) => println!("Received file written to {}", out_path),
+                    Err(err) => {
+                        eprintln!("-- Error in writing '{}'", out_path);
+                        eprintln!("{}", err);
+                    }
+                }
+            }
+            None => {
+                eprintln!("-- Error: Blob holds a file. Use --output to choose where to write it")
+            }
+        },
+        Err(err) => {
+            eprintln!("-- Error in receiving blob");
+            eprintln!("{}", err);
+        }
+    }
+}
+
+/// Passphrase for portable file encryption if user asked for one,
+/// either interactively or through a key file.
+fn portable_passphrase(args: &ArgMatches, confirm: bool) -> Option<String> {
+    let passphrase = if let Some(key_file) = args.value_of("key-file") {
+        read_key_file(key_file)
+    } else if args.is_present("passphrase") {
+        ask_passphrase(confirm)
+    } else {
+        return None;
+    };
+    match passphrase {
+        Ok(passphrase) => Some(passphrase),
+        Err(err) => {
+            eprintln!("-- Error in reading passphrase");
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
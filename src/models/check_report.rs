@@ -0,0 +1,10 @@
+/// Outcome of `givme check`
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    /// Entry rows which were read
+    pub rows_checked: usize,
+    /// Row id, if the problem is about a single row, and what is wrong
+    pub problems: Vec<(Option<i64>, String)>,
+    /// Row ids moved to `cred_quarantine`
+    pub quarantined: Vec<i64>,
+}
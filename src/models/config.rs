@@ -0,0 +1,34 @@
+use super::enums::StorageKind;
+
+/// Settings read from `config` file next to vault
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub storage: StorageKind,
+    /// Path of vault for `StorageKind::JsonFile`, `vault.json` next to
+    /// config file when not set
+    pub json_path: Option<String>,
+    /// Path of vault for `StorageKind::Sealed`, `vault.sealed` next to
+    /// config file when not set
+    pub sealed_path: Option<String>,
+    /// Command copying its stdin to clipboard, a known clipboard program
+    /// is used when not set
+    pub clipboard_copy: Option<String>,
+    /// Command printing what clipboard holds, used to check if a copied
+    /// value is still there before clearing it
+    pub clipboard_paste: Option<String>,
+    /// Seconds after which a copied value is cleared, 0 keeps it
+    pub clipboard_clear: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            storage: StorageKind::Sqlite,
+            json_path: None,
+            sealed_path: None,
+            clipboard_copy: None,
+            clipboard_paste: None,
+            clipboard_clear: 45,
+        }
+    }
+}
@@ -1,9 +1,23 @@
+use super::secret::Secret;
+
 /// Used as a wrapper for data from/to Database
 #[derive(Clone, Debug)]
 pub struct Credentials {
     pub key: String,
-    pub value: String,
-    pub info: Option<String>,
+    /// Password or other secret, plain or encrypted
+    pub value: Secret,
+    pub info: Option<Secret>,
+    /// `otpauth://` URI of one time password, if any
+    pub otp: Option<Secret>,
+    pub username: Option<Secret>,
+    pub url: Option<String>,
+    /// Unix time of last change, `None` for rows saved before it was
+    /// tracked. Used by `givme sync` to tell newer edits apart.
+    pub modified: Option<u64>,
+    /// Entry name encrypted with a random nonce, for stored rows whose
+    /// `key` is the HMAC index of the name. `None` on rows saved before
+    /// names were indexed, their `key` is the encrypted name itself.
+    pub name: Option<String>,
 }
 
 impl Credentials {
@@ -12,25 +26,25 @@ impl Credentials {
         if info.is_empty() {
             Credentials {
                 key: key,
-                value: value,
+                value: Secret::from(value),
                 info: None,
+                otp: None,
+                username: None,
+                url: None,
+                modified: None,
+                name: None,
             }
         } else {
             Credentials {
                 key: key,
-                value: value,
-                info: Some(info),
+                value: Secret::from(value),
+                info: Some(Secret::from(info)),
+                otp: None,
+                username: None,
+                url: None,
+                modified: None,
+                name: None,
             }
         }
     }
-
-    /// Returns tuple for data in current struct by
-    /// dealing with info which is `Option<String>
-    pub fn provide(&self) -> (String, String, String) {
-        if let Some(info) = self.info.clone() {
-            (self.key.clone(), self.value.clone(), info.clone())
-        } else {
-            (self.key.clone(), self.value.clone(), String::new())
-        }
-    }
 }
@@ -1,6 +1,171 @@
+use super::credentials::Credentials;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum OperatingSystem {
     Windows,
     Linux,
     Mac,
     Other,
-}
\ No newline at end of file
+}
+
+/// Content of a blob shared with `givme share` or encrypted
+/// for recipients with `--enc-file --recipient`
+pub enum SharedPayload {
+    Entry(Credentials),
+    File(Vec<u8>),
+}
+
+/// Hash used for HMAC while generating one time passwords
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Moving factor of one time passwords
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OtpKind {
+    /// Time based, new code every `period` seconds
+    Totp { period: u64 },
+    /// Counter based, counter is increased on every generated code
+    Hotp { counter: u64 },
+}
+
+/// Formats understood by `givme import`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportFormat {
+    Csv,
+    ChromeCsv,
+    FirefoxCsv,
+    BitwardenJson,
+    KeepassXml,
+    /// Written by `givme export --format json`, possibly encrypted
+    GivmeJson,
+    /// Decrypted tree of `pass` (password-store), or `pass show` output
+    PassDir,
+}
+
+/// What `givme import` does when an entry name is already taken
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePolicy {
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+/// Formats written by `givme export`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    KeepassXml,
+}
+
+/// Formats written by `givme env`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvFormat {
+    /// `KEY='value'` lines of a `.env` file
+    Dotenv,
+    /// POSIX shell `export KEY='value'`
+    Sh,
+    /// fish `set -x KEY 'value'`
+    Fish,
+    Json,
+}
+
+/// How `givme sync` settles an entry changed in both vaults
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// Ask user for every conflict
+    Ask,
+    /// Keep the most recently modified version
+    Newest,
+    /// Keep both, version of other vault gets a new name
+    KeepBoth,
+}
+
+/// Where entries are kept, chosen by `storage` in config file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageKind {
+    /// `cred.db` next to config, the default
+    Sqlite,
+    /// Single JSON file of encrypted rows, easy to carry around
+    JsonFile,
+    /// Whole vault encrypted and padded into a single blob
+    Sealed,
+}
+
+/// What `givme shell` got when reading a command
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShellInput {
+    /// A whole line, without the newline
+    Line(String),
+    /// Input closed or Ctrl-D on an empty line
+    End,
+    /// Nothing typed for longer than idle timeout
+    Idle,
+}
+
+/// What `Tab` does in `givme shell`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Completion {
+    /// Text to add to the line
+    Insert(String),
+    /// Several matches agreeing no further
+    Show(Vec<String>),
+    /// No match
+    Nothing,
+}
+
+/// Key pressed on a terminal read key by key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    /// Control and a letter, `Ctrl('c')` for Ctrl-C
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    /// Any other special key
+    Other,
+    /// Nothing pressed for a while
+    Timeout,
+    /// Terminal can't be read anymore
+    Closed,
+}
+
+/// What keys do in `givme tui`
+#[derive(Clone, Debug, PartialEq)]
+pub enum TuiMode {
+    /// Moving around entry list
+    Browse,
+    /// Typing into filter box
+    Filter,
+    /// Waiting for `y` to delete selected entry
+    Delete,
+    /// Typing name of a new entry with generated password
+    Generate(String),
+}
+
+/// What has to be done to the vault after a key in `givme tui`
+#[derive(Clone, Debug, PartialEq)]
+pub enum TuiAction {
+    Nothing,
+    Quit,
+    /// Copy value of selected entry
+    Copy,
+    /// Delete selected entry, already confirmed
+    Delete,
+    /// Save a new entry with a generated password
+    Generate(String),
+    /// Ask for new value of an entry
+    Edit(String),
+}
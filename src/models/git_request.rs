@@ -0,0 +1,14 @@
+use super::secret::Secret;
+
+/// Attributes git sends to a credential helper, one `key=value` per line
+#[derive(Clone, Debug, Default)]
+pub struct GitRequest {
+    /// Like `https`
+    pub protocol: Option<String>,
+    /// Host name, with port if it is not the default one
+    pub host: Option<String>,
+    /// Only sent when `credential.useHttpPath` is set
+    pub path: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+}
@@ -1,13 +1,17 @@
 use sqlite::Connection;
+use std::rc::Rc;
 
 use super::enums::OperatingSystem;
+use super::secret::Secret;
+use crate::storage::StorageBackend;
 
 pub struct GivMe {
-    pub key: Option<String>, /* Key Size must be 32 Bytes long. Repeat the sequence when storing in struct. */
-    pub password: Option<String>,
-    pub sql_con: Option<Connection>,
+    pub key: Option<Secret>, /* Key Size must be 32 Bytes long. Repeat the sequence when storing in struct. */
+    pub password: Option<Secret>,
+    pub sql_con: Option<Rc<Connection>>, /* Shared with `SqliteBackend` of vault */
     pub os: Option<OperatingSystem>,
     pub username: Option<String>,
+    pub storage: Option<Box<dyn StorageBackend>>, /* Populated by `connect_storage()` */
 }
 
 impl GivMe {
@@ -18,6 +22,7 @@ impl GivMe {
             sql_con: None,
             username: None,
             os: None,
+            storage: None,
         }
     }
 }
@@ -0,0 +1,13 @@
+/// Outcome of `givme import`
+#[derive(Clone, Debug, Default)]
+pub struct ImportSummary {
+    /// Saved under their own name
+    pub imported: usize,
+    /// Replaced an existing entry
+    pub overwritten: usize,
+    /// Saved under a new name because original was taken
+    pub renamed: usize,
+    pub skipped: usize,
+    /// Row number and reason
+    pub failed: Vec<(usize, String)>,
+}
@@ -1,3 +1,10 @@
+pub mod check_report;
+pub mod config;
 pub mod credentials;
 pub mod enums;
+pub mod git_request;
 pub mod givme;
+pub mod import_summary;
+pub mod otp;
+pub mod secret;
+pub mod sync_summary;
@@ -0,0 +1,13 @@
+use super::enums::{OtpAlgorithm, OtpKind};
+
+/// One time password parameters of an entry. Follows RFC 4226
+/// (HOTP) and RFC 6238 (TOTP).
+#[derive(Clone, Debug)]
+pub struct Otp {
+    pub label: String,
+    pub issuer: Option<String>,
+    pub secret: Vec<u8>,
+    pub algorithm: OtpAlgorithm,
+    pub digits: u32,
+    pub kind: OtpKind,
+}
@@ -0,0 +1,196 @@
+use crate::utils::{constant_time_eq, wipe};
+use std::fmt;
+
+/// String holding a key or a decrypted value. Memory is zeroed when it
+/// is dropped and it is never shown by `Debug`, use `expose()` to read
+/// it where it is really needed.
+///
+/// With `mlock` feature its memory is also kept out of swap, on a best
+/// effort basis as the limit of locked memory may be low.
+///
+/// Comparison takes same time wherever two secrets differ.
+#[derive(Default)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        #[cfg(all(unix, feature = "mlock"))]
+        memory_lock::lock(value.as_bytes());
+        Secret(value)
+    }
+
+    /// The secret itself. Avoid copying it into plain `String`s.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Secret in an optional field, like `Option::as_deref()` for strings
+pub fn exposed(secret: &Option<Secret>) -> Option<&str> {
+    secret.as_ref().map(Secret::expose)
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Secret::new(value.to_string())
+    }
+}
+
+impl Clone for Secret {
+    fn clone(&self) -> Self {
+        Secret::new(self.0.clone())
+    }
+}
+
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Zero whole allocation of `value`, also spare capacity which may still
+/// hold bytes of a longer value it was cut from. `value` then spans the
+/// whole allocation, all zero and still valid UTF-8.
+fn wipe_allocation(value: &mut String) {
+    let bytes = unsafe { value.as_mut_vec() };
+    let capacity = bytes.capacity();
+    unsafe { bytes.set_len(capacity) };
+    wipe(bytes);
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        #[cfg(all(unix, feature = "mlock"))]
+        let len = self.0.len();
+        wipe_allocation(&mut self.0);
+        #[cfg(all(unix, feature = "mlock"))]
+        memory_lock::unlock(&self.0.as_bytes()[..len]);
+    }
+}
+
+#[cfg(all(unix, feature = "mlock"))]
+mod memory_lock {
+    use lazy_static::lazy_static;
+    use std::collections::HashMap;
+    use std::ffi::c_void;
+    use std::ops::RangeInclusive;
+    use std::sync::Mutex;
+
+    extern "C" {
+        fn mlock(addr: *const c_void, len: usize) -> i32;
+        fn munlock(addr: *const c_void, len: usize) -> i32;
+        fn getpagesize() -> i32;
+    }
+
+    lazy_static! {
+        /// Number of live secrets on each locked page. Locks don't stack,
+        /// so a page is only unlocked once no secret uses it.
+        static ref LOCKED_PAGES: Mutex<HashMap<usize, usize>> = Mutex::new(HashMap::new());
+    }
+
+    fn page_size() -> usize {
+        unsafe { getpagesize() as usize }
+    }
+
+    /// Numbers of pages `bytes` spans
+    fn pages(bytes: &[u8]) -> RangeInclusive<usize> {
+        let start = bytes.as_ptr() as usize;
+        start / page_size()..=(start + bytes.len() - 1) / page_size()
+    }
+
+    pub fn lock(bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|err| err.into_inner());
+        for page in pages(bytes) {
+            let users = locked.entry(page).or_insert(0);
+            if *users == 0 {
+                unsafe { mlock((page * page_size()) as *const c_void, page_size()) };
+            }
+            *users += 1;
+        }
+    }
+
+    pub fn unlock(bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|err| err.into_inner());
+        for page in pages(bytes) {
+            if let Some(users) = locked.get_mut(&page) {
+                *users -= 1;
+                if *users == 0 {
+                    locked.remove(&page);
+                    unsafe { munlock((page * page_size()) as *const c_void, page_size()) };
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn page_stays_locked_while_a_secret_uses_it() {
+            /* Whole page of our own, no other secret can share it */
+            let buffer = vec![1u8; 3 * page_size()];
+            let offset = page_size() - buffer.as_ptr() as usize % page_size();
+            let (first, second) = buffer[offset..offset + page_size()].split_at(page_size() / 2);
+            let page = *pages(first).start();
+            let users = |page| LOCKED_PAGES.lock().unwrap().get(&page).copied();
+
+            lock(first);
+            lock(second);
+            assert_eq!(users(page), Some(2));
+            unlock(first);
+            assert_eq!(users(page), Some(1));
+            unlock(second);
+            assert_eq!(users(page), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_whole_value() {
+        assert_eq!(Secret::from("password"), Secret::from("password"));
+        assert_ne!(Secret::from("password"), Secret::from("passwore"));
+        assert_ne!(Secret::from("password"), Secret::from("pass"));
+        assert_ne!(Secret::from(""), Secret::from("p"));
+        assert_eq!(Secret::default(), Secret::from(""));
+    }
+
+    #[test]
+    fn wipes_spare_capacity() {
+        let mut value = String::with_capacity(32);
+        value.push_str("hunter2 and what came after it");
+        value.truncate(7);
+        wipe_allocation(&mut value);
+        assert_eq!(value.len(), 32);
+        assert!(value.bytes().all(|byte| byte == 0));
+    }
+
+    #[test]
+    fn never_shows_value() {
+        let secret = Secret::from("hunter2");
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(exposed(&Some(secret)), Some("hunter2"));
+    }
+}
@@ -0,0 +1,14 @@
+/// Outcome of `givme sync`
+#[derive(Clone, Debug, Default)]
+pub struct SyncSummary {
+    /// Entries added or changed in this vault
+    pub pulled: usize,
+    /// Entries added or changed in other vault
+    pub pushed: usize,
+    /// Entries deleted in this vault because they were deleted there
+    pub deleted_here: usize,
+    /// Entries deleted in other vault because they were deleted here
+    pub deleted_there: usize,
+    /// Name of each entry changed in both vaults and how it was settled
+    pub conflicts: Vec<(String, String)>,
+}
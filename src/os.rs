@@ -1,7 +1,11 @@
 use crate::io::{debug, DEBUG};
 use crate::models::enums::OperatingSystem;
 use crate::models::givme::GivMe;
+use crate::storage::storage_path;
+use rand::random;
 use std::env::consts::OS;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
 use std::path::Path;
 
 /// Checks for current OS and user. Populate in provided
@@ -20,43 +24,217 @@ pub fn get_os_and_username(handle: &mut GivMe) {
     handle.username = Some(whoami::username());
 }
 
-/// Check if our database file exist. If not, then
+/// Path of vault database file for current OS and user
+pub fn vault_path(handle: &GivMe) -> Result<String, String> {
+    if *DEBUG {
+        return Ok(String::from("./cred_debug.db"));
+    }
+    match handle.os.as_ref().unwrap() {
+        OperatingSystem::Linux | OperatingSystem::Mac => Ok(format!(
+            "{}/.config/givme/cred.db",
+            home::home_dir()
+                .expect("Consider settings for home dir")
+                .display()
+        )),
+        OperatingSystem::Windows => Ok(format!(
+            "C:\\Users\\{}\\givme\\cred.db",
+            handle.username.as_ref().unwrap()
+        )),
+        /* Maybe working on some other platforms like MacOS but still not sure */
+        OperatingSystem::Other => Err(String::from("Unsupported Platform")),
+    }
+}
+
+/// Check if our vault file exist. If not, then
 /// this is our first run.
 pub fn is_first_run(handle: &GivMe) -> Result<bool, String> {
-    if *DEBUG {
-        Ok(!(Path::new("./cred_debug.db").exists()))
-    } else {
-        match handle.os.as_ref().unwrap() {
-            OperatingSystem::Linux => Ok(!(Path::new(
-                format!(
-                    "{}/.config/givme/cred.db",
-                    home::home_dir()
-                        .expect("Consider settings for home dir")
-                        .display()
-                )
-                .as_str(),
-            )
-            .exists())),
-            OperatingSystem::Mac => Ok(!(Path::new(
-                format!(
-                    "{}/.config/givme/cred.db",
-                    home::home_dir()
-                        .expect("Consider settings for home dir")
-                        .display()
-                )
-                .as_str(),
-            )
-            .exists())),
-            OperatingSystem::Windows => Ok(!(Path::new(
-                format!(
-                    "C:\\Users\\{}\\givme\\cred.db",
-                    handle.username.as_ref().unwrap()
-                )
-                .as_str(),
-            )
-            .exists())),
-            /* Maybe working on some other platforms like MacOS but still not sure */
-            OperatingSystem::Other => Err(String::from("Unsupported Platform")),
+    Ok(!Path::new(&storage_path(handle)?).exists())
+}
+
+/// Write `data` to `out_path` without ever leaving a half written or
+/// truncated destination behind.
+///
+/// Data goes to a temporary file in the same directory first, which is
+/// fsynced and then renamed over the destination. An existing destination
+/// is only replaced when `force` is set, otherwise the temporary file is
+/// hard linked to the destination, which fails if a file got there in the
+/// meantime. On Unix the file is only readable by current user.
+pub fn write_file_atomically(out_path: &str, data: &[u8], force: bool) -> io::Result<()> {
+    let out = Path::new(out_path);
+    let already_exists = || {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", out_path),
+        )
+    };
+    if out.exists() && !force {
+        return Err(already_exists());
+    }
+
+    let dir = match out.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = out
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid destination"))?;
+    let tmp_path = dir.join(format!(
+        ".{}.givme-{:08x}.tmp",
+        file_name.to_string_lossy(),
+        random::<u32>()
+    ));
+    debug(format!("Writing to temporary file {}", tmp_path.display()).as_str());
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    /* Output is either encrypted or a secret, keep it private to user */
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let result = options
+        .open(&tmp_path)
+        .and_then(|mut tmp_file| {
+            tmp_file.write_all(data)?;
+            tmp_file.sync_all()
+        })
+        .and_then(|_| {
+            if force {
+                return fs::rename(&tmp_path, out);
+            }
+            match fs::hard_link(&tmp_path, out) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(already_exists()),
+                Err(err) => {
+                    /* Filesystem without hard links, still never replace a file */
+                    debug(format!("Can't hard link, writing in place: {}", err).as_str());
+                    let mut out_file = options.open(out).map_err(|err| match err.kind() {
+                        io::ErrorKind::AlreadyExists => already_exists(),
+                        _ => err,
+                    })?;
+                    out_file.write_all(data)?;
+                    out_file.sync_all()
+                }
+                Ok(_) => Ok(()),
+            }
+        });
+
+    if !force || result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result?;
+
+    /* Persist the rename or link itself. Directories can't be opened like this on Windows */
+    if let Ok(dir_handle) = File::open(dir) {
+        let _ = dir_handle.sync_all();
+    }
+    Ok(())
+}
+
+/// Fail when `in_path` and `out_path` are the same file, checked before
+/// anything is written. Shredding the source would then destroy the
+/// output that just replaced it.
+pub fn refuse_shredding_output(in_path: &str, out_path: &str) -> io::Result<()> {
+    let same = match (fs::canonicalize(in_path), fs::canonicalize(out_path)) {
+        (Ok(source), Ok(destination)) => source == destination,
+        /* Destination does not exist yet, so it can't be the source */
+        _ => false,
+    };
+    if same {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' would be both written and shredded, use another output path",
+                out_path
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Overwrite a file with random bytes before removing it, so the
+/// plaintext is not trivially recoverable after encryption.
+///
+/// ~Journaling and copy-on-write filesystems or SSDs may still keep
+/// old copies of the data around.
+pub fn shred_file(path: &str) -> io::Result<()> {
+    let length = fs::metadata(path)?.len() as usize;
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let noise: Vec<u8> = (0..length).map(|_| random::<u8>()).collect();
+    file.write_all(&noise)?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{decrypt_file_with_passphrase, encrypt_file_with_passphrase};
+    use crate::utils::test_dir;
+
+    #[test]
+    fn shredding_is_refused_when_output_is_input() {
+        let dir = test_dir("shred");
+        let path = format!("{}/plain.txt", dir);
+        fs::write(&path, "keep me").unwrap();
+        let same_path = format!("{}/./plain.txt", dir);
+
+        assert!(refuse_shredding_output(&path, &same_path).is_err());
+        assert!(
+            encrypt_file_with_passphrase(path.clone(), same_path, "passphrase", true, true)
+                .is_err()
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+
+        let other_path = format!("{}/plain.txt.enc", dir);
+        assert!(refuse_shredding_output(&path, &other_path).is_ok());
+        encrypt_file_with_passphrase(path.clone(), other_path.clone(), "passphrase", true, true)
+            .unwrap();
+        assert!(!Path::new(&path).exists());
+        assert!(Path::new(&other_path).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn files_in(dir: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn existing_destination_is_never_clobbered() {
+        let dir = test_dir("atomic");
+        let out = format!("{}/out.txt", dir);
+        write_file_atomically(&out, b"first", false).unwrap();
+        let err = write_file_atomically(&out, b"second", false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&out).unwrap(), b"first");
+        write_file_atomically(&out, b"third", true).unwrap();
+        assert_eq!(fs::read(&out).unwrap(), b"third");
+        assert_eq!(files_in(&dir), ["out.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_decrypt_leaves_destination_untouched() {
+        let dir = test_dir("atomic");
+        let plain = format!("{}/plain.txt", dir);
+        let encrypted = format!("{}/plain.givme", dir);
+        let out = format!("{}/out.txt", dir);
+        fs::write(&plain, "secret notes").unwrap();
+        encrypt_file_with_passphrase(plain, encrypted.clone(), "passphrase", false, false).unwrap();
+        let existing: Vec<u8> = (0..=255).collect();
+        fs::write(&out, &existing).unwrap();
+
+        for force in [false, true] {
+            assert!(
+                decrypt_file_with_passphrase(encrypted.clone(), out.clone(), "wrong", force)
+                    .is_err()
+            );
+            assert_eq!(fs::read(&out).unwrap(), existing);
         }
+        assert_eq!(files_in(&dir), ["out.txt", "plain.givme", "plain.txt"]);
+        fs::remove_dir_all(dir).unwrap();
     }
 }
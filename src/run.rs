@@ -0,0 +1,302 @@
+use crate::cred::give_credentials;
+use crate::models::givme::GivMe;
+use crate::models::secret::Secret;
+use crate::utils::MASKED_VALUE;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+/// How long output held back by `copy_masked()` waits for more input.
+/// Prompts ending in what could be the start of a value still show up.
+const FLUSH_DELAY: Duration = Duration::from_millis(100);
+
+/// Split `NAME=entry` given to `givme run --env`
+pub fn parse_env_mapping(mapping: &str) -> Result<(String, String), String> {
+    let (name, entry) = mapping
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not NAME=entry", mapping))?;
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(format!("'{}' can't be an environment variable name", name));
+    }
+    if entry.is_empty() {
+        return Err(format!("No entry given for {}", name));
+    }
+    Ok((name.to_string(), entry.to_string()))
+}
+
+/// Run `command` with value of each entry in `env` set as its variable.
+/// Values are masked wherever they show up in output of the command.
+/// Returns exit code of the command.
+pub fn run_with_secrets(
+    command: &[&str],
+    env: &[(String, String)],
+    handle: &mut GivMe,
+) -> Result<i32, String> {
+    let mut secrets = Vec::new();
+    for (name, entry) in env {
+        let cred = give_credentials(entry.clone(), handle)
+            .ok_or_else(|| format!("'{}' not found!", entry))?;
+        secrets.push((name.as_str(), cred.value));
+    }
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .envs(secrets.iter().map(|(name, value)| (name, value.expose())))
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Can't run {}: {}", command[0], err))?;
+
+    let mut values: Vec<&Secret> = secrets
+        .iter()
+        .map(|(_, value)| value)
+        .filter(|value| !value.expose().is_empty())
+        .collect();
+    /* Longer values first, so one containing another is masked whole */
+    values.sort_by_key(|value| std::cmp::Reverse(value.expose().len()));
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    std::thread::scope(|scope| {
+        let errors = scope.spawn(|| copy_masked(stderr, io::stderr(), &values));
+        let _ = copy_masked(stdout, io::stdout(), &values);
+        let _ = errors.join();
+    });
+
+    let status = child.wait().map_err(|err| err.to_string())?;
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return Ok(128 + signal);
+    }
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Copy `input` to `output` with every value replaced by `MASKED_VALUE`.
+/// Output is held back while it could still become a value, so values
+/// split between reads are masked too. Held back output is written as it
+/// is when no more input comes within `FLUSH_DELAY`. It never reaches
+/// past a newline, unless a value has one.
+fn copy_masked(
+    mut input: impl Read + Send + 'static,
+    mut output: impl Write,
+    values: &[&Secret],
+) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    /* Reads block, so they are done aside to notice when input pauses */
+    std::thread::spawn(move || {
+        let mut chunk = [0u8; 4096];
+        loop {
+            let read = input.read(&mut chunk);
+            let done = !matches!(read, Ok(read) if read > 0);
+            let chunk = read.map(|read| chunk[..read].to_vec());
+            if sender.send(chunk).is_err() || done {
+                return;
+            }
+        }
+    });
+
+    let mut pending = Vec::new();
+    loop {
+        let received = if pending.is_empty() {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            receiver.recv_timeout(FLUSH_DELAY)
+        };
+        /* Empty chunk is the end of input */
+        let (chunk, end) = match received {
+            Ok(chunk) => {
+                let chunk = chunk?;
+                let end = chunk.is_empty();
+                (chunk, end)
+            }
+            Err(RecvTimeoutError::Timeout) => (Vec::new(), false),
+            Err(RecvTimeoutError::Disconnected) => (Vec::new(), true),
+        };
+        let paused = chunk.is_empty();
+        pending.extend_from_slice(&chunk);
+        let (masked, used) = mask_values(&pending, values, paused);
+        output.write_all(&masked)?;
+        output.flush()?;
+        pending.drain(..used);
+        if end {
+            return Ok(());
+        }
+    }
+}
+
+/// Mask values in `data` from its start. Stops where rest of data could
+/// turn into a value with more input, unless it is `complete`. Values
+/// overlapping each other are masked as one. Returns masked data and how
+/// much of `data` it covers.
+fn mask_values(data: &[u8], values: &[&Secret], complete: bool) -> (Vec<u8>, usize) {
+    let unfinished = |rest: &[u8]| {
+        !complete
+            && values.iter().any(|value| {
+                let value = value.expose().as_bytes();
+                value.len() > rest.len() && value.starts_with(rest)
+            })
+    };
+    let mut masked = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len() {
+        if unfinished(&data[index..]) {
+            break;
+        }
+        let found = values
+            .iter()
+            .find(|value| data[index..].starts_with(value.expose().as_bytes()));
+        match found {
+            Some(value) => {
+                let mut end = index + value.expose().len();
+                let mut next = index + 1;
+                while next < end {
+                    if unfinished(&data[next..]) {
+                        return (masked, index);
+                    }
+                    for value in values {
+                        if data[next..].starts_with(value.expose().as_bytes()) {
+                            end = end.max(next + value.expose().len());
+                        }
+                    }
+                    next += 1;
+                }
+                masked.extend_from_slice(MASKED_VALUE.as_bytes());
+                index = end;
+            }
+            None => {
+                masked.push(data[index]);
+                index += 1;
+            }
+        }
+    }
+    (masked, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    /// Input giving one chunk per read, ends once sender is dropped
+    struct Chunks(Receiver<Vec<u8>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let chunk = self.0.recv().unwrap_or_default();
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn chunks() -> (Sender<Vec<u8>>, Chunks) {
+        let (sender, receiver) = mpsc::channel();
+        (sender, Chunks(receiver))
+    }
+
+    /// Output of `copy_masked()` for input coming in `reads`
+    fn copied(reads: &[&str], values: &[&str]) -> String {
+        let values: Vec<Secret> = values.iter().map(|&value| Secret::from(value)).collect();
+        let values: Vec<&Secret> = values.iter().collect();
+        let (sender, input) = chunks();
+        for read in reads {
+            sender.send(read.as_bytes().to_vec()).unwrap();
+        }
+        drop(sender);
+        let output = Output::default();
+        copy_masked(input, output.clone(), &values).unwrap();
+        output.text()
+    }
+
+    fn masked(data: &str, values: &[&str], complete: bool) -> (String, usize) {
+        let values: Vec<Secret> = values.iter().map(|&value| Secret::from(value)).collect();
+        let values: Vec<&Secret> = values.iter().collect();
+        let (masked, used) = mask_values(data.as_bytes(), &values, complete);
+        (String::from_utf8(masked).unwrap(), used)
+    }
+
+    #[test]
+    fn masks_values_split_across_reads() {
+        assert_eq!(
+            copied(&["token: sec", "ret-", "value\n"], &["secret-value"]),
+            "token: ********\n"
+        );
+        assert_eq!(
+            copied(&["s", "e", "c", "r", "e", "t", " secre", "t"], &["secret"]),
+            "******** ********"
+        );
+        assert_eq!(copied(&["secrets"], &["secret"]), "********s");
+    }
+
+    #[test]
+    fn masks_overlapping_values_as_one() {
+        assert_eq!(
+            masked("xabcdex", &["abc", "cde"], true),
+            (String::from("x********x"), 7)
+        );
+        assert_eq!(
+            masked("xabcdx", &["abcd", "bc"], true),
+            (String::from("x********x"), 6)
+        );
+        /* Second value may still start inside the first one */
+        assert_eq!(
+            masked("xabcd", &["abc", "cdef"], false),
+            (String::from("x"), 1)
+        );
+        assert_eq!(copied(&["xabc", "dex"], &["abc", "cde"]), "x********x");
+    }
+
+    #[test]
+    fn prefix_of_value_is_written_at_end() {
+        assert_eq!(
+            masked("a pass", &["password"], false),
+            (String::from("a "), 2)
+        );
+        assert_eq!(copied(&["a pass"], &["password"]), "a pass");
+        assert_eq!(copied(&["a pass", "word"], &["password"]), "a ********");
+    }
+
+    #[test]
+    fn held_back_output_is_written_when_input_pauses() {
+        let values = [Secret::from("password")];
+        let values: Vec<&Secret> = values.iter().collect();
+        let (sender, input) = chunks();
+        let output = Output::default();
+        std::thread::scope(|scope| {
+            let copy = scope.spawn(|| copy_masked(input, output.clone(), &values));
+            sender.send(b"Enter pass".to_vec()).unwrap();
+            let started = Instant::now();
+            while output.text() != "Enter pass" {
+                assert!(started.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            sender.send(b"\nok".to_vec()).unwrap();
+            drop(sender);
+            copy.join().unwrap().unwrap();
+        });
+        assert_eq!(output.text(), "Enter pass\nok");
+    }
+}
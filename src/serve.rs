@@ -0,0 +1,558 @@
+use crate::backup::auto_backup;
+use crate::cred::{
+    delete_credentails, give_all_credentials, give_credentials, overwrite_credentials,
+    save_credentials,
+};
+use crate::export::optional_json;
+use crate::io::{debug, lock_vault, unlock_with_master_key};
+use crate::json::{parse_json, to_json, JsonValue};
+use crate::models::credentials::Credentials;
+use crate::models::givme::GivMe;
+use crate::models::secret::{exposed, Secret};
+use crate::os::{vault_path, write_file_atomically};
+use crate::twofactor::generate_otp;
+use crate::utils::{generate_password, percent_decode, GENERATED_PASSWORD_LENGTH};
+use rand::random;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Largest request body `givme serve` reads
+const SERVE_MAX_BODY: usize = 1024 * 1024;
+
+/// Longest a client may go without sending anything
+const SERVE_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest a whole request may take to arrive
+const SERVE_REQUEST_DEADLINE: Duration = Duration::from_secs(15);
+
+/// Set when `givme serve` is asked to stop by a signal
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Request read from a client of `givme serve`
+struct ApiRequest {
+    method: String,
+    /// Percent decoded, without query
+    path: String,
+    token: Option<Secret>,
+    body: Secret,
+}
+
+/// Default file `givme serve` writes its token to, next to vault
+pub fn serve_token_path(handle: &GivMe) -> Result<PathBuf, String> {
+    let vault = vault_path(handle)?;
+    Ok(Path::new(&vault)
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("serve-token"))
+}
+
+/// Answer JSON requests on `bind` until interrupted or terminated. Every
+/// request needs the random token written to `token_path`, readable only
+/// by current user. Token file is removed again on the way out. Only
+/// loopback addresses are accepted as there is no TLS.
+pub fn serve_api(bind: &str, token_path: &Path, handle: &mut GivMe) -> Result<(), String> {
+    let addresses: Vec<_> = bind
+        .to_socket_addrs()
+        .map_err(|err| format!("Can't use '{}': {}", bind, err))?
+        .collect();
+    if addresses.is_empty() || !addresses.iter().all(|address| address.ip().is_loopback()) {
+        return Err(format!(
+            "'{}' is not a loopback address, API is only served locally",
+            bind
+        ));
+    }
+    let listener = TcpListener::bind(&addresses[..])
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        .map_err(|err| format!("Can't listen on '{}': {}", bind, err))?;
+
+    let token = Secret::from(
+        random::<[u8; 32]>()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>(),
+    );
+    SHUTDOWN.store(false, Ordering::SeqCst);
+    let token_file = token_path.to_string_lossy();
+    write_file_atomically(&token_file, token.expose().as_bytes(), true)
+        .map_err(|err| format!("Can't write '{}': {}", token_file, err))?;
+    let _token_file = TokenFile {
+        path: token_path.to_path_buf(),
+        token: token.clone(),
+    };
+    catch_shutdown_signals();
+    eprintln!(
+        "Listening on http://{}, token is in {}",
+        listener.local_addr().map_err(|err| err.to_string())?,
+        token_file
+    );
+
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(err) => {
+                debug(format!("Can't accept: {}", err).as_str());
+                continue;
+            }
+        };
+        let _ = stream.set_nonblocking(false);
+        let deadline = Instant::now() + SERVE_REQUEST_DEADLINE;
+        let (status, body) = match read_request(&stream, deadline) {
+            /* `Secret` compares in constant time */
+            Ok(request) if request.token.as_ref() != Some(&token) => {
+                (401, error_json("Missing or wrong bearer token"))
+            }
+            Ok(request) => {
+                debug(format!("{} {}", request.method, request.path).as_str());
+                route(&request, handle)
+            }
+            Err(err) => (400, error_json(&err)),
+        };
+        if let Err(err) = write_response(&mut stream, status, &body) {
+            debug(format!("Can't answer: {}", err).as_str());
+        }
+    }
+    eprintln!("Stopped serving");
+    Ok(())
+}
+
+/// Token file of `givme serve`, removed when dropped unless another
+/// instance has written its own token there since
+struct TokenFile {
+    path: PathBuf,
+    token: Secret,
+}
+
+impl Drop for TokenFile {
+    fn drop(&mut self) {
+        let written = std::fs::read_to_string(&self.path).ok().map(Secret::from);
+        if written.as_ref() == Some(&self.token) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+#[cfg(unix)]
+extern "C" fn request_shutdown(_signum: i32) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Stop serving on Ctrl-C, `kill` or a closed terminal instead of dying
+/// with the token file left behind
+#[cfg(unix)]
+fn catch_shutdown_signals() {
+    const SIGHUP: i32 = 1;
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
+    for signum in [SIGHUP, SIGINT, SIGTERM] {
+        unsafe {
+            signal(signum, request_shutdown);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_shutdown_signals() {}
+
+/// Status code and JSON answer for an authorized request
+fn route(request: &ApiRequest, handle: &mut GivMe) -> (u16, JsonValue) {
+    let body = if request.body.expose().trim().is_empty() {
+        JsonValue::Object(Vec::new())
+    } else {
+        match parse_json(request.body.expose()) {
+            Ok(body) => body,
+            Err(err) => return (400, error_json(&format!("Invalid JSON: {}", err))),
+        }
+    };
+    let method = request.method.as_str();
+    let path = request.path.trim_end_matches('/');
+    match (method, path) {
+        ("POST", "/unlock") => {
+            return match body.get("master_key").and_then(JsonValue::as_str) {
+                Some(master_key) => match unlock_with_master_key(master_key, handle) {
+                    Ok(_) => (200, status_json("unlocked")),
+                    Err(err) => (403, error_json(&err)),
+                },
+                None => (400, error_json("`master_key` is missing")),
+            };
+        }
+        ("POST", "/lock") => {
+            lock_vault(handle);
+            return (200, status_json("locked"));
+        }
+        _ => {}
+    }
+    if handle.key.is_none() {
+        return (423, error_json("Vault is locked, POST /unlock first"));
+    }
+
+    if let Some(name) = path
+        .strip_prefix("/entries/")
+        .filter(|name| !name.is_empty())
+    {
+        return match method {
+            "GET" => match give_credentials(name.to_string(), handle) {
+                Some(cred) => (200, entry_json(&cred, true)),
+                None => (404, error_json(&format!("'{}' not found!", name))),
+            },
+            "PUT" => put_entry(name, &body, handle),
+            "DELETE" => match auto_backup("delete", handle)
+                .and_then(|_| delete_credentails(name.to_string(), handle))
+            {
+                Ok(true) => (200, status_json("deleted")),
+                Ok(false) => (404, error_json(&format!("'{}' not found!", name))),
+                Err(err) => (500, error_json(&err)),
+            },
+            _ => (405, error_json("Use GET, PUT or DELETE")),
+        };
+    }
+    if let Some(name) = path.strip_prefix("/totp/").filter(|name| !name.is_empty()) {
+        if method != "GET" {
+            return (405, error_json("Use GET"));
+        }
+        return match generate_otp(name.to_string(), handle) {
+            Ok((code, remaining)) => (
+                200,
+                JsonValue::Object(vec![
+                    (String::from("code"), JsonValue::String(code)),
+                    (
+                        String::from("remaining"),
+                        remaining
+                            .map_or(JsonValue::Null, |seconds| JsonValue::Number(seconds as f64)),
+                    ),
+                ]),
+            ),
+            Err(err) => (404, error_json(&err)),
+        };
+    }
+    match (method, path) {
+        ("GET", "/entries") => {
+            let creds = give_all_credentials(handle);
+            let failed = creds.iter().filter(|cred| cred.is_err()).count();
+            let entries = creds
+                .iter()
+                .flatten()
+                .map(|cred| entry_json(cred, false))
+                .collect();
+            (
+                200,
+                JsonValue::Object(vec![
+                    (String::from("entries"), JsonValue::Array(entries)),
+                    (String::from("failed"), JsonValue::Number(failed as f64)),
+                ]),
+            )
+        }
+        ("POST", "/generate") => generate_entry(&body, handle),
+        _ => (404, error_json(&format!("No {} {}", method, path))),
+    }
+}
+
+/// Save entry from `password`, `username`, `url` and `note` of `body`.
+/// Fields left out keep their old value when entry exists.
+fn put_entry(name: &str, body: &JsonValue, handle: &mut GivMe) -> (u16, JsonValue) {
+    let field = |key: &str| body.get(key).and_then(JsonValue::as_str).map(String::from);
+    let old = give_credentials(name.to_string(), handle);
+    let password = match (field("password"), &old) {
+        (Some(password), _) => password,
+        (None, Some(old)) => old.value.expose().to_string(),
+        (None, None) => return (400, error_json("`password` is missing")),
+    };
+    let mut cred = Credentials::new(name.to_string(), password, String::new());
+    cred.info = field("note")
+        .map(Secret::from)
+        .or_else(|| old.as_ref().and_then(|old| old.info.clone()));
+    cred.username = field("username")
+        .map(Secret::from)
+        .or_else(|| old.as_ref().and_then(|old| old.username.clone()));
+    cred.url = field("url").or_else(|| old.as_ref().and_then(|old| old.url.clone()));
+    cred.otp = old.as_ref().and_then(|old| old.otp.clone());
+    let result = match old {
+        Some(_) => overwrite_credentials(cred, handle).map(|_| (200, status_json("updated"))),
+        None => save_credentials(cred, handle).map(|_| (201, status_json("created"))),
+    };
+    result.unwrap_or_else(|err| (500, error_json(&err)))
+}
+
+/// Random password of `length`, saved as entry `name` if it is given
+fn generate_entry(body: &JsonValue, handle: &mut GivMe) -> (u16, JsonValue) {
+    let length = match body.get("length") {
+        None => GENERATED_PASSWORD_LENGTH,
+        Some(JsonValue::Number(length)) if (1.0..=1024.0).contains(length) => *length as usize,
+        Some(_) => return (400, error_json("`length` has to be between 1 and 1024")),
+    };
+    let password = generate_password(length);
+    let mut answer = vec![(
+        String::from("password"),
+        JsonValue::String(password.expose().to_string()),
+    )];
+    if let Some(name) = body.get("name").and_then(JsonValue::as_str) {
+        let cred = Credentials::new(
+            name.to_string(),
+            password.expose().to_string(),
+            String::new(),
+        );
+        if let Err(err) = save_credentials(cred, handle) {
+            return (409, error_json(&err));
+        }
+        answer.push((String::from("name"), JsonValue::String(name.to_string())));
+    }
+    (200, JsonValue::Object(answer))
+}
+
+/// Entry as JSON, value only when `with_password`
+fn entry_json(cred: &Credentials, with_password: bool) -> JsonValue {
+    let mut pairs = vec![(String::from("name"), JsonValue::String(cred.key.clone()))];
+    if with_password {
+        pairs.push((
+            String::from("password"),
+            JsonValue::String(cred.value.expose().to_string()),
+        ));
+    }
+    pairs.push((
+        String::from("username"),
+        optional_json(exposed(&cred.username)),
+    ));
+    pairs.push((String::from("url"), optional_json(cred.url.as_deref())));
+    pairs.push((String::from("note"), optional_json(exposed(&cred.info))));
+    pairs.push((String::from("otp"), JsonValue::Bool(cred.otp.is_some())));
+    pairs.push((
+        String::from("modified"),
+        cred.modified.map_or(JsonValue::Null, |modified| {
+            JsonValue::Number(modified as f64)
+        }),
+    ));
+    JsonValue::Object(pairs)
+}
+
+fn status_json(status: &str) -> JsonValue {
+    JsonValue::Object(vec![(
+        String::from("status"),
+        JsonValue::String(status.to_string()),
+    )])
+}
+
+fn error_json(error: &str) -> JsonValue {
+    JsonValue::Object(vec![(
+        String::from("error"),
+        JsonValue::String(error.to_string()),
+    )])
+}
+
+/// Reads of a client, failing once `deadline` passed. A client which
+/// sends slowly can't hold up the others for long either.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request took too long",
+            ));
+        }
+        self.stream
+            .set_read_timeout(Some(left.min(SERVE_READ_TIMEOUT)))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Read one HTTP/1.1 request, which has to arrive before `deadline`.
+/// Connection is closed after answering it.
+fn read_request(stream: &TcpStream, deadline: Instant) -> Result<ApiRequest, String> {
+    let reader = DeadlineReader { stream, deadline };
+    let mut reader = BufReader::new(reader.take(SERVE_MAX_BODY as u64 + 64 * 1024));
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|err| format!("Can't read request: {}", err))?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let path = percent_decode(target.split('?').next().unwrap_or_default())?;
+    if method.is_empty() || !path.starts_with('/') {
+        return Err(String::from("Malformed request line"));
+    }
+
+    let mut token = None;
+    let mut length = 0;
+    loop {
+        line.clear();
+        reader
+            .read_line(&mut line)
+            .map_err(|err| format!("Can't read request: {}", err))?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format!("Malformed header '{}'", header))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            token = value
+                .strip_prefix("Bearer ")
+                .map(|token| Secret::from(token.trim()));
+        } else if name.eq_ignore_ascii_case("content-length") {
+            length = value
+                .parse()
+                .map_err(|_| format!("Invalid Content-Length '{}'", value))?;
+        }
+    }
+    if length > SERVE_MAX_BODY {
+        return Err(format!("Body is larger than {} bytes", SERVE_MAX_BODY));
+    }
+    let mut body = vec![0u8; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|err| format!("Can't read body: {}", err))?;
+    let body = Secret::from(String::from_utf8(body).map_err(|_| "Body is not UTF-8")?);
+    Ok(ApiRequest {
+        method,
+        path,
+        token,
+        body,
+    })
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, body: &JsonValue) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        423 => "Locked",
+        _ => "Internal Server Error",
+    };
+    /* Answer may hold a value */
+    let body = Secret::from(to_json(body) + "\n");
+    let response = Secret::from(format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.expose().len(),
+        body.expose()
+    ));
+    stream.write_all(response.expose().as_bytes())?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::storage;
+    use crate::utils::{lock_test_home, test_dir, test_vault};
+
+    fn request(method: &str, path: &str, body: &str) -> ApiRequest {
+        ApiRequest {
+            method: String::from(method),
+            path: String::from(path),
+            token: None,
+            body: Secret::from(body),
+        }
+    }
+
+    #[test]
+    fn lock_wipes_sealed_storage() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sealed", None);
+        let (status, _) = route(
+            &request("PUT", "/entries/a", r#"{"password": "b"}"#),
+            &mut handle,
+        );
+        assert_eq!(status, 201);
+
+        assert_eq!(route(&request("POST", "/lock", ""), &mut handle).0, 200);
+        assert!(handle.key.is_none() && handle.password.is_none());
+        assert!(storage(&handle).list().is_err());
+        assert_eq!(
+            route(
+                &request("PUT", "/entries/a", r#"{"password": "c"}"#),
+                &mut handle
+            )
+            .0,
+            423
+        );
+
+        let unlock = request("POST", "/unlock", r#"{"master_key": "Key"}"#);
+        assert_eq!(route(&unlock, &mut handle).0, 200);
+        assert_eq!(storage(&handle).list().unwrap().len(), 1);
+    }
+
+    /// Connected client and server ends of a local connection
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn slow_request_is_cut_off_at_deadline() {
+        let (mut client, server) = connection();
+        client
+            .write_all(b"PUT /entries/a HTTP/1.1\r\nContent-Length: 1000\r\n\r\nab")
+            .unwrap();
+        let started = Instant::now();
+        let request = std::thread::scope(|scope| {
+            let read = scope.spawn(|| read_request(&server, started + Duration::from_secs(1)));
+            /* Trickles in more often than the read timeout, never finishes */
+            while !read.is_finished() && started.elapsed() < Duration::from_secs(5) {
+                let _ = client.write_all(b"c");
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            read.join().unwrap()
+        });
+        assert!(request.is_err());
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        let (mut client, server) = connection();
+        client
+            .write_all(b"GET /entries HTTP/1.1\r\nAuthorization: Bearer abc\r\n\r\n")
+            .unwrap();
+        let request = read_request(&server, Instant::now() + Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("GET", "/entries")
+        );
+        assert_eq!(request.token, Some(Secret::from("abc")));
+    }
+
+    #[test]
+    fn token_file_is_removed_on_shutdown() {
+        let _home = lock_test_home();
+        let token_path = Path::new(&test_dir("serve")).join("serve-token");
+        std::thread::scope(|scope| {
+            let serve = scope.spawn(|| {
+                let mut handle = test_vault("sqlite", None);
+                serve_api("127.0.0.1:0", &token_path, &mut handle)
+            });
+            let started = Instant::now();
+            while std::fs::read_to_string(&token_path).map_or(true, |token| token.len() != 64) {
+                assert!(started.elapsed() < Duration::from_secs(10));
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            SHUTDOWN.store(true, Ordering::SeqCst);
+            assert_eq!(serve.join().unwrap(), Ok(()));
+        });
+        assert!(!token_path.exists());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::models::{config::Config, enums::StorageKind, givme::GivMe};
+use crate::os::vault_path;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Path of config file, next to default vault
+pub fn config_path(handle: &GivMe) -> Result<PathBuf, String> {
+    let vault = vault_path(handle)?;
+    Ok(Path::new(&vault)
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("config"))
+}
+
+/// Read config file. Each line is `name = value`, lines starting with
+/// `#` are comments. Defaults are used when there is no config file.
+pub fn load_config(handle: &GivMe) -> Result<Config, String> {
+    let path = config_path(handle)?;
+    let mut config = Config::default();
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(_) if !path.exists() => return Ok(config),
+        Err(err) => return Err(format!("{}: {}", path.display(), err)),
+    };
+    for (index, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line
+            .split_once('=')
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .ok_or_else(|| format!("{}:{}: Expected `name = value`", path.display(), index + 1))?;
+        match name {
+            "storage" => {
+                config.storage = match value {
+                    "sqlite" => StorageKind::Sqlite,
+                    "json" => StorageKind::JsonFile,
+                    "sealed" => StorageKind::Sealed,
+                    _ => {
+                        return Err(format!(
+                            "{}:{}: Unknown storage '{}', use sqlite, json or sealed",
+                            path.display(),
+                            index + 1,
+                            value
+                        ))
+                    }
+                }
+            }
+            "json_path" => config.json_path = Some(value.to_string()),
+            "sealed_path" => config.sealed_path = Some(value.to_string()),
+            "clipboard_copy" => config.clipboard_copy = Some(value.to_string()),
+            "clipboard_paste" => config.clipboard_paste = Some(value.to_string()),
+            "clipboard_clear" => {
+                config.clipboard_clear = value.parse().map_err(|_| {
+                    format!(
+                        "{}:{}: clipboard_clear must be a number of seconds",
+                        path.display(),
+                        index + 1
+                    )
+                })?
+            }
+            _ => {
+                return Err(format!(
+                    "{}:{}: Unknown setting '{}'",
+                    path.display(),
+                    index + 1,
+                    name
+                ))
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Vault file used by JSON storage
+pub fn json_vault_path(config: &Config, handle: &GivMe) -> Result<String, String> {
+    match &config.json_path {
+        Some(path) => Ok(path.clone()),
+        None => Ok(config_path(handle)?
+            .with_file_name("vault.json")
+            .to_string_lossy()
+            .to_string()),
+    }
+}
+
+/// Vault file used by sealed storage
+pub fn sealed_vault_path(config: &Config, handle: &GivMe) -> Result<String, String> {
+    match &config.sealed_path {
+        Some(path) => Ok(path.clone()),
+        None => Ok(config_path(handle)?
+            .with_file_name("vault.sealed")
+            .to_string_lossy()
+            .to_string()),
+    }
+}
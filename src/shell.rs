@@ -0,0 +1,362 @@
+use crate::backup::auto_backup;
+use crate::cred::{
+    delete_credentails, give_all_credentials, give_credentials, overwrite_credentials,
+    save_credentials, show_credentials,
+};
+use crate::io::{ask_for_confirmation, ask_user_for_value, lock_vault};
+use crate::models::enums::{Completion, Key, ShellInput};
+use crate::models::givme::GivMe;
+use crate::models::secret::exposed;
+use crate::term::{read_key, RawTerminal};
+use crate::twofactor::generate_otp;
+use crate::utils::{generate_password, GENERATED_PASSWORD_LENGTH};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Usage of `givme shell` commands, each starts with the command
+const SHELL_COMMANDS: [&str; 10] = [
+    "get NAME        Show an entry",
+    "store NAME      Save a new entry",
+    "edit NAME       Change value and note of an entry",
+    "rm NAME         Delete an entry",
+    "ls              List all entries",
+    "search TEXT     Entries with TEXT in name, username or URL",
+    "gen [LENGTH]    Random password, 20 characters by default",
+    "otp NAME        Current one time password of an entry",
+    "help            Show this help",
+    "exit            Lock vault and leave",
+];
+
+/// Run commands on vault behind `handle` until user leaves or nothing
+/// is typed for `idle` seconds. Vault has to be unlocked already, keys
+/// are wiped when shell ends.
+pub fn run_shell(idle: u64, handle: &mut GivMe) {
+    let mut names = entry_names(handle);
+    println!(
+        "Vault unlocked, {} entries. Type `help` for commands.",
+        names.len()
+    );
+    loop {
+        let line = match read_command("givme> ", &names, Duration::from_secs(idle)) {
+            ShellInput::Line(line) => line,
+            ShellInput::End => {
+                println!();
+                break;
+            }
+            ShellInput::Idle => {
+                println!("\nLocked after {} seconds without use", idle);
+                break;
+            }
+        };
+        let (command, arg) = parse_command(&line);
+        match command {
+            "" => {}
+            "exit" | "quit" => break,
+            "ls" => names.iter().for_each(|name| println!("{}", name)),
+            _ => match shell_command(command, arg, handle, ask_for_confirmation) {
+                Ok(true) => names = entry_names(handle),
+                Ok(false) => {}
+                Err(err) => eprintln!("-- Error: {}", err),
+            },
+        }
+    }
+    lock_vault(handle);
+}
+
+/// Command and its argument from a typed line, both trimmed
+fn parse_command(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.split_once(' ') {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    }
+}
+
+/// Run one shell command. `confirm` is asked before anything is deleted.
+/// Returns whether entries were added or removed.
+fn shell_command(
+    command: &str,
+    arg: &str,
+    handle: &mut GivMe,
+    confirm: impl FnOnce(&str) -> bool,
+) -> Result<bool, String> {
+    let name = || {
+        if arg.is_empty() {
+            let usage = SHELL_COMMANDS
+                .iter()
+                .find(|usage| usage.split(' ').next() == Some(command))
+                .and_then(|usage| usage.split("  ").next())
+                .unwrap_or_default();
+            Err(format!("Usage: {}", usage))
+        } else {
+            Ok(arg.to_string())
+        }
+    };
+    match command {
+        "get" => {
+            let name = name()?;
+            let cred = give_credentials(name.clone(), handle)
+                .ok_or_else(|| format!("'{}' not found!", name))?;
+            show_credentials(&cred);
+            Ok(false)
+        }
+        "store" => {
+            let cred = ask_user_for_value(&name()?).map_err(|err| err.to_string())?;
+            save_credentials(cred, handle)?;
+            println!("Saved Successfully");
+            Ok(true)
+        }
+        "edit" => {
+            edit_entry(&name()?, handle)?;
+            println!("Saved Successfully");
+            Ok(false)
+        }
+        "rm" => {
+            let name = name()?;
+            if !confirm(&format!("Delete '{}'?", name)) {
+                return Ok(false);
+            }
+            auto_backup("delete", handle)?;
+            if !delete_credentails(name.clone(), handle)? {
+                return Err(format!("'{}' not found!", name));
+            }
+            println!("'{}' deleted successfully", name);
+            Ok(true)
+        }
+        "search" => {
+            let text = name()?.to_lowercase();
+            for cred in give_all_credentials(handle).into_iter().flatten() {
+                let found = [
+                    Some(cred.key.as_str()),
+                    exposed(&cred.username),
+                    cred.url.as_deref(),
+                ]
+                .iter()
+                .flatten()
+                .any(|field| field.to_lowercase().contains(&text));
+                if found {
+                    println!("{}", cred.key);
+                }
+            }
+            Ok(false)
+        }
+        "gen" => {
+            let length = match arg {
+                "" => GENERATED_PASSWORD_LENGTH,
+                length => length
+                    .parse()
+                    .map_err(|_| String::from("LENGTH must be a number"))?,
+            };
+            println!("{}", generate_password(length).expose());
+            Ok(false)
+        }
+        "otp" => {
+            let name = name()?;
+            match generate_otp(name.clone(), handle)? {
+                (code, Some(remaining)) => {
+                    println!("Code for '{}':  {} (valid {}s)", name, code, remaining)
+                }
+                (code, None) => println!("Code for '{}':  {}", name, code),
+            }
+            Ok(false)
+        }
+        "help" => {
+            SHELL_COMMANDS
+                .iter()
+                .for_each(|usage| println!("{}", usage));
+            Ok(false)
+        }
+        _ => Err(format!("Unknown command '{}', try `help`", command)),
+    }
+}
+
+/// Ask for new value and note of entry `name`, other fields are kept.
+/// Empty note keeps the old one.
+pub(crate) fn edit_entry(name: &str, handle: &mut GivMe) -> Result<(), String> {
+    let old = give_credentials(name.to_string(), handle)
+        .ok_or_else(|| format!("'{}' not found!", name))?;
+    let mut cred = ask_user_for_value(name).map_err(|err| err.to_string())?;
+    cred.info = cred.info.or(old.info);
+    cred.otp = old.otp;
+    cred.username = old.username;
+    cred.url = old.url;
+    overwrite_credentials(cred, handle).map(|_| ())
+}
+
+/// Decrypted names of all entries, sorted
+fn entry_names(handle: &mut GivMe) -> Vec<String> {
+    give_all_credentials(handle)
+        .into_iter()
+        .flatten()
+        .map(|cred| cred.key)
+        .collect()
+}
+
+/// Read one command line. On a terminal it is read key by key so `Tab`
+/// can complete commands and entry names.
+fn read_command(prompt: &str, names: &[String], idle: Duration) -> ShellInput {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    let _raw = match RawTerminal::enable() {
+        Some(raw) => raw,
+        None => {
+            let mut line = String::new();
+            return match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => ShellInput::End,
+                Ok(_) => ShellInput::Line(line.trim_end_matches(&['\r', '\n'][..]).to_string()),
+            };
+        }
+    };
+
+    let mut line = String::new();
+    let mut last_key = Instant::now();
+    loop {
+        let key = read_key();
+        match key {
+            Key::Timeout if last_key.elapsed() >= idle => return ShellInput::Idle,
+            Key::Timeout => continue,
+            _ => last_key = Instant::now(),
+        }
+        match key {
+            Key::Enter => {
+                println!();
+                return ShellInput::Line(line);
+            }
+            Key::Closed => return ShellInput::End,
+            Key::Ctrl('d') if line.is_empty() => return ShellInput::End,
+            /* Ctrl-C drops the line */
+            Key::Ctrl('c') => {
+                print!("^C\n{}", prompt);
+                line.clear();
+            }
+            Key::Backspace if !line.is_empty() => {
+                line.pop();
+                print!("\x08 \x08");
+            }
+            Key::Tab => match completion(&line, names) {
+                Completion::Insert(rest) => {
+                    print!("{}", rest);
+                    line.push_str(&rest);
+                }
+                Completion::Show(matches) => {
+                    print!("\n{}\n{}{}", matches.join("  "), prompt, line)
+                }
+                Completion::Nothing => {}
+            },
+            Key::Char(c) => {
+                print!("{}", c);
+                line.push(c);
+            }
+            _ => {}
+        }
+        io::stdout().flush().unwrap();
+    }
+}
+
+/// What `Tab` does to `line`. First word is a command, rest of line is
+/// an entry name. Several matches are completed as far as they agree
+/// and shown when there is nothing more to complete.
+fn completion(line: &str, names: &[String]) -> Completion {
+    let (start, candidates): (usize, Vec<&str>) = match line.find(' ') {
+        None => (
+            0,
+            SHELL_COMMANDS
+                .iter()
+                .filter_map(|usage| usage.split(' ').next())
+                .collect(),
+        ),
+        Some(space) => (space + 1, names.iter().map(String::as_str).collect()),
+    };
+    let typed = &line[start..];
+    let matches: Vec<&str> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(typed))
+        .collect();
+    if matches.is_empty() {
+        return Completion::Nothing;
+    }
+    let common = common_prefix(&matches);
+    if common.len() > typed.len() || matches.len() == 1 {
+        let mut rest = common[typed.len()..].to_string();
+        if matches.len() == 1 && start == 0 {
+            rest.push(' ');
+        }
+        Completion::Insert(rest)
+    } else {
+        Completion::Show(matches.into_iter().map(String::from).collect())
+    }
+}
+
+/// Longest start all `words` share
+fn common_prefix<'a>(words: &[&'a str]) -> &'a str {
+    let first = words[0];
+    let mut end = first.len();
+    for word in &words[1..] {
+        end = first[..end]
+            .char_indices()
+            .zip(word.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((index, _), _)| index)
+            .unwrap_or_else(|| end.min(word.len()));
+    }
+    &first[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::credentials::Credentials;
+    use crate::utils::{lock_test_home, test_vault};
+
+    #[test]
+    fn parses_command_and_argument() {
+        assert_eq!(parse_command("  get   prod/db  "), ("get", "prod/db"));
+        assert_eq!(parse_command("search my entry"), ("search", "my entry"));
+        assert_eq!(parse_command("ls"), ("ls", ""));
+        assert_eq!(parse_command("   "), ("", ""));
+    }
+
+    #[test]
+    fn completes_commands_then_entry_names() {
+        let names: Vec<String> = ["prod/db", "prod/dns", "staging/db"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let insert = |rest: &str| Completion::Insert(rest.to_string());
+        assert_eq!(completion("st", &names), insert("ore "));
+        assert_eq!(completion("get ", &names), Completion::Show(names.clone()));
+        assert_eq!(completion("get p", &names), insert("rod/d"));
+        assert_eq!(
+            completion("get prod/d", &names),
+            Completion::Show(vec![String::from("prod/db"), String::from("prod/dns")])
+        );
+        assert_eq!(completion("get prod/db", &names), insert(""));
+        assert_eq!(completion("get x", &names), Completion::Nothing);
+        assert_eq!(completion("x", &names), Completion::Nothing);
+        assert_eq!(common_prefix(&["über", "übel"]), "übe");
+    }
+
+    #[test]
+    fn rm_needs_confirmation() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        let cred = Credentials::new(String::from("db"), String::from("pw"), String::new());
+        save_credentials(cred, &mut handle).unwrap();
+
+        let mut asked = String::new();
+        let declined = shell_command("rm", "db", &mut handle, |question| {
+            asked = question.to_string();
+            false
+        });
+        assert_eq!(declined, Ok(false));
+        assert_eq!(asked, "Delete 'db'?");
+        assert!(give_credentials(String::from("db"), &mut handle).is_some());
+
+        assert_eq!(shell_command("rm", "db", &mut handle, |_| true), Ok(true));
+        assert!(give_credentials(String::from("db"), &mut handle).is_none());
+        assert_eq!(
+            shell_command("rm", "", &mut handle, |_| unreachable!()),
+            Err(String::from("Usage: rm NAME"))
+        );
+    }
+}
@@ -1,10 +1,18 @@
 use std::io::Write;
 
 use crate::{
+    backup::auto_backup,
     io::{debug, DEBUG},
-    models::{credentials::Credentials, enums::OperatingSystem, givme::GivMe},
+    models::{
+        credentials::Credentials,
+        enums::OperatingSystem,
+        givme::GivMe,
+        secret::{exposed, Secret},
+    },
+    utils::unix_now,
 };
 use sqlite::{Connection, State};
+use std::rc::Rc;
 
 /// Open new Sql Connection to file and populate it in
 /// `GivMe` Struct.
@@ -12,7 +20,7 @@ use sqlite::{Connection, State};
 /// Behaviour changes when DEBUG is enabled
 pub fn get_sql_con(handle: &mut GivMe) {
     if *DEBUG {
-        handle.sql_con = Some(Connection::open("./cred_debug.db").unwrap())
+        handle.sql_con = Some(Rc::new(Connection::open("./cred_debug.db").unwrap()))
     } else {
         handle.sql_con = match handle.os.as_ref().unwrap() {
             OperatingSystem::Linux => {
@@ -26,7 +34,7 @@ pub fn get_sql_con(handle: &mut GivMe) {
                     .as_str(),
                 )
                 .unwrap();
-                Some(
+                Some(Rc::new(
                     Connection::open(
                         format!(
                             "{}/.config/givme/cred.db",
@@ -37,7 +45,7 @@ pub fn get_sql_con(handle: &mut GivMe) {
                         .as_str(),
                     )
                     .unwrap(),
-                )
+                ))
             }
             OperatingSystem::Mac => {
                 std::fs::create_dir_all(
@@ -50,7 +58,7 @@ pub fn get_sql_con(handle: &mut GivMe) {
                     .as_str(),
                 )
                 .unwrap();
-                Some(
+                Some(Rc::new(
                     Connection::open(
                         format!(
                             "{}/.config/givme/cred.db",
@@ -61,14 +69,14 @@ pub fn get_sql_con(handle: &mut GivMe) {
                         .as_str(),
                     )
                     .unwrap(),
-                )
+                ))
             }
             OperatingSystem::Windows => {
                 std::fs::create_dir_all(
                     format!("C:\\Users\\{}\\givme", handle.username.as_ref().unwrap()).as_str(),
                 )
                 .unwrap();
-                Some(
+                Some(Rc::new(
                     Connection::open(
                         format!(
                             "C:\\Users\\{}\\givme\\cred.db",
@@ -77,11 +85,74 @@ pub fn get_sql_con(handle: &mut GivMe) {
                         .as_str(),
                     )
                     .unwrap(),
-                )
+                ))
             }
             OperatingSystem::Other => None,
         };
     }
+    upgrade_sql(handle);
+}
+
+/// Columns added to `cred` table after its first release, in
+/// order they were added
+const ADDED_COLUMNS: [&str; 5] = [
+    "otp TEXT",
+    "username TEXT",
+    "url TEXT",
+    "modified INTEGER",
+    "name TEXT",
+];
+
+/// Remembers names of deleted entries, so `givme sync` does not bring
+/// them back from another vault
+const TOMBSTONE_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS cred_tombstone (key TEXT PRIMARY KEY, deleted INTEGER)";
+
+/// Bring tables created by older versions up to date. Does nothing
+/// on a fresh database, `create_tables()` creates latest layout there.
+pub fn upgrade_sql(handle: &GivMe) {
+    let con = match handle.sql_con.as_ref() {
+        Some(con) => con,
+        None => return,
+    };
+    let mut columns: Vec<String> = Vec::new();
+    let mut statement = con.prepare("PRAGMA table_info(cred)").unwrap();
+    while let State::Row = statement.next().unwrap() {
+        columns.push(statement.read::<String>(1).unwrap());
+    }
+    if columns.is_empty() {
+        return;
+    }
+    con.execute(TOMBSTONE_TABLE).unwrap();
+    let missing: Vec<&str> = ADDED_COLUMNS
+        .iter()
+        .filter(|added| {
+            let name = added.split(' ').next().unwrap_or_default();
+            !columns.iter().any(|column| column == name)
+        })
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+    if let Err(err) = auto_backup("migrate", handle) {
+        eprintln!("-- Error in backing up vault before upgrading it");
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    for added in missing {
+        debug(format!("Adding {} column to cred table", added).as_str());
+        con.execute(format!("ALTER TABLE cred ADD COLUMN {}", added))
+            .unwrap();
+    }
+}
+
+/// Read optional column, treating empty string same as NULL
+fn read_optional(statement: &sqlite::Statement, column: usize) -> Option<String> {
+    statement
+        .read::<Option<String>>(column)
+        .unwrap()
+        .filter(|value| !value.is_empty())
 }
 
 /// Retreive Data from Sqlite Database by querying given key
@@ -95,23 +166,137 @@ pub fn get_from_sql(key: &str, handle: &GivMe) -> Vec<Credentials> {
         .sql_con
         .as_ref()
         .unwrap()
-        .prepare(format!("SELECT * FROM cred WHERE key = '{}'", key))
+        .prepare(
+            "SELECT key, value, info, otp, username, url, modified, name FROM cred WHERE key = ?",
+        )
         .unwrap();
-    let mut cred: Credentials;
+    statement.bind(1, key).unwrap();
     let mut cred_vec: Vec<Credentials> = Vec::new();
 
     while let State::Row = statement.next().unwrap() {
-        cred = Credentials::new(
-            statement.read::<String>(0).unwrap(),
-            statement.read::<String>(1).unwrap(),
-            statement.read::<String>(2).unwrap(),
-        );
-        cred_vec.push(cred);
+        cred_vec.push(read_credentials_row(&statement));
     }
 
     cred_vec
 }
 
+/// Build `Credentials` from current row of a statement selecting
+/// `key, value, info, otp, username, url, modified, name`
+fn read_credentials_row(statement: &sqlite::Statement) -> Credentials {
+    let mut cred = Credentials::new(
+        statement.read::<String>(0).unwrap(),
+        statement.read::<String>(1).unwrap(),
+        statement.read::<String>(2).unwrap(),
+    );
+    cred.otp = read_optional(statement, 3).map(Secret::from);
+    cred.username = read_optional(statement, 4).map(Secret::from);
+    cred.url = read_optional(statement, 5);
+    cred.modified = statement
+        .read::<Option<i64>>(6)
+        .unwrap()
+        .map(|modified| modified as u64);
+    cred.name = read_optional(statement, 7);
+    cred
+}
+
+/// Rows of `cred` table holding vault internals instead of entries.
+/// Entries are saved under base64 name index (or encrypted name in older
+/// vaults), so these never collide.
+pub const RESERVED_ROWS: [&str; 3] = ["secret_key", "identity_key", "names_indexed"];
+
+/// Retreive every entry from Sqlite Database, still encrypted.
+/// Rows listed in `RESERVED_ROWS` are left out.
+pub fn get_all_from_sql(handle: &GivMe) -> Vec<Credentials> {
+    if handle.sql_con.is_none() {
+        eprintln!("Sql connection was not initialized when getting query data from sql");
+        std::process::exit(1);
+    }
+
+    let mut statement = handle
+        .sql_con
+        .as_ref()
+        .unwrap()
+        .prepare(format!(
+            "SELECT key, value, info, otp, username, url, modified, name FROM cred WHERE key NOT IN ('{}')",
+            RESERVED_ROWS.join("', '")
+        ))
+        .unwrap();
+    let mut cred_vec: Vec<Credentials> = Vec::new();
+
+    while let State::Row = statement.next().unwrap() {
+        cred_vec.push(read_credentials_row(&statement));
+    }
+
+    cred_vec
+}
+
+/// Like `get_all_from_sql()`, but with row id of each row. Used
+/// where rows can't be told apart by key, like duplicated rows.
+pub fn get_all_rows_from_sql(handle: &GivMe) -> Vec<(i64, Credentials)> {
+    if handle.sql_con.is_none() {
+        eprintln!("Sql connection was not initialized when getting query data from sql");
+        std::process::exit(1);
+    }
+
+    let mut statement = handle
+        .sql_con
+        .as_ref()
+        .unwrap()
+        .prepare(format!(
+            "SELECT key, value, info, otp, username, url, modified, name, rowid FROM cred \
+             WHERE key NOT IN ('{}')",
+            RESERVED_ROWS.join("', '")
+        ))
+        .unwrap();
+    let mut rows = Vec::new();
+
+    while let State::Row = statement.next().unwrap() {
+        rows.push((
+            statement.read::<i64>(8).unwrap(),
+            read_credentials_row(&statement),
+        ));
+    }
+
+    rows
+}
+
+/// Move a row out of `cred` into `cred_quarantine`, keeping it
+/// encrypted along with `reason`
+pub fn quarantine_in_sql(rowid: i64, reason: &str, handle: &GivMe) -> Result<(), sqlite::Error> {
+    if handle.sql_con.is_none() {
+        eprintln!("Sql connection was not initialized when updating data in sql");
+        std::process::exit(1);
+    }
+
+    let con = handle.sql_con.as_ref().unwrap();
+    con.execute(
+        "CREATE TABLE IF NOT EXISTS cred_quarantine (key TEXT, value TEXT, info TEXT, otp TEXT, \
+         username TEXT, url TEXT, reason TEXT)",
+    )?;
+    /* Row must never end up in both tables or in none */
+    con.execute("BEGIN")?;
+    let moved = (|| {
+        let mut statement = con.prepare(
+            "INSERT INTO cred_quarantine SELECT key, value, info, otp, username, url, ?1 \
+             FROM cred WHERE rowid = ?2",
+        )?;
+        statement.bind(1, reason)?;
+        statement.bind(2, rowid)?;
+        while statement.next()? != State::Done {}
+        let mut statement = con.prepare("DELETE FROM cred WHERE rowid = ?")?;
+        statement.bind(1, rowid)?;
+        while statement.next()? != State::Done {}
+        Ok(())
+    })();
+    match moved {
+        Ok(_) => con.execute("COMMIT"),
+        Err(err) => {
+            con.execute("ROLLBACK")?;
+            Err(err)
+        }
+    }
+}
+
 /// Saves data to Sqlite database
 pub fn save_to_sql(cred: Credentials, handle: &mut GivMe) -> Result<(), sqlite::Error> {
     let mut option = String::new();
@@ -120,8 +305,6 @@ pub fn save_to_sql(cred: Credentials, handle: &mut GivMe) -> Result<(), sqlite::
         std::process::exit(1);
     }
 
-    let cred = cred.provide();
-
     let mut statement = handle
         .sql_con
         .as_ref()
@@ -132,7 +315,7 @@ pub fn save_to_sql(cred: Credentials, handle: &mut GivMe) -> Result<(), sqlite::
     let mut count: i64 = 0;
 
     // Bind the key to the statement and execute
-    statement.bind(1, &*cred.0).unwrap();
+    statement.bind(1, &*cred.key).unwrap();
 
     // Step through the result to get the count
     while let sqlite::State::Row = statement.next().unwrap() {
@@ -141,34 +324,72 @@ pub fn save_to_sql(cred: Credentials, handle: &mut GivMe) -> Result<(), sqlite::
 
     if count > 0 {
         println!("{}", count);
-        println!("Record with key {} already exist", cred.0);
+        println!("Record with key {} already exist", cred.key);
         print!("Do you want to overwrite? (y/n) ");
         std::io::stdout().flush().unwrap();
         std::io::stdin().read_line(&mut option).unwrap();
         if option.chars().next().is_some() {
             if option.to_lowercase().chars().next().unwrap() == 'y' {
-                handle
-                    .sql_con
-                    .as_ref()
-                    .unwrap()
-                    .execute(format!(
-                        "UPDATE cred SET value = '{}' info = '{}' WHERE key = '{}'",
-                        cred.1, cred.2, cred.0
-                    ))
-                    .unwrap();
+                update_in_sql(&cred, handle).unwrap();
             }
         }
     } else {
-        handle
-            .sql_con
-            .as_ref()
-            .unwrap()
-            .execute(format!(
-                "INSERT INTO cred VALUES ('{}', '{}', '{}')",
-                cred.0, cred.1, cred.2
-            ))
-            .unwrap();
+        insert_in_sql(&cred, handle)?;
+    }
+    Ok(())
+}
+
+/// Insert a row without checking for an existing one. A tombstone
+/// left by an earlier delete of the same key is removed.
+pub fn insert_in_sql(cred: &Credentials, handle: &GivMe) -> Result<(), sqlite::Error> {
+    if handle.sql_con.is_none() {
+        eprintln!("Sql connection was not initialized when saving data from sql");
+        std::process::exit(1);
+    }
+
+    let con = handle.sql_con.as_ref().unwrap();
+    let mut statement = con.prepare(
+        "INSERT INTO cred (key, value, info, otp, username, url, modified, name) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )?;
+    bind_credentials(&mut statement, cred)?;
+    while statement.next()? != State::Done {}
+    let mut statement = con.prepare("DELETE FROM cred_tombstone WHERE key = ?")?;
+    statement.bind(1, cred.key.as_str())?;
+    while statement.next()? != State::Done {}
+    Ok(())
+}
+
+/// Bind all columns of `cred` in table order. Missing optional
+/// values are stored as empty strings, like `info` always was.
+/// Without `modified` current time is stored.
+fn bind_credentials(
+    statement: &mut sqlite::Statement,
+    cred: &Credentials,
+) -> Result<(), sqlite::Error> {
+    statement.bind(1, cred.key.as_str())?;
+    statement.bind(2, cred.value.expose())?;
+    statement.bind(3, exposed(&cred.info).unwrap_or_default())?;
+    statement.bind(4, exposed(&cred.otp).unwrap_or_default())?;
+    statement.bind(5, exposed(&cred.username).unwrap_or_default())?;
+    statement.bind(6, cred.url.as_deref().unwrap_or_default())?;
+    statement.bind(7, cred.modified.unwrap_or_else(unix_now) as i64)?;
+    statement.bind(8, cred.name.as_deref().unwrap_or_default())
+}
+
+/// Replace every column of an already saved row without asking
+pub fn update_in_sql(cred: &Credentials, handle: &GivMe) -> Result<(), sqlite::Error> {
+    if handle.sql_con.is_none() {
+        eprintln!("Sql connection was not initialized when updating data in sql");
+        std::process::exit(1);
     }
+
+    let mut statement = handle.sql_con.as_ref().unwrap().prepare(
+        "UPDATE cred SET value = ?2, info = ?3, otp = ?4, username = ?5, url = ?6, \
+         modified = ?7, name = ?8 WHERE key = ?1",
+    )?;
+    bind_credentials(&mut statement, cred)?;
+    while statement.next()? != State::Done {}
     Ok(())
 }
 
@@ -179,46 +400,70 @@ pub fn del_from_sql(key: String, handle: &mut GivMe) -> Result<(), sqlite::Error
         std::process::exit(1);
     }
 
-    handle
+    remove_from_sql(&key, handle)?;
+    record_tombstone(&key, unix_now(), handle)
+}
+
+/// Delete row `key` without remembering it was deleted
+pub fn remove_from_sql(key: &str, handle: &GivMe) -> Result<(), sqlite::Error> {
+    let mut statement = handle
         .sql_con
         .as_ref()
         .unwrap()
-        .execute(format!("DELETE FROM cred WHERE key='{}'", key))
+        .prepare("DELETE FROM cred WHERE key = ?")?;
+    statement.bind(1, key)?;
+    while statement.next()? != State::Done {}
+    Ok(())
 }
 
-/// SetUp Sqlite Database. Like create new Database file and Create
-/// new table in newly created Database file
-pub fn setup_sql(handle: &mut GivMe) {
-    get_sql_con(handle);
-    match handle
+/// Remember that entry `key` was deleted at Unix time `deleted`
+pub fn record_tombstone(key: &str, deleted: u64, handle: &GivMe) -> Result<(), sqlite::Error> {
+    let mut statement = handle
         .sql_con
         .as_ref()
         .unwrap()
-        .execute("CREATE TABLE cred (key TEXT, value TEXT, info TEXT)")
-    {
-        Ok(_) => {}
-        Err(e) => {
-            debug(format!("{:?}", e).as_str());
-        }
-    };
+        .prepare("INSERT OR REPLACE INTO cred_tombstone (key, deleted) VALUES (?, ?)")?;
+    statement.bind(1, key)?;
+    statement.bind(2, deleted as i64)?;
+    while statement.next()? != State::Done {}
+    Ok(())
+}
+
+/// Every remembered delete as key and Unix time of deletion
+pub fn get_tombstones_from_sql(handle: &GivMe) -> Vec<(String, u64)> {
+    let mut statement = handle
+        .sql_con
+        .as_ref()
+        .unwrap()
+        .prepare("SELECT key, deleted FROM cred_tombstone")
+        .unwrap();
+    let mut tombstones = Vec::new();
+    while let State::Row = statement.next().unwrap() {
+        tombstones.push((
+            statement.read::<String>(0).unwrap(),
+            statement.read::<i64>(1).unwrap() as u64,
+        ));
+    }
+    tombstones
+}
+
+/// Create tables of latest layout, if they don't exist yet
+pub fn create_tables(handle: &GivMe) -> Result<(), sqlite::Error> {
+    let con = handle.sql_con.as_ref().unwrap();
+    con.execute(
+        "CREATE TABLE IF NOT EXISTS cred (key TEXT, value TEXT, info TEXT, otp TEXT, \
+         username TEXT, url TEXT, modified INTEGER, name TEXT)",
+    )?;
+    con.execute(TOMBSTONE_TABLE)
 }
 
 /// Checks if value already exist in Sqlite
 pub fn already_exist_in_sql(key: String, handle: &mut GivMe) -> Result<bool, sqlite::Error> {
-    match handle
+    let mut statement = handle
         .sql_con
         .as_ref()
         .unwrap()
-        .prepare(format!("SELECT value FROM cred WHERE key = '{}'", &key))
-    {
-        Ok(mut r) => {
-            if r.next().unwrap() == sqlite::State::Row {
-                //                println!("Exist {:?}", a);
-                Ok(true)
-            } else {
-                Ok(false)
-            }
-        }
-        Err(err) => Err(err),
-    }
+        .prepare("SELECT value FROM cred WHERE key = ?")?;
+    statement.bind(1, key.as_str())?;
+    Ok(statement.next()? == State::Row)
 }
@@ -0,0 +1,1169 @@
+use crate::encryption::{aead_open, aead_seal, decrypt, handle_from_key_material};
+use crate::json::{parse_json, to_json, JsonValue};
+use crate::models::{
+    credentials::Credentials,
+    enums::StorageKind,
+    givme::GivMe,
+    secret::{exposed, Secret},
+};
+use crate::os::{vault_path, write_file_atomically};
+use crate::settings::{config_path, json_vault_path, load_config, sealed_vault_path};
+use crate::sql::{
+    already_exist_in_sql, create_tables, del_from_sql, get_all_from_sql, get_from_sql, get_sql_con,
+    get_tombstones_from_sql, insert_in_sql, record_tombstone, remove_from_sql, update_in_sql,
+    RESERVED_ROWS,
+};
+use crate::utils::{unix_now, wipe};
+use nettle::hash::Sha256;
+use nettle::kdf::hkdf;
+use rand::random;
+use sqlite::Connection;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Where rows of the vault are kept. Rows are stored exactly as given,
+/// encryption is done before they reach a backend.
+pub trait StorageBackend {
+    /// Row saved under `key`
+    fn get(&self, key: &str) -> Result<Option<Credentials>, String>;
+    /// Save a row, replacing one with the same key. Rows without
+    /// `modified` are stamped with current time. A tombstone of the key
+    /// is removed.
+    fn put(&mut self, cred: &Credentials) -> Result<(), String>;
+    /// Remove a row and leave a tombstone, returns `false` if there was
+    /// no row
+    fn delete(&mut self, key: &str) -> Result<bool, String>;
+    /// Remove a row without a tombstone, for a row moved under another
+    /// key. Sync and git must not delete it from other vaults.
+    fn remove(&mut self, key: &str) -> Result<bool, String>;
+    /// Every row except `RESERVED_ROWS`
+    fn list(&self) -> Result<Vec<Credentials>, String>;
+    /// Keys of deleted rows with Unix time of deletion, so sync and git
+    /// don't bring deleted entries back
+    fn tombstones(&self) -> Result<Vec<(String, u64)>, String>;
+    /// Remember that row `key` was deleted at Unix time `deleted`
+    fn record_tombstone(&mut self, key: &str, deleted: u64) -> Result<(), String>;
+    /// Run `f` so that either all or none of its changes are kept
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn StorageBackend) -> Result<(), String>,
+    ) -> Result<(), String>;
+    /// Called with vault keys once Master Key was entered. Backends
+    /// encrypting the whole vault can only read rows after this.
+    fn unlock(&mut self, _key: &str, _password: &str) -> Result<(), String> {
+        Ok(())
+    }
+    /// Forget whatever `unlock()` opened
+    fn lock(&mut self) {}
+}
+
+/// Rows in `cred` table of a SQLite database, the default
+pub struct SqliteBackend {
+    /// Only `sql_con` is set, so the functions of `sql.rs` can be used
+    handle: GivMe,
+}
+
+impl SqliteBackend {
+    /// Use an already open database, which must have `cred` table.
+    /// Connection may be shared with `GivMe.sql_con` of the vault.
+    pub fn new(con: Rc<Connection>) -> Self {
+        let mut handle = GivMe::new();
+        handle.sql_con = Some(con);
+        SqliteBackend { handle }
+    }
+
+    /// Open database at `path`, creating tables if needed
+    pub fn open(path: &str) -> Result<Self, String> {
+        let con = Connection::open(path).map_err(|err| err.to_string())?;
+        let backend = SqliteBackend::new(Rc::new(con));
+        create_tables(&backend.handle).map_err(|err| err.to_string())?;
+        Ok(backend)
+    }
+}
+
+impl StorageBackend for SqliteBackend {
+    fn get(&self, key: &str) -> Result<Option<Credentials>, String> {
+        Ok(get_from_sql(key, &self.handle).into_iter().next())
+    }
+
+    fn put(&mut self, cred: &Credentials) -> Result<(), String> {
+        match already_exist_in_sql(cred.key.clone(), &mut self.handle) {
+            Ok(true) => update_in_sql(cred, &self.handle),
+            Ok(false) => insert_in_sql(cred, &self.handle),
+            Err(err) => Err(err),
+        }
+        .map_err(|err| err.to_string())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, String> {
+        if !already_exist_in_sql(key.to_string(), &mut self.handle)
+            .map_err(|err| err.to_string())?
+        {
+            return Ok(false);
+        }
+        del_from_sql(key.to_string(), &mut self.handle).map_err(|err| err.to_string())?;
+        Ok(true)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool, String> {
+        if !already_exist_in_sql(key.to_string(), &mut self.handle)
+            .map_err(|err| err.to_string())?
+        {
+            return Ok(false);
+        }
+        remove_from_sql(key, &self.handle).map_err(|err| err.to_string())?;
+        Ok(true)
+    }
+
+    fn list(&self) -> Result<Vec<Credentials>, String> {
+        Ok(get_all_from_sql(&self.handle))
+    }
+
+    fn tombstones(&self) -> Result<Vec<(String, u64)>, String> {
+        Ok(get_tombstones_from_sql(&self.handle))
+    }
+
+    fn record_tombstone(&mut self, key: &str, deleted: u64) -> Result<(), String> {
+        record_tombstone(key, deleted, &self.handle).map_err(|err| err.to_string())
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn StorageBackend) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let con = |sql: &str, backend: &SqliteBackend| {
+            backend
+                .handle
+                .sql_con
+                .as_ref()
+                .unwrap()
+                .execute(sql)
+                .map_err(|err| err.to_string())
+        };
+        con("BEGIN", self)?;
+        match f(self) {
+            Ok(_) => con("COMMIT", self),
+            Err(err) => {
+                con("ROLLBACK", self)?;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Stamp rows saved without modification time
+fn stamped(cred: &Credentials) -> Credentials {
+    let mut cred = cred.clone();
+    cred.modified = Some(cred.modified.unwrap_or_else(unix_now));
+    cred
+}
+
+/// Rows kept in memory only. Nothing survives the process, meant for
+/// tests and for tools built on this crate.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    rows: BTreeMap<String, Credentials>,
+    tombstones: BTreeMap<String, u64>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend::default()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn get(&self, key: &str) -> Result<Option<Credentials>, String> {
+        Ok(self.rows.get(key).cloned())
+    }
+
+    fn put(&mut self, cred: &Credentials) -> Result<(), String> {
+        self.tombstones.remove(&cred.key);
+        self.rows.insert(cred.key.clone(), stamped(cred));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, String> {
+        if self.rows.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.tombstones.insert(key.to_string(), unix_now());
+        Ok(true)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool, String> {
+        Ok(self.rows.remove(key).is_some())
+    }
+
+    fn list(&self) -> Result<Vec<Credentials>, String> {
+        Ok(self
+            .rows
+            .values()
+            .filter(|cred| !RESERVED_ROWS.contains(&cred.key.as_str()))
+            .cloned()
+            .collect())
+    }
+
+    fn tombstones(&self) -> Result<Vec<(String, u64)>, String> {
+        Ok(tombstone_list(&self.tombstones))
+    }
+
+    fn record_tombstone(&mut self, key: &str, deleted: u64) -> Result<(), String> {
+        self.tombstones.insert(key.to_string(), deleted);
+        Ok(())
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn StorageBackend) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let snapshot = (self.rows.clone(), self.tombstones.clone());
+        if let Err(err) = f(self) {
+            (self.rows, self.tombstones) = snapshot;
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+fn tombstone_list(tombstones: &BTreeMap<String, u64>) -> Vec<(String, u64)> {
+    tombstones
+        .iter()
+        .map(|(key, deleted)| (key.clone(), *deleted))
+        .collect()
+}
+
+/// Version written to `givme_vault` of JSON vault files
+const JSON_VAULT_VERSION: f64 = 1.0;
+
+/// Row as JSON object, fields stay as they are (encrypted)
+pub fn credentials_to_json(cred: &Credentials) -> JsonValue {
+    let mut pairs = vec![
+        (String::from("key"), JsonValue::String(cred.key.clone())),
+        (
+            String::from("value"),
+            JsonValue::String(cred.value.expose().to_string()),
+        ),
+    ];
+    for (name, field) in [
+        ("info", exposed(&cred.info)),
+        ("otp", exposed(&cred.otp)),
+        ("username", exposed(&cred.username)),
+        ("url", cred.url.as_deref()),
+        ("name", cred.name.as_deref()),
+    ] {
+        if let Some(field) = field {
+            pairs.push((String::from(name), JsonValue::String(field.to_string())));
+        }
+    }
+    pairs.push((
+        String::from("modified"),
+        JsonValue::Number(cred.modified.unwrap_or_default() as f64),
+    ));
+    JsonValue::Object(pairs)
+}
+
+/// Tombstone as JSON object, told apart from a row by `deleted`
+pub fn tombstone_to_json(key: &str, deleted: u64) -> JsonValue {
+    JsonValue::Object(vec![
+        (String::from("key"), JsonValue::String(key.to_string())),
+        (String::from("deleted"), JsonValue::Number(deleted as f64)),
+    ])
+}
+
+/// Key and time of deletion of an object written by
+/// `tombstone_to_json()`, `None` for a row
+pub fn tombstone_from_json(record: &JsonValue) -> Option<(String, u64)> {
+    match (record.get("key"), record.get("deleted")) {
+        (Some(JsonValue::String(key)), Some(JsonValue::Number(deleted))) => {
+            Some((key.clone(), *deleted as u64))
+        }
+        _ => None,
+    }
+}
+
+/// Every tombstone in array `name` of a vault object
+fn tombstones_from_json(vault: &JsonValue, name: &str) -> BTreeMap<String, u64> {
+    vault
+        .get(name)
+        .map(JsonValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(tombstone_from_json)
+        .collect()
+}
+
+fn tombstones_to_json(tombstones: &BTreeMap<String, u64>) -> JsonValue {
+    JsonValue::Array(
+        tombstones
+            .iter()
+            .map(|(key, deleted)| tombstone_to_json(key, *deleted))
+            .collect(),
+    )
+}
+
+/// Row from JSON object written by `credentials_to_json()`
+pub fn credentials_from_json(row: &JsonValue) -> Result<Credentials, String> {
+    let text = |name: &str| row.get(name).and_then(JsonValue::as_str).map(String::from);
+    let key = text("key").ok_or_else(|| String::from("Row has no key"))?;
+    let mut cred = Credentials::new(key, text("value").unwrap_or_default(), String::new());
+    cred.info = text("info").map(Secret::from);
+    cred.otp = text("otp").map(Secret::from);
+    cred.username = text("username").map(Secret::from);
+    cred.url = text("url");
+    cred.name = text("name");
+    cred.modified = match row.get("modified") {
+        Some(JsonValue::Number(modified)) => Some(*modified as u64),
+        _ => None,
+    };
+    Ok(cred)
+}
+
+/// Rows in a single JSON file. Every field is encrypted just like in
+/// SQLite, so the file is as safe to carry around as `cred.db`. File is
+/// rewritten atomically after every change.
+pub struct JsonFileBackend {
+    path: String,
+    rows: BTreeMap<String, Credentials>,
+    tombstones: BTreeMap<String, u64>,
+    /// Writing is delayed until the end of a transaction
+    in_transaction: bool,
+}
+
+impl JsonFileBackend {
+    /// Read vault at `path`, a missing file is an empty vault
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut rows = BTreeMap::new();
+        let mut tombstones = BTreeMap::new();
+        if Path::new(path).exists() {
+            let data = fs::read_to_string(path).map_err(|err| err.to_string())?;
+            let vault = parse_json(&data)?;
+            match vault.get("givme_vault") {
+                Some(JsonValue::Number(version)) if *version <= JSON_VAULT_VERSION => {}
+                Some(_) => return Err(String::from("Vault was written by a newer givme")),
+                None => return Err(format!("'{}' is not a givme vault", path)),
+            }
+            for row in vault
+                .get("rows")
+                .map(JsonValue::as_array)
+                .unwrap_or_default()
+            {
+                let cred = credentials_from_json(row)?;
+                rows.insert(cred.key.clone(), cred);
+            }
+            tombstones = tombstones_from_json(&vault, "tombstones");
+        }
+        Ok(JsonFileBackend {
+            path: path.to_string(),
+            rows,
+            tombstones,
+            in_transaction: false,
+        })
+    }
+
+    /// Write `rows` and `tombstones` to file and keep them, nothing
+    /// changes if writing fails
+    fn save(
+        &mut self,
+        rows: BTreeMap<String, Credentials>,
+        tombstones: BTreeMap<String, u64>,
+    ) -> Result<(), String> {
+        if !self.in_transaction {
+            self.write(&rows, &tombstones)?;
+        }
+        self.rows = rows;
+        self.tombstones = tombstones;
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        rows: &BTreeMap<String, Credentials>,
+        tombstones: &BTreeMap<String, u64>,
+    ) -> Result<(), String> {
+        let vault = JsonValue::Object(vec![
+            (
+                String::from("givme_vault"),
+                JsonValue::Number(JSON_VAULT_VERSION),
+            ),
+            (
+                String::from("rows"),
+                JsonValue::Array(rows.values().map(credentials_to_json).collect()),
+            ),
+            (String::from("tombstones"), tombstones_to_json(tombstones)),
+        ]);
+        write_file_atomically(&self.path, (to_json(&vault) + "\n").as_bytes(), true)
+            .map_err(|err| format!("{}: {}", self.path, err))
+    }
+}
+
+impl StorageBackend for JsonFileBackend {
+    fn get(&self, key: &str) -> Result<Option<Credentials>, String> {
+        Ok(self.rows.get(key).cloned())
+    }
+
+    fn put(&mut self, cred: &Credentials) -> Result<(), String> {
+        let mut rows = self.rows.clone();
+        let mut tombstones = self.tombstones.clone();
+        rows.insert(cred.key.clone(), stamped(cred));
+        tombstones.remove(&cred.key);
+        self.save(rows, tombstones)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, String> {
+        let mut rows = self.rows.clone();
+        if rows.remove(key).is_none() {
+            return Ok(false);
+        }
+        let mut tombstones = self.tombstones.clone();
+        tombstones.insert(key.to_string(), unix_now());
+        self.save(rows, tombstones)?;
+        Ok(true)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool, String> {
+        let mut rows = self.rows.clone();
+        if rows.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.save(rows, self.tombstones.clone())?;
+        Ok(true)
+    }
+
+    fn list(&self) -> Result<Vec<Credentials>, String> {
+        Ok(self
+            .rows
+            .values()
+            .filter(|cred| !RESERVED_ROWS.contains(&cred.key.as_str()))
+            .cloned()
+            .collect())
+    }
+
+    fn tombstones(&self) -> Result<Vec<(String, u64)>, String> {
+        Ok(tombstone_list(&self.tombstones))
+    }
+
+    fn record_tombstone(&mut self, key: &str, deleted: u64) -> Result<(), String> {
+        let mut tombstones = self.tombstones.clone();
+        tombstones.insert(key.to_string(), deleted);
+        self.save(self.rows.clone(), tombstones)
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn StorageBackend) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let snapshot = (self.rows.clone(), self.tombstones.clone());
+        self.in_transaction = true;
+        let result = f(self);
+        self.in_transaction = false;
+        match result.and_then(|_| self.write(&self.rows, &self.tombstones)) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                (self.rows, self.tombstones) = snapshot;
+                Err(err)
+            }
+        }
+    }
+}
+
+/// First line of vaults kept by `SealedBackend`
+pub const SEALED_VAULT_HEADER: &str = "GIVME-SEALED-2";
+
+/// Header of vaults sealed by older versions, without authentication.
+/// Still read, written back as `SEALED_VAULT_HEADER` once unlocked.
+const SEALED_VAULT_HEADER_V1: &str = "GIVME-SEALED-1";
+
+/// Sealed part of a vault is never smaller than this and grows in powers
+/// of two, so its size only tells an upper bound of what is inside
+const SEALED_MIN_SIZE: usize = 16 * 1024;
+
+const SEALED_SALT_SIZE: usize = 16;
+
+/// Whole vault as one encrypted and padded blob. Unlike SQLite and JSON
+/// storage, nothing about entries (count, names, sizes or which fields
+/// are set) can be learned from the file.
+///
+/// Only the encrypted Master Key row is kept outside, as it is needed to
+/// unlock the rest. Everything else is unavailable until `unlock()`.
+pub struct SealedBackend {
+    path: String,
+    secret: Option<Credentials>,
+    /// Salt and ciphertext lines as read from file, written back as they
+    /// are while locked
+    sealed: String,
+    /// Content of sealed part, once unlocked
+    unlocked: Option<Unsealed>,
+    /// Writing is delayed until the end of a transaction
+    in_transaction: bool,
+    /// Sealed part was written with `SEALED_VAULT_HEADER_V1`
+    legacy: bool,
+}
+
+/// Rows and tombstones of an unlocked `SealedBackend`, with keys they
+/// are sealed with
+struct Unsealed {
+    rows: BTreeMap<String, Credentials>,
+    tombstones: BTreeMap<String, u64>,
+    keys: GivMe,
+}
+
+impl SealedBackend {
+    /// Read vault at `path`, a missing file is an empty vault
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut backend = SealedBackend {
+            path: path.to_string(),
+            secret: None,
+            sealed: String::new(),
+            unlocked: None,
+            in_transaction: false,
+            legacy: false,
+        };
+        if !Path::new(path).exists() {
+            return Ok(backend);
+        }
+        let data = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut lines = data.lines();
+        match lines.next() {
+            Some(SEALED_VAULT_HEADER) => {}
+            Some(SEALED_VAULT_HEADER_V1) => backend.legacy = true,
+            _ => return Err(format!("'{}' is not a sealed givme vault", path)),
+        }
+        backend.secret = match lines.next().unwrap_or_default() {
+            "" => None,
+            secret => Some(Credentials::new(
+                String::from("secret_key"),
+                secret.to_string(),
+                String::new(),
+            )),
+        };
+        backend.sealed = lines.collect::<Vec<&str>>().join("\n");
+        Ok(backend)
+    }
+
+    fn unsealed(&self) -> Result<&Unsealed, String> {
+        self.unlocked
+            .as_ref()
+            .ok_or_else(|| String::from("Vault is locked"))
+    }
+
+    fn unsealed_mut(&mut self) -> Result<&mut Unsealed, String> {
+        self.unlocked
+            .as_mut()
+            .ok_or_else(|| String::from("Vault is locked"))
+    }
+
+    /// Key sealed part is encrypted with, derived from vault keys and a
+    /// salt changing on every write
+    fn sealing_key(keys: &GivMe, salt: &[u8]) -> [u8; 32] {
+        let material = Secret::from(format!(
+            "{}{}",
+            keys.key.as_ref().unwrap().expose(),
+            keys.password.as_ref().unwrap().expose()
+        ));
+        let mut key = [0u8; 32];
+        hkdf::<Sha256>(
+            material.expose().as_bytes(),
+            salt,
+            SEALED_VAULT_HEADER.as_bytes(),
+            &mut key,
+        );
+        key
+    }
+
+    /// Keys of `decrypt()` used by vaults with `SEALED_VAULT_HEADER_V1`
+    fn legacy_sealing_keys(keys: &GivMe, salt: &[u8]) -> GivMe {
+        let mut derived = [0u8; 42];
+        hkdf::<Sha256>(
+            format!(
+                "{}{}",
+                keys.key.as_ref().unwrap().expose(),
+                keys.password.as_ref().unwrap().expose()
+            )
+            .as_bytes(),
+            salt,
+            SEALED_VAULT_HEADER_V1.as_bytes(),
+            &mut derived,
+        );
+        handle_from_key_material(&derived)
+    }
+
+    /// Encrypt content padded up to next size step with ChaCha20-Poly1305,
+    /// under a fresh salt and nonce
+    fn seal(unsealed: &Unsealed) -> Result<String, String> {
+        let content = format!(
+            r#""rows":{},"tombstones":{}"#,
+            to_json(&JsonValue::Array(
+                unsealed.rows.values().map(credentials_to_json).collect(),
+            )),
+            to_json(&tombstones_to_json(&unsealed.tombstones))
+        );
+        let overhead = r#"{,"padding":""}"#.len();
+        let size = (content.len() + overhead)
+            .max(SEALED_MIN_SIZE)
+            .next_power_of_two();
+        /* Random padding, repeated blocks would show where entries end */
+        let random_bytes: Vec<u8> = (0..size).map(|_| random::<u8>()).collect();
+        let padding = &base64::encode(random_bytes)[..size - content.len() - overhead];
+        let payload = format!(r#"{{{},"padding":"{}"}}"#, content, padding);
+
+        let salt: Vec<u8> = (0..SEALED_SALT_SIZE).map(|_| random::<u8>()).collect();
+        let mut key = SealedBackend::sealing_key(&unsealed.keys, &salt);
+        let encrypted = aead_seal(&key, SEALED_VAULT_HEADER.as_bytes(), payload.as_bytes());
+        wipe(&mut key);
+        let encrypted = encrypted?;
+        Ok(format!(
+            "{}\n{}",
+            base64::encode(&salt),
+            base64::encode(encrypted)
+        ))
+    }
+
+    /// Content of sealed part written by `seal()`. Fails if it was tampered
+    /// with or keys are wrong.
+    fn unseal(sealed: &str, keys: GivMe, legacy: bool) -> Result<Unsealed, String> {
+        let mut unsealed = Unsealed {
+            rows: BTreeMap::new(),
+            tombstones: BTreeMap::new(),
+            keys,
+        };
+        if sealed.trim().is_empty() {
+            return Ok(unsealed);
+        }
+        let damaged = || String::from("Vault can't be unsealed, it may be damaged");
+        let mut lines = sealed.lines();
+        let salt = base64::decode(lines.next().unwrap_or_default()).map_err(|_| damaged())?;
+        let encrypted = base64::decode(lines.next().unwrap_or_default()).map_err(|_| damaged())?;
+        let payload = if legacy {
+            Secret::from(decrypt(
+                &encrypted,
+                &SealedBackend::legacy_sealing_keys(&unsealed.keys, &salt),
+            )?)
+        } else {
+            let mut key = SealedBackend::sealing_key(&unsealed.keys, &salt);
+            let opened = aead_open(&key, SEALED_VAULT_HEADER.as_bytes(), &encrypted);
+            wipe(&mut key);
+            let opened = opened.ok_or_else(|| String::from("Vault tampered or wrong key"))?;
+            Secret::from(String::from_utf8(opened).map_err(|_| damaged())?)
+        };
+        let payload = parse_json(payload.expose()).map_err(|_| damaged())?;
+        for row in payload
+            .get("rows")
+            .map(JsonValue::as_array)
+            .unwrap_or_default()
+        {
+            let cred = credentials_from_json(row)?;
+            unsealed.rows.insert(cred.key.clone(), cred);
+        }
+        unsealed.tombstones = tombstones_from_json(&payload, "tombstones");
+        Ok(unsealed)
+    }
+
+    /// Reseal and write vault, unless in a transaction
+    fn save(&mut self) -> Result<(), String> {
+        if self.in_transaction {
+            return Ok(());
+        }
+        if let Some(unsealed) = &self.unlocked {
+            self.sealed = SealedBackend::seal(unsealed)?;
+            self.legacy = false;
+        }
+        let header = if self.legacy {
+            SEALED_VAULT_HEADER_V1
+        } else {
+            SEALED_VAULT_HEADER
+        };
+        let secret = self
+            .secret
+            .as_ref()
+            .map(|secret| secret.value.expose().to_string())
+            .unwrap_or_default();
+        let data = format!("{}\n{}\n{}\n", header, secret, self.sealed);
+        write_file_atomically(&self.path, data.as_bytes(), true)
+            .map_err(|err| format!("{}: {}", self.path, err))
+    }
+}
+
+impl StorageBackend for SealedBackend {
+    fn get(&self, key: &str) -> Result<Option<Credentials>, String> {
+        if key == "secret_key" {
+            return Ok(self.secret.clone());
+        }
+        Ok(self.unsealed()?.rows.get(key).cloned())
+    }
+
+    fn put(&mut self, cred: &Credentials) -> Result<(), String> {
+        if cred.key == "secret_key" {
+            self.secret = Some(stamped(cred));
+        } else {
+            let unsealed = self.unsealed_mut()?;
+            unsealed.tombstones.remove(&cred.key);
+            unsealed.rows.insert(cred.key.clone(), stamped(cred));
+        }
+        self.save()
+    }
+
+    fn delete(&mut self, key: &str) -> Result<bool, String> {
+        let unsealed = self.unsealed_mut()?;
+        if unsealed.rows.remove(key).is_none() {
+            return Ok(false);
+        }
+        unsealed.tombstones.insert(key.to_string(), unix_now());
+        self.save()?;
+        Ok(true)
+    }
+
+    fn remove(&mut self, key: &str) -> Result<bool, String> {
+        if self.unsealed_mut()?.rows.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn list(&self) -> Result<Vec<Credentials>, String> {
+        Ok(self
+            .unsealed()?
+            .rows
+            .values()
+            .filter(|cred| !RESERVED_ROWS.contains(&cred.key.as_str()))
+            .cloned()
+            .collect())
+    }
+
+    fn tombstones(&self) -> Result<Vec<(String, u64)>, String> {
+        Ok(tombstone_list(&self.unsealed()?.tombstones))
+    }
+
+    fn record_tombstone(&mut self, key: &str, deleted: u64) -> Result<(), String> {
+        self.unsealed_mut()?
+            .tombstones
+            .insert(key.to_string(), deleted);
+        self.save()
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn StorageBackend) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let secret = self.secret.clone();
+        let unsealed = self.unsealed()?;
+        let snapshot = (unsealed.rows.clone(), unsealed.tombstones.clone());
+        self.in_transaction = true;
+        let result = f(self);
+        self.in_transaction = false;
+        if let Err(err) = result.and_then(|_| self.save()) {
+            self.secret = secret;
+            if let Some(unsealed) = self.unlocked.as_mut() {
+                (unsealed.rows, unsealed.tombstones) = snapshot;
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn unlock(&mut self, key: &str, password: &str) -> Result<(), String> {
+        let mut keys = GivMe::new();
+        keys.key = Some(Secret::from(key));
+        keys.password = Some(Secret::from(password));
+        self.unlocked = Some(SealedBackend::unseal(&self.sealed, keys, self.legacy)?);
+        Ok(())
+    }
+
+    fn lock(&mut self) {
+        /* Keys are `Secret`s, wiped as they are dropped */
+        self.unlocked = None;
+    }
+}
+
+/// Vault file of configured storage
+pub fn storage_path(handle: &GivMe) -> Result<String, String> {
+    let config = load_config(handle)?;
+    match config.storage {
+        StorageKind::Sqlite => vault_path(handle),
+        StorageKind::JsonFile => json_vault_path(&config, handle),
+        StorageKind::Sealed => sealed_vault_path(&config, handle),
+    }
+}
+
+/// Open storage chosen in config and populate it in `GivMe`. SQLite
+/// storage also sets `sql_con`, used by backup, check and sync, to the
+/// connection storage works on.
+pub fn connect_storage(handle: &mut GivMe) -> Result<(), String> {
+    let config = load_config(handle)?;
+    let path = storage_path(handle)?;
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    handle.storage = Some(match config.storage {
+        StorageKind::Sqlite => {
+            get_sql_con(handle);
+            create_tables(handle).map_err(|err| err.to_string())?;
+            let con = handle
+                .sql_con
+                .clone()
+                .ok_or_else(|| String::from("Can't open vault database"))?;
+            Box::new(SqliteBackend::new(con))
+        }
+        StorageKind::JsonFile => Box::new(JsonFileBackend::open(&path)?),
+        StorageKind::Sealed => Box::new(SealedBackend::open(&path)?),
+    });
+    Ok(())
+}
+
+/// Storage populated by `connect_storage()`
+pub fn storage(handle: &GivMe) -> &dyn StorageBackend {
+    match handle.storage.as_ref() {
+        Some(storage) => storage.as_ref(),
+        None => {
+            eprintln!("Storage was not initialized");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Mutable storage populated by `connect_storage()`
+pub fn storage_mut(handle: &mut GivMe) -> &mut dyn StorageBackend {
+    match handle.storage.as_mut() {
+        Some(storage) => storage.as_mut(),
+        None => {
+            eprintln!("Storage was not initialized");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Encrypted master key row of storage, exits if vault has none
+pub fn stored_secret_key(handle: &GivMe) -> String {
+    match storage(handle).get("secret_key") {
+        Ok(Some(secret)) => secret.value.expose().to_string(),
+        Ok(None) => {
+            eprintln!("Vault has no Master Key, it may be damaged");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Can't read Master Key: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Error unless vault is kept in SQLite. Backups, checks, sync and git
+/// history work on the database file itself.
+pub fn require_sqlite(what: &str, handle: &GivMe) -> Result<(), String> {
+    match load_config(handle)?.storage {
+        StorageKind::Sqlite => Ok(()),
+        _ => Err(format!(
+            "{} needs SQLite storage, set `storage = sqlite` in {}",
+            what,
+            config_path(handle)?.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{lock_test_home, test_dir, test_vault};
+
+    type Fields = (
+        String,
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<u64>,
+        Option<String>,
+    );
+
+    fn fields(cred: &Credentials) -> Fields {
+        (
+            cred.key.clone(),
+            cred.value.expose().to_string(),
+            exposed(&cred.info).map(String::from),
+            exposed(&cred.otp).map(String::from),
+            exposed(&cred.username).map(String::from),
+            cred.url.clone(),
+            cred.modified,
+            cred.name.clone(),
+        )
+    }
+
+    fn full_row(key: &str) -> Credentials {
+        let mut cred = Credentials::new(
+            key.to_string(),
+            format!("{} value", key),
+            String::from("note"),
+        );
+        cred.otp = Some(Secret::from("otp"));
+        cred.username = Some(Secret::from("user"));
+        cred.url = Some(String::from("https://example.com"));
+        cred.modified = Some(1_700_000_000);
+        cred.name = Some(String::from("sealed name"));
+        cred
+    }
+
+    fn keys(storage: &dyn StorageBackend) -> Vec<String> {
+        let mut keys: Vec<String> = storage
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|cred| cred.key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Same checks for every backend
+    fn round_trip(storage: &mut dyn StorageBackend) {
+        let secret = Credentials::new(String::from("secret_key"), String::from("s"), String::new());
+        storage.put(&secret).unwrap();
+        let row = full_row("a");
+        storage.put(&row).unwrap();
+        assert_eq!(fields(&storage.get("a").unwrap().unwrap()), fields(&row));
+        assert_eq!(
+            storage.get("secret_key").unwrap().unwrap().value.expose(),
+            "s"
+        );
+        assert!(storage.get("missing").unwrap().is_none());
+
+        let plain = Credentials::new(String::from("b"), String::from("b value"), String::new());
+        storage.put(&plain).unwrap();
+        let stored = storage.get("b").unwrap().unwrap();
+        assert_eq!(stored.info, None);
+        assert_eq!(stored.username, None);
+        assert!(stored.modified.unwrap() >= 1_700_000_000);
+        assert_eq!(keys(storage), ["a", "b"]);
+
+        let mut changed = full_row("a");
+        changed.value = Secret::from("new value");
+        changed.url = None;
+        storage.put(&changed).unwrap();
+        assert_eq!(
+            fields(&storage.get("a").unwrap().unwrap()),
+            fields(&changed)
+        );
+        assert_eq!(keys(storage), ["a", "b"]);
+
+        assert!(storage.delete("a").unwrap());
+        assert!(!storage.delete("a").unwrap());
+        assert!(storage.get("a").unwrap().is_none());
+        assert_eq!(keys(storage), ["b"]);
+        let tombstones = storage.tombstones().unwrap();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].0, "a");
+
+        storage.put(&row).unwrap();
+        assert!(storage.tombstones().unwrap().is_empty());
+        storage.record_tombstone("c", 42).unwrap();
+        assert_eq!(storage.tombstones().unwrap(), [(String::from("c"), 42)]);
+    }
+
+    /// Failed transaction leaves nothing behind, a successful one keeps
+    /// everything
+    fn rollback(storage: &mut dyn StorageBackend) {
+        storage.put(&full_row("kept")).unwrap();
+        let failed = storage.transaction(&mut |storage| {
+            storage.put(&full_row("added"))?;
+            storage.delete("kept")?;
+            storage.record_tombstone("other", 1)?;
+            Err(String::from("stop"))
+        });
+        assert_eq!(failed, Err(String::from("stop")));
+        assert_eq!(keys(storage), ["kept"]);
+        assert!(storage.tombstones().unwrap().is_empty());
+
+        storage
+            .transaction(&mut |storage| {
+                storage.put(&full_row("added"))?;
+                storage.delete("kept").map(|_| ())
+            })
+            .unwrap();
+        assert_eq!(keys(storage), ["added"]);
+        assert_eq!(storage.tombstones().unwrap()[0].0, "kept");
+    }
+
+    fn unlocked_sealed(path: &str) -> SealedBackend {
+        let mut sealed = SealedBackend::open(path).unwrap();
+        sealed.unlock("vault key", "vault password").unwrap();
+        sealed
+    }
+
+    #[test]
+    fn memory_backend_round_trips() {
+        round_trip(&mut MemoryBackend::new());
+        rollback(&mut MemoryBackend::new());
+    }
+
+    #[test]
+    fn sqlite_backend_round_trips() {
+        let dir = test_dir("storage");
+        round_trip(&mut SqliteBackend::open(&format!("{}/a.db", dir)).unwrap());
+        rollback(&mut SqliteBackend::open(&format!("{}/b.db", dir)).unwrap());
+
+        let reopened = SqliteBackend::open(&format!("{}/a.db", dir)).unwrap();
+        assert_eq!(keys(&reopened), ["a", "b"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_backend_keeps_quotes_in_keys() {
+        let dir = test_dir("storage");
+        let mut storage = SqliteBackend::open(&format!("{}/a.db", dir)).unwrap();
+        storage.put(&full_row("other")).unwrap();
+        let key = "it's' OR '1'='1";
+        assert!(storage.get(key).unwrap().is_none());
+        assert!(!storage.delete(key).unwrap());
+        storage.put(&full_row(key)).unwrap();
+        assert_eq!(
+            fields(&storage.get(key).unwrap().unwrap()),
+            fields(&full_row(key))
+        );
+        assert!(storage.delete(key).unwrap());
+        assert_eq!(keys(&storage), ["other"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn json_backend_round_trips() {
+        let dir = test_dir("storage");
+        let path = format!("{}/vault.json", dir);
+        round_trip(&mut JsonFileBackend::open(&path).unwrap());
+        rollback(&mut JsonFileBackend::open(&format!("{}/other.json", dir)).unwrap());
+
+        let reopened = JsonFileBackend::open(&path).unwrap();
+        assert_eq!(keys(&reopened), ["a", "b"]);
+        assert_eq!(
+            fields(&reopened.get("a").unwrap().unwrap()),
+            fields(&full_row("a"))
+        );
+        assert_eq!(reopened.tombstones().unwrap(), [(String::from("c"), 42)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sealed_backend_round_trips() {
+        let dir = test_dir("storage");
+        let path = format!("{}/vault.sealed", dir);
+        round_trip(&mut unlocked_sealed(&path));
+        rollback(&mut unlocked_sealed(&format!("{}/other.sealed", dir)));
+
+        let data = fs::read_to_string(&path).unwrap();
+        assert!(!data.contains("value") && !data.contains("example.com"));
+        let mut locked = SealedBackend::open(&path).unwrap();
+        assert_eq!(
+            locked.get("secret_key").unwrap().unwrap().value.expose(),
+            "s"
+        );
+        assert!(locked.list().is_err());
+        assert!(locked.put(&full_row("d")).is_err());
+        assert!(locked.unlock("vault key", "wrong password").is_err());
+
+        let reopened = unlocked_sealed(&path);
+        assert_eq!(keys(&reopened), ["a", "b"]);
+        assert_eq!(
+            fields(&reopened.get("a").unwrap().unwrap()),
+            fields(&full_row("a"))
+        );
+        assert_eq!(reopened.tombstones().unwrap(), [(String::from("c"), 42)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Vault file with base64 ciphertext line changed by `change`
+    fn change_ciphertext(path: &str, change: impl Fn(&mut Vec<u8>)) {
+        let data = fs::read_to_string(path).unwrap();
+        let mut lines: Vec<String> = data.lines().map(String::from).collect();
+        let mut encrypted = base64::decode(&lines[3]).unwrap();
+        change(&mut encrypted);
+        lines[3] = base64::encode(encrypted);
+        fs::write(path, lines.join("\n")).unwrap();
+    }
+
+    #[test]
+    fn sealed_backend_detects_tampering() {
+        let dir = test_dir("storage");
+        let path = format!("{}/vault.sealed", dir);
+        let mut sealed = unlocked_sealed(&path);
+        sealed.put(&full_row("a")).unwrap();
+        let first = fs::read_to_string(&path).unwrap();
+        sealed.put(&full_row("a")).unwrap();
+        let second = fs::read_to_string(&path).unwrap();
+        assert!(first.starts_with("GIVME-SEALED-2\n"));
+        assert_ne!(first.lines().nth(3), second.lines().nth(3));
+
+        let tampered = Err(String::from("Vault tampered or wrong key"));
+        let mut locked = SealedBackend::open(&path).unwrap();
+        assert_eq!(locked.unlock("vault key", "wrong password"), tampered);
+
+        change_ciphertext(&path, |encrypted| encrypted[40] ^= 1);
+        let mut locked = SealedBackend::open(&path).unwrap();
+        assert_eq!(locked.unlock("vault key", "vault password"), tampered);
+
+        fs::write(&path, &second).unwrap();
+        change_ciphertext(&path, |encrypted| encrypted.truncate(encrypted.len() - 1));
+        let mut locked = SealedBackend::open(&path).unwrap();
+        assert_eq!(locked.unlock("vault key", "vault password"), tampered);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sealed_backend_upgrades_legacy_vaults() {
+        let dir = test_dir("storage");
+        let path = format!("{}/vault.sealed", dir);
+        let mut vault_keys = GivMe::new();
+        vault_keys.key = Some(Secret::from("vault key"));
+        vault_keys.password = Some(Secret::from("vault password"));
+        let row = credentials_to_json(&full_row("a"));
+        let payload = format!(r#"{{"rows":[{}],"tombstones":{{}}}}"#, to_json(&row));
+        let salt = [3u8; SEALED_SALT_SIZE];
+        let encrypted = crate::encryption::encrypt(
+            &payload,
+            &mut SealedBackend::legacy_sealing_keys(&vault_keys, &salt),
+        )
+        .unwrap();
+        let legacy = format!(
+            "{}\nsecret\n{}\n{}\n",
+            SEALED_VAULT_HEADER_V1,
+            base64::encode(salt),
+            base64::encode(encrypted)
+        );
+        fs::write(&path, &legacy).unwrap();
+
+        /* Written back untouched while locked */
+        let mut locked = SealedBackend::open(&path).unwrap();
+        locked
+            .put(&Credentials::new(
+                String::from("secret_key"),
+                String::from("secret"),
+                String::new(),
+            ))
+            .unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("GIVME-SEALED-1\n"));
+
+        let mut sealed = unlocked_sealed(&path);
+        assert_eq!(
+            fields(&sealed.get("a").unwrap().unwrap()),
+            fields(&full_row("a"))
+        );
+        sealed.put(&full_row("b")).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("GIVME-SEALED-2\n"));
+        assert_eq!(keys(&unlocked_sealed(&path)), ["a", "b"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_storage_shares_connection_of_vault() {
+        let _home = lock_test_home();
+        let mut handle = test_vault("sqlite", None);
+        assert_eq!(Rc::strong_count(handle.sql_con.as_ref().unwrap()), 2);
+        storage_mut(&mut handle).put(&full_row("shared")).unwrap();
+        assert_eq!(get_from_sql("shared", &handle).len(), 1);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::backup::auto_backup;
+use crate::cred::{row_name, set_row_name, upgrade_entry_names};
+use crate::encryption::name_index;
+use crate::models::{
+    credentials::Credentials, enums::SyncPolicy, givme::GivMe, sync_summary::SyncSummary,
+};
+use crate::sql::upgrade_sql;
+use crate::storage::{require_sqlite, storage, storage_mut, SqliteBackend};
+use crate::utils::format_timestamp;
+use sqlite::{Connection, State};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Parse name given to `--policy`
+pub fn parse_sync_policy(name: &str) -> Result<SyncPolicy, String> {
+    match name {
+        "ask" => Ok(SyncPolicy::Ask),
+        "newest" => Ok(SyncPolicy::Newest),
+        "keep-both" => Ok(SyncPolicy::KeepBoth),
+        _ => Err(format!("Unknown sync policy '{}'", name)),
+    }
+}
+
+/// Rows saved before modification time was tracked count as oldest
+fn modified_of(cred: &Credentials) -> u64 {
+    cred.modified.unwrap_or_default()
+}
+
+/// Entries are stored encrypted with same keys in both vaults, so
+/// equal ciphertext means equal content
+fn same_content(a: &Credentials, b: &Credentials) -> bool {
+    a.value == b.value
+        && a.info == b.info
+        && a.otp == b.otp
+        && a.username == b.username
+        && a.url == b.url
+}
+
+/// Save an already encrypted row, replacing one with the same key.
+/// Modification time of `cred` is kept.
+fn put_row(cred: &Credentials, handle: &mut GivMe) -> Result<(), String> {
+    let mut cred = cred.clone();
+    cred.modified = Some(modified_of(&cred));
+    storage_mut(handle).put(&cred)
+}
+
+/// Delete a row, keeping time of deletion from other vault
+fn delete_row(key: &str, deleted: u64, handle: &mut GivMe) -> Result<(), String> {
+    let storage = storage_mut(handle);
+    storage.delete(key)?;
+    storage.record_tombstone(key, deleted)
+}
+
+/// Decrypted name of an entry, for messages to user
+fn entry_name(cred: &Credentials, handle: &GivMe) -> String {
+    row_name(cred, handle).unwrap_or_else(|_| String::from("<undecryptable name>"))
+}
+
+/// Ask user which version of a conflicting entry to keep
+fn ask_conflict(name: &str, local: &Credentials, other: &Credentials) -> SyncChoice {
+    println!("\n'{}' was changed in both vaults", name);
+    println!(
+        "  [t]his vault, modified {}",
+        format_timestamp(modified_of(local))
+    );
+    println!(
+        "  [o]ther vault, modified {}",
+        format_timestamp(modified_of(other))
+    );
+    println!("  [b]oth, other one is saved under a new name");
+    loop {
+        print!("Keep which? (t/o/b) ");
+        std::io::stdout().flush().unwrap();
+        let mut option = String::new();
+        std::io::stdin().read_line(&mut option).unwrap();
+        match option.trim().to_lowercase().as_str() {
+            "t" => return SyncChoice::Local,
+            "o" => return SyncChoice::Other,
+            "b" => return SyncChoice::Both,
+            _ => {}
+        }
+    }
+}
+
+/// What is kept of an entry changed in both vaults
+enum SyncChoice {
+    Local,
+    Other,
+    Both,
+}
+
+/// Encrypted master key row of a vault. Read directly, as other vault
+/// may not be upgraded to latest layout yet.
+fn stored_secret(handle: &GivMe) -> Option<String> {
+    let mut statement = handle
+        .sql_con
+        .as_ref()?
+        .prepare("SELECT value FROM cred WHERE key = 'secret_key'")
+        .ok()?;
+    match statement.next().ok()? {
+        State::Row => statement.read::<String>(0).ok(),
+        State::Done => None,
+    }
+}
+
+/// Merge vault at `other_path` and the vault behind `handle` both ways.
+///
+/// Both vaults must share the Master Key, `handle` has to be unlocked.
+/// An entry only present on one side is copied to the other, unless the
+/// other side deleted it later than it was last modified. Entries changed
+/// on both sides are settled by `policy`. Both vaults are backed up
+/// automatically before anything is written.
+pub fn sync_vaults(
+    other_path: &str,
+    policy: SyncPolicy,
+    handle: &mut GivMe,
+) -> Result<SyncSummary, String> {
+    require_sqlite("Sync", handle)?;
+    if !Path::new(other_path).is_file() {
+        return Err(format!("'{}' does not exist", other_path));
+    }
+    let mut other = GivMe {
+        key: handle.key.clone(),
+        password: handle.password.clone(),
+        sql_con: Some(Rc::new(
+            Connection::open(other_path).map_err(|err| err.to_string())?,
+        )),
+        os: handle.os,
+        username: handle.username.clone(),
+        storage: None,
+    };
+    if stored_secret(&other).is_none() || stored_secret(&other) != stored_secret(handle) {
+        return Err(String::from(
+            "Other vault does not share Master Key of this vault",
+        ));
+    }
+    /* Other vault is snapshotted as it was, before its layout is upgraded */
+    auto_backup("sync", handle)?;
+    auto_backup("sync-other", &other)?;
+    upgrade_sql(&other);
+    /* Same entry under old and indexed key would be seen as two entries */
+    let con = Rc::clone(other.sql_con.as_ref().unwrap());
+    other.storage = Some(Box::new(SqliteBackend::new(con)));
+    upgrade_entry_names(&mut other)?;
+    merge_vaults(&mut other, policy, handle)
+}
+
+/// Merge entries and tombstones of `other` and `handle` both ways, as
+/// described on `sync_vaults()`. Both must use the same keys, nothing
+/// is checked or backed up here. Works on storage of both, whatever
+/// kind it is.
+pub fn merge_vaults(
+    other: &mut GivMe,
+    policy: SyncPolicy,
+    handle: &mut GivMe,
+) -> Result<SyncSummary, String> {
+    let entries = |handle: &GivMe| -> Result<HashMap<String, Credentials>, String> {
+        Ok(storage(handle)
+            .list()?
+            .into_iter()
+            .map(|cred| (cred.key.clone(), cred))
+            .collect())
+    };
+    let local_entries = entries(handle)?;
+    let other_entries = entries(other)?;
+    let local_tombstones: HashMap<String, u64> =
+        storage(handle).tombstones()?.into_iter().collect();
+    let other_tombstones: HashMap<String, u64> = storage(other).tombstones()?.into_iter().collect();
+    let keys: BTreeSet<&String> = local_entries
+        .keys()
+        .chain(other_entries.keys())
+        .chain(local_tombstones.keys())
+        .chain(other_tombstones.keys())
+        .collect();
+
+    let mut summary = SyncSummary::default();
+    for key in keys {
+        match (local_entries.get(key), other_entries.get(key)) {
+            (Some(local), Some(other_cred)) => {
+                if same_content(local, other_cred) {
+                    continue;
+                }
+                let name = entry_name(local, handle);
+                let choice = match policy {
+                    SyncPolicy::Ask => ask_conflict(&name, local, other_cred),
+                    SyncPolicy::Newest if modified_of(local) > modified_of(other_cred) => {
+                        SyncChoice::Local
+                    }
+                    SyncPolicy::Newest if modified_of(local) < modified_of(other_cred) => {
+                        SyncChoice::Other
+                    }
+                    /* Same time of change, nothing tells which one is right */
+                    SyncPolicy::Newest | SyncPolicy::KeepBoth => SyncChoice::Both,
+                };
+                match choice {
+                    SyncChoice::Local => {
+                        put_row(local, other)?;
+                        summary.pushed += 1;
+                        summary
+                            .conflicts
+                            .push((name, String::from("kept this vault's version")));
+                    }
+                    SyncChoice::Other => {
+                        put_row(other_cred, handle)?;
+                        summary.pulled += 1;
+                        summary
+                            .conflicts
+                            .push((name, String::from("kept other vault's version")));
+                    }
+                    SyncChoice::Both => {
+                        let mut suffix = 1;
+                        let new_name = loop {
+                            let new_name = if suffix == 1 {
+                                format!("{} (other vault)", name)
+                            } else {
+                                format!("{} (other vault {})", name, suffix)
+                            };
+                            let new_key = name_index(&new_name, handle)?;
+                            if !local_entries.contains_key(&new_key)
+                                && !other_entries.contains_key(&new_key)
+                                && storage(handle).get(&new_key)?.is_none()
+                            {
+                                break new_name;
+                            }
+                            suffix += 1;
+                        };
+                        let mut renamed = other_cred.clone();
+                        set_row_name(&mut renamed, &new_name, handle)?;
+                        put_row(&renamed, handle)?;
+                        put_row(&renamed, other)?;
+                        put_row(local, other)?;
+                        summary.pulled += 1;
+                        summary.pushed += 2;
+                        summary.conflicts.push((
+                            name,
+                            format!("other vault's version saved as '{}'", new_name),
+                        ));
+                    }
+                }
+            }
+            (Some(local), None) => match other_tombstones.get(key) {
+                Some(deleted) if *deleted >= modified_of(local) => {
+                    delete_row(key, *deleted, handle)?;
+                    summary.deleted_here += 1;
+                }
+                _ => {
+                    put_row(local, other)?;
+                    summary.pushed += 1;
+                }
+            },
+            (None, Some(other_cred)) => match local_tombstones.get(key) {
+                Some(deleted) if *deleted >= modified_of(other_cred) => {
+                    delete_row(key, *deleted, other)?;
+                    summary.deleted_there += 1;
+                }
+                _ => {
+                    put_row(other_cred, handle)?;
+                    summary.pulled += 1;
+                }
+            },
+            (None, None) => {
+                /* Only tombstones left, keep the latest delete on both sides */
+                let deleted = local_tombstones
+                    .get(key)
+                    .max(other_tombstones.get(key))
+                    .copied()
+                    .unwrap_or_default();
+                if local_tombstones.get(key) != Some(&deleted) {
+                    storage_mut(handle).record_tombstone(key, deleted)?;
+                }
+                if other_tombstones.get(key) != Some(&deleted) {
+                    storage_mut(other).record_tombstone(key, deleted)?;
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Prints outcome of `sync_vaults()` to user
+pub fn show_sync_summary(summary: &SyncSummary) {
+    for (name, resolution) in &summary.conflicts {
+        println!("Conflict on '{}': {}", name, resolution);
+    }
+    println!(
+        "\nPulled: {}  Pushed: {}  Deleted here: {}  Deleted there: {}  Conflicts: {}",
+        summary.pulled,
+        summary.pushed,
+        summary.deleted_here,
+        summary.deleted_there,
+        summary.conflicts.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::backup_dir;
+    use crate::cred::{
+        delete_credentails, give_all_credentials, give_credentials, save_credentials,
+    };
+    use crate::encryption::{encrypt, encrypt_legacy};
+    use crate::os::vault_path;
+    use crate::utils::{lock_test_home, test_vault};
+
+    fn names(handle: &mut GivMe) -> Vec<String> {
+        let mut names: Vec<String> = give_all_credentials(handle)
+            .into_iter()
+            .map(|cred| cred.unwrap().key)
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn save(name: &str, value: &str, handle: &mut GivMe) {
+        let cred = Credentials::new(name.to_string(), value.to_string(), String::new());
+        save_credentials(cred, handle).unwrap();
+    }
+
+    /// Vault with `names` and its path, sharing Master Key of `shared_with`
+    fn vault_with(names: &[&str], shared_with: Option<&GivMe>) -> (GivMe, String) {
+        let mut handle = test_vault("sqlite", shared_with);
+        for name in names {
+            save(name, "value", &mut handle);
+        }
+        let path = vault_path(&handle).unwrap();
+        (handle, path)
+    }
+
+    #[test]
+    fn vaults_converge_and_deletions_propagate() {
+        let _home = lock_test_home();
+        let (mut other, other_path) = vault_with(&["both", "only-other"], None);
+        let (mut handle, _) = vault_with(&["only-here"], Some(&other));
+        save("both", "changed", &mut handle);
+
+        /* Same name saved on both sides is a conflict, both are kept */
+        let summary = sync_vaults(&other_path, SyncPolicy::KeepBoth, &mut handle).unwrap();
+        assert_eq!((summary.pulled, summary.pushed), (2, 3));
+        assert_eq!(summary.conflicts.len(), 1);
+        let all = ["both", "both (other vault)", "only-here", "only-other"];
+        assert_eq!(names(&mut handle), all);
+        assert_eq!(names(&mut other), all);
+        let value = |name: &str, handle: &mut GivMe| {
+            let cred = give_credentials(name.to_string(), handle).unwrap();
+            cred.value.expose().to_string()
+        };
+        assert_eq!(value("both", &mut other), "changed");
+        assert_eq!(value("both (other vault)", &mut handle), "value");
+
+        assert_eq!(
+            delete_credentails(String::from("only-other"), &mut other),
+            Ok(true)
+        );
+        assert_eq!(
+            delete_credentails(String::from("only-here"), &mut handle),
+            Ok(true)
+        );
+        let summary = sync_vaults(&other_path, SyncPolicy::Newest, &mut handle).unwrap();
+        assert_eq!((summary.deleted_here, summary.deleted_there), (1, 1));
+        assert_eq!(names(&mut handle), ["both", "both (other vault)"]);
+        assert_eq!(names(&mut other), ["both", "both (other vault)"]);
+
+        /* Nothing left to do once converged */
+        let summary = sync_vaults(&other_path, SyncPolicy::Newest, &mut handle).unwrap();
+        assert_eq!(
+            (
+                summary.pulled,
+                summary.pushed,
+                summary.deleted_here,
+                summary.deleted_there
+            ),
+            (0, 0, 0, 0)
+        );
+        assert!(summary.conflicts.is_empty());
+    }
+
+    #[test]
+    fn other_vault_is_backed_up_before_upgrade() {
+        let _home = lock_test_home();
+        let (mut other, other_path) = vault_with(&[], None);
+        let (mut handle, _) = vault_with(&[], Some(&other));
+        /* Row the way versions before the name index saved it */
+        let legacy = Credentials::new(
+            base64::encode(encrypt_legacy("github", &mut other).unwrap()),
+            base64::encode(encrypt("legacy value", &mut other).unwrap()),
+            String::new(),
+        );
+        storage_mut(&mut other).put(&legacy).unwrap();
+
+        sync_vaults(&other_path, SyncPolicy::Newest, &mut handle).unwrap();
+        assert_eq!(names(&mut handle), ["github"]);
+        assert!(storage(&other).get(&legacy.key).unwrap().is_none());
+
+        let backup = std::fs::read_dir(backup_dir(&handle).unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_string_lossy().ends_with("-sync-other.db"))
+            .unwrap();
+        let backup = Connection::open(backup).unwrap();
+        let mut statement = backup
+            .prepare("SELECT count(*) FROM cred WHERE key = ?")
+            .unwrap();
+        statement.bind(1, legacy.key.as_str()).unwrap();
+        statement.next().unwrap();
+        assert_eq!(statement.read::<i64>(0).unwrap(), 1);
+    }
+}
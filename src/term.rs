@@ -0,0 +1,175 @@
+use crate::models::enums::Key;
+use std::io::{self, IsTerminal, Read};
+use std::process::{Command, Stdio};
+
+/// Terminal reading key by key without echo, restored when dropped
+pub struct RawTerminal {
+    saved: String,
+}
+
+impl RawTerminal {
+    /// `None` when stdin is not a terminal or it can't be switched
+    pub fn enable() -> Option<RawTerminal> {
+        if !io::stdin().is_terminal() {
+            return None;
+        }
+        let saved = stty(&["-g"])?.trim().to_string();
+        /* A read gives up after a second so callers can check idle time */
+        stty(&["-icanon", "-echo", "-isig", "min", "0", "time", "10"])?;
+        Some(RawTerminal { saved })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = stty(&[self.saved.as_str()]);
+    }
+}
+
+/// Rows and columns of terminal, 24x80 when it can't be told
+pub fn terminal_size() -> (usize, usize) {
+    stty(&["size"])
+        .and_then(|size| {
+            let mut numbers = size.split_whitespace().map(|number| number.parse().ok());
+            Some((numbers.next()??, numbers.next()??))
+        })
+        .filter(|(rows, cols)| *rows > 0 && *cols > 0)
+        .unwrap_or((24, 80))
+}
+
+/// Next key pressed on a `RawTerminal`, `Key::Timeout` if there was
+/// none for a second
+pub fn read_key() -> Key {
+    key_from(&mut io::stdin())
+}
+
+/// Next key sent by a terminal on `input`
+fn key_from(input: &mut impl Read) -> Key {
+    let byte = match read_byte(input) {
+        Ok(Some(byte)) => byte,
+        Ok(None) => return Key::Timeout,
+        Err(_) => return Key::Closed,
+    };
+    match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        8 | 127 => Key::Backspace,
+        27 => read_escape_sequence(input),
+        byte if byte < 0x20 => Key::Ctrl((byte + b'a' - 1) as char),
+        byte => {
+            let length = match byte.leading_ones() {
+                2 => 2,
+                3 => 3,
+                4 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            while bytes.len() < length {
+                match read_byte(input) {
+                    Ok(Some(byte)) => bytes.push(byte),
+                    _ => return Key::Other,
+                }
+            }
+            std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|text| text.chars().next())
+                .map_or(Key::Other, Key::Char)
+        }
+    }
+}
+
+/// Key sent as an escape sequence, or `Escape` itself
+fn read_escape_sequence(input: &mut impl Read) -> Key {
+    match read_byte(input) {
+        Ok(Some(b'[')) | Ok(Some(b'O')) => {}
+        _ => return Key::Escape,
+    }
+    let mut sequence = Vec::new();
+    while let Ok(Some(byte)) = read_byte(input) {
+        sequence.push(byte);
+        if (0x40..=0x7e).contains(&byte) {
+            break;
+        }
+    }
+    match sequence.as_slice() {
+        b"A" => Key::Up,
+        b"B" => Key::Down,
+        b"H" | b"1~" => Key::Home,
+        b"F" | b"4~" => Key::End,
+        b"5~" => Key::PageUp,
+        b"6~" => Key::PageDown,
+        _ => Key::Other,
+    }
+}
+
+/// One byte of input, `None` on timeout
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Run `stty` on terminal of stdin and return what it printed
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every key in `input`, until it runs out
+    fn keys(mut input: &[u8]) -> Vec<Key> {
+        let mut keys = Vec::new();
+        while !input.is_empty() {
+            keys.push(key_from(&mut input));
+        }
+        keys
+    }
+
+    #[test]
+    fn reads_keys_from_terminal_input() {
+        assert_eq!(
+            keys("aé€😀\r\t\x7f\x08\x03\x04".as_bytes()),
+            [
+                Key::Char('a'),
+                Key::Char('é'),
+                Key::Char('€'),
+                Key::Char('😀'),
+                Key::Enter,
+                Key::Tab,
+                Key::Backspace,
+                Key::Backspace,
+                Key::Ctrl('c'),
+                Key::Ctrl('d'),
+            ]
+        );
+        assert_eq!(
+            keys(b"\x1b[A\x1b[B\x1bOH\x1b[4~\x1b[5~\x1b[6~\x1b[1;5C"),
+            [
+                Key::Up,
+                Key::Down,
+                Key::Home,
+                Key::End,
+                Key::PageUp,
+                Key::PageDown,
+                Key::Other,
+            ]
+        );
+        /* Escape alone, broken UTF-8 and nothing at all */
+        assert_eq!(keys(b"\x1bq"), [Key::Escape]);
+        assert_eq!(keys(b"\xc3"), [Key::Other]);
+        assert_eq!(key_from(&mut &b""[..]), Key::Timeout);
+    }
+}
@@ -0,0 +1,519 @@
+use crate::backup::auto_backup;
+use crate::clipboard::copy_to_clipboard;
+use crate::cred::{delete_credentails, give_all_credentials, save_credentials};
+use crate::io::lock_vault;
+use crate::models::credentials::Credentials;
+use crate::models::enums::{Key, TuiAction, TuiMode};
+use crate::models::givme::GivMe;
+use crate::models::secret::Secret;
+use crate::shell::edit_entry;
+use crate::term::{read_key, terminal_size, RawTerminal};
+use crate::utils::{generate_password, GENERATED_PASSWORD_LENGTH, MASKED_VALUE};
+use std::io::{self, Write};
+
+/// Keys shown at bottom of screen while browsing
+const TUI_HELP: &str =
+    "Up/Down move  / filter  Enter show  c copy  e edit  d delete  g generate  q quit";
+
+/// State of `givme tui` between key presses
+struct Tui {
+    creds: Vec<Credentials>,
+    /// Indices of `creds` matching filter
+    shown: Vec<usize>,
+    filter: String,
+    /// Position in `shown`
+    selected: usize,
+    /// First row of list on screen
+    top: usize,
+    revealed: bool,
+    mode: TuiMode,
+    status: String,
+    /// Rows and columns of terminal, checked again when no key comes
+    size: (usize, usize),
+    /// Screen as last drawn, `None` when it has to be cleared first
+    drawn: Option<Secret>,
+}
+
+/// Browse vault behind `handle` full screen. Vault has to be unlocked
+/// already, keys are wiped when TUI ends.
+pub fn run_tui(handle: &mut GivMe) -> Result<(), String> {
+    let mut tui = Tui::new(terminal_size());
+    tui.load(handle, None);
+    loop {
+        let edit = {
+            let _raw = RawTerminal::enable().ok_or_else(|| String::from("TUI needs a terminal"))?;
+            let _screen = FullScreen::enter();
+            tui.browse(handle)
+        };
+        /* New value is asked on the normal screen */
+        let name = match edit {
+            Some(name) => name,
+            None => break,
+        };
+        let result = edit_entry(&name, handle);
+        tui.load(handle, Some(&name));
+        tui.status = match result {
+            Ok(_) => format!("Saved '{}'", name),
+            Err(err) => err,
+        };
+    }
+    lock_vault(handle);
+    Ok(())
+}
+
+impl Tui {
+    fn new(size: (usize, usize)) -> Tui {
+        Tui {
+            creds: Vec::new(),
+            shown: Vec::new(),
+            filter: String::new(),
+            selected: 0,
+            top: 0,
+            revealed: false,
+            mode: TuiMode::Browse,
+            status: String::new(),
+            size,
+            drawn: None,
+        }
+    }
+
+    /// Handle keys until user quits, or returns name of an entry to edit
+    fn browse(&mut self, handle: &mut GivMe) -> Option<String> {
+        self.size = terminal_size();
+        self.drawn = None;
+        loop {
+            self.draw();
+            let key = match read_key() {
+                Key::Timeout => {
+                    let size = terminal_size();
+                    if size != self.size {
+                        self.size = size;
+                        self.drawn = None;
+                    }
+                    continue;
+                }
+                Key::Closed => return None,
+                key => key,
+            };
+            match self.press(key) {
+                TuiAction::Quit => return None,
+                TuiAction::Edit(name) => return Some(name),
+                TuiAction::Copy => self.copy_selected(handle),
+                TuiAction::Delete => self.delete_selected(handle),
+                TuiAction::Generate(name) => self.generate_entry(&name, handle),
+                TuiAction::Nothing => {}
+            }
+        }
+    }
+
+    /// Change state for `key`, returns what has to be done to the vault
+    fn press(&mut self, key: Key) -> TuiAction {
+        match self.mode.clone() {
+            TuiMode::Browse => {
+                self.status.clear();
+                let page = self.size.0.saturating_sub(4).max(1);
+                match key {
+                    Key::Char('q') | Key::Escape | Key::Ctrl('c') => return TuiAction::Quit,
+                    Key::Up | Key::Char('k') => self.select(self.selected.saturating_sub(1)),
+                    Key::Down | Key::Char('j') => self.select(self.selected + 1),
+                    Key::PageUp => self.select(self.selected.saturating_sub(page)),
+                    Key::PageDown => self.select(self.selected + page),
+                    Key::Home => self.select(0),
+                    Key::End => self.select(usize::MAX),
+                    Key::Char('/') => self.mode = TuiMode::Filter,
+                    Key::Enter | Key::Char(' ') => self.revealed = !self.revealed,
+                    Key::Char('c') => return TuiAction::Copy,
+                    Key::Char('e') => {
+                        if let Some(cred) = self.current() {
+                            return TuiAction::Edit(cred.key.clone());
+                        }
+                    }
+                    Key::Char('d') if self.current().is_some() => self.mode = TuiMode::Delete,
+                    Key::Char('g') => self.mode = TuiMode::Generate(String::new()),
+                    _ => {}
+                }
+            }
+            TuiMode::Filter => match key {
+                Key::Enter => self.mode = TuiMode::Browse,
+                Key::Escape => {
+                    self.filter.clear();
+                    self.apply_filter();
+                    self.mode = TuiMode::Browse;
+                }
+                Key::Backspace => {
+                    self.filter.pop();
+                    self.apply_filter();
+                }
+                Key::Char(c) => {
+                    self.filter.push(c);
+                    self.apply_filter();
+                }
+                Key::Up => self.select(self.selected.saturating_sub(1)),
+                Key::Down => self.select(self.selected + 1),
+                _ => {}
+            },
+            TuiMode::Delete => {
+                self.mode = TuiMode::Browse;
+                if key == Key::Char('y') {
+                    return TuiAction::Delete;
+                }
+                self.status = String::from("Not deleted");
+            }
+            TuiMode::Generate(mut name) => match key {
+                Key::Enter => {
+                    self.mode = TuiMode::Browse;
+                    return TuiAction::Generate(name.trim().to_string());
+                }
+                Key::Escape => self.mode = TuiMode::Browse,
+                Key::Backspace => {
+                    name.pop();
+                    self.mode = TuiMode::Generate(name);
+                }
+                Key::Char(c) => {
+                    name.push(c);
+                    self.mode = TuiMode::Generate(name);
+                }
+                _ => {}
+            },
+        }
+        TuiAction::Nothing
+    }
+
+    /// Read entries again and select `select` if it is shown
+    fn load(&mut self, handle: &mut GivMe, select: Option<&str>) {
+        let creds = give_all_credentials(handle);
+        let failed = creds.iter().filter(|cred| cred.is_err()).count();
+        self.creds = creds.into_iter().flatten().collect();
+        self.status = if failed > 0 {
+            format!("{} entries failed to decrypt, see `givme check`", failed)
+        } else {
+            String::new()
+        };
+        self.apply_filter();
+        if let Some(name) = select {
+            if let Some(position) = self
+                .shown
+                .iter()
+                .position(|index| self.creds[*index].key == name)
+            {
+                self.select(position);
+            }
+        }
+    }
+
+    fn apply_filter(&mut self) {
+        self.shown = matching(&self.creds, &self.filter);
+        self.revealed = false;
+        self.select(self.selected);
+    }
+
+    /// Move selection, value is hidden again when it changes
+    fn select(&mut self, selected: usize) {
+        let selected = selected.min(self.shown.len().saturating_sub(1));
+        if selected != self.selected {
+            self.revealed = false;
+        }
+        self.selected = selected;
+    }
+
+    fn current(&self) -> Option<&Credentials> {
+        self.shown
+            .get(self.selected)
+            .map(|index| &self.creds[*index])
+    }
+
+    fn copy_selected(&mut self, handle: &GivMe) {
+        self.status = match self.current() {
+            Some(cred) => match copy_to_clipboard(cred.value.expose(), handle) {
+                Ok(0) => format!("Copied value of '{}'", cred.key),
+                Ok(seconds) => format!(
+                    "Copied value of '{}', cleared in {} seconds",
+                    cred.key, seconds
+                ),
+                Err(err) => err,
+            },
+            None => String::from("Nothing to copy"),
+        };
+    }
+
+    fn delete_selected(&mut self, handle: &mut GivMe) {
+        let name = match self.current() {
+            Some(cred) => cred.key.clone(),
+            None => return,
+        };
+        let result =
+            auto_backup("delete", handle).and_then(|_| delete_credentails(name.clone(), handle));
+        self.load(handle, None);
+        self.status = match result {
+            Ok(true) => format!("'{}' deleted", name),
+            Ok(false) => format!("'{}' not found!", name),
+            Err(err) => err,
+        };
+    }
+
+    fn generate_entry(&mut self, name: &str, handle: &mut GivMe) {
+        if name.is_empty() {
+            self.status = String::from("Entry needs a name");
+            return;
+        }
+        let password = generate_password(GENERATED_PASSWORD_LENGTH);
+        let cred = Credentials::new(
+            name.to_string(),
+            password.expose().to_string(),
+            String::new(),
+        );
+        let result = save_credentials(cred, handle);
+        self.filter.clear();
+        self.load(handle, Some(name));
+        self.status = match result {
+            Ok(_) => format!("Saved '{}' with a generated password, c copies it", name),
+            Err(err) => err,
+        };
+    }
+
+    /// Lines of detail pane for selected entry
+    fn details(&self) -> Vec<String> {
+        let cred = match self.current() {
+            Some(cred) => cred,
+            None if self.creds.is_empty() => return vec![String::from("Vault is empty")],
+            None => return vec![String::from("No entry matches filter")],
+        };
+        let value = if self.revealed {
+            cred.value.expose()
+        } else {
+            MASKED_VALUE
+        };
+        let mut lines = vec![
+            format!("Name:     {}", cred.key),
+            format!("Value:    {}", value),
+        ];
+        if let Some(username) = &cred.username {
+            lines.push(format!("Username: {}", username.expose()));
+        }
+        if let Some(url) = &cred.url {
+            lines.push(format!("URL:      {}", url));
+        }
+        if let Some(info) = &cred.info {
+            lines.push(format!("Note:     {}", info.expose()));
+        }
+        if cred.otp.is_some() {
+            lines.push(format!("2FA:      `givme otp {}`", cred.key));
+        }
+        lines
+    }
+
+    /// Draw screen unless it looks same as last time
+    fn draw(&mut self) {
+        let screen = self.render();
+        match &self.drawn {
+            Some(drawn) if *drawn == screen => return,
+            Some(_) => print!("{}", screen.expose()),
+            None => print!("\x1b[2J{}", screen.expose()),
+        }
+        io::stdout().flush().unwrap();
+        self.drawn = Some(screen);
+    }
+
+    /// Screen for current state. Lines are padded or cleared to their
+    /// end, so whole screen is only cleared after terminal changed size.
+    fn render(&mut self) -> Secret {
+        let (rows, cols) = self.size;
+        let list_height = rows.saturating_sub(4).max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + list_height {
+            self.top = self.selected + 1 - list_height;
+        }
+        let list_width = (cols / 3).clamp(16, 40).min(cols);
+        let detail_width = cols.saturating_sub(list_width + 2);
+
+        let filter = match self.mode {
+            TuiMode::Filter => format!("{}_", self.filter),
+            _ if self.filter.is_empty() => String::from("press / to filter"),
+            _ => self.filter.clone(),
+        };
+        let mut screen = format!(
+            "\x1b[H{}\x1b[K\x1b[2;1H{}",
+            fit(
+                &format!(
+                    " givme  {} of {} entries  Filter: {}",
+                    self.shown.len(),
+                    self.creds.len(),
+                    filter
+                ),
+                cols
+            ),
+            "-".repeat(cols)
+        );
+        let details = self.details();
+        for row in 0..list_height {
+            screen += &format!("\x1b[{};1H", row + 3);
+            let name = self
+                .shown
+                .get(self.top + row)
+                .map(|index| fit(&self.creds[*index].key, list_width));
+            screen += &match name {
+                Some(name) if self.top + row == self.selected => {
+                    format!("\x1b[7m{:<width$}\x1b[0m", name, width = list_width)
+                }
+                name => format!("{:<width$}", name.unwrap_or_default(), width = list_width),
+            };
+            screen += &format!(
+                "\x1b[{};{}H| {}\x1b[K",
+                row + 3,
+                list_width + 1,
+                fit(
+                    details.get(row).map(String::as_str).unwrap_or_default(),
+                    detail_width
+                )
+            );
+        }
+        let status = match &self.mode {
+            TuiMode::Delete => format!("Delete '{}'? y/n", self.current().unwrap().key),
+            TuiMode::Generate(name) => format!("Name of new entry: {}_", name),
+            _ if !self.status.is_empty() => self.status.clone(),
+            _ => String::from(TUI_HELP),
+        };
+        screen += &format!(
+            "\x1b[{};1H{}\x1b[{};1H{}\x1b[K",
+            rows - 1,
+            "-".repeat(cols),
+            rows,
+            fit(&status, cols)
+        );
+        /* Screen may hold a revealed value */
+        Secret::from(screen)
+    }
+}
+
+/// Indices of `creds` with `filter` in their name, ignoring case
+fn matching(creds: &[Credentials], filter: &str) -> Vec<usize> {
+    let filter = filter.to_lowercase();
+    (0..creds.len())
+        .filter(|index| creds[*index].key.to_lowercase().contains(&filter))
+        .collect()
+}
+
+/// `text` cut to `width` characters, control characters replaced so
+/// entry names can't move the cursor
+fn fit(text: &str, width: usize) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .take(width)
+        .collect()
+}
+
+/// Alternate screen with hidden cursor, left when dropped
+struct FullScreen;
+
+impl FullScreen {
+    fn enter() -> FullScreen {
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush().unwrap();
+        FullScreen
+    }
+}
+
+impl Drop for FullScreen {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        io::stdout().flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_tui(names: &[&str]) -> Tui {
+        let mut tui = Tui::new((24, 80));
+        tui.creds = names
+            .iter()
+            .map(|name| {
+                Credentials::new(name.to_string(), format!("{} value", name), String::new())
+            })
+            .collect();
+        tui.apply_filter();
+        tui
+    }
+
+    fn press(tui: &mut Tui, keys: &str) -> TuiAction {
+        let mut action = TuiAction::Nothing;
+        for c in keys.chars() {
+            action = tui.press(match c {
+                '\n' => Key::Enter,
+                '\x1b' => Key::Escape,
+                '\x08' => Key::Backspace,
+                c => Key::Char(c),
+            });
+        }
+        action
+    }
+
+    fn shown(tui: &Tui) -> Vec<&str> {
+        tui.shown
+            .iter()
+            .map(|index| tui.creds[*index].key.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn filters_names_ignoring_case() {
+        let mut tui = test_tui(&["prod/DB", "prod/web", "staging/db"]);
+        assert_eq!(matching(&tui.creds, "Db"), [0, 2]);
+        assert_eq!(matching(&tui.creds, ""), [0, 1, 2]);
+        assert!(matching(&tui.creds, "nope").is_empty());
+
+        press(&mut tui, "jj");
+        assert_eq!(tui.current().unwrap().key, "staging/db");
+        /* Selection stays within what is shown */
+        press(&mut tui, "/web");
+        assert_eq!(shown(&tui), ["prod/web"]);
+        assert_eq!(tui.current().unwrap().key, "prod/web");
+        press(&mut tui, "\x08\x08\x08x\n");
+        assert_eq!(tui.filter, "x");
+        assert!(tui.current().is_none());
+        press(&mut tui, "/\x1b");
+        assert_eq!(tui.filter, "");
+        assert_eq!(shown(&tui).len(), 3);
+    }
+
+    #[test]
+    fn keys_ask_for_vault_changes() {
+        let mut tui = test_tui(&["a", "b"]);
+        assert_eq!(press(&mut tui, "j"), TuiAction::Nothing);
+        assert_eq!(press(&mut tui, "e"), TuiAction::Edit(String::from("b")));
+        assert_eq!(press(&mut tui, "c"), TuiAction::Copy);
+
+        /* Anything but `y` keeps the entry */
+        assert_eq!(press(&mut tui, "dn"), TuiAction::Nothing);
+        assert_eq!(tui.status, "Not deleted");
+        assert_eq!(press(&mut tui, "dy"), TuiAction::Delete);
+
+        assert_eq!(
+            press(&mut tui, "g new \x08x \n"),
+            TuiAction::Generate(String::from("newx"))
+        );
+        assert_eq!(press(&mut tui, "gname\x1b"), TuiAction::Nothing);
+        assert_eq!(tui.mode, TuiMode::Browse);
+        assert_eq!(press(&mut tui, "q"), TuiAction::Quit);
+
+        let mut empty = test_tui(&[]);
+        assert_eq!(press(&mut empty, "de"), TuiAction::Nothing);
+        assert_eq!(empty.mode, TuiMode::Browse);
+    }
+
+    #[test]
+    fn screen_hides_values_and_control_characters() {
+        assert_eq!(fit("a\x1b[2Jb\nc\u{9b}", 80), "a?[2Jb?c?");
+        assert_eq!(fit("ünïcode", 3), "ünï");
+
+        let mut tui = test_tui(&["evil\x1b[H"]);
+        let screen = tui.render();
+        assert!(screen.expose().contains("evil?[H"));
+        assert!(!screen.expose().contains("evil\x1b"));
+        assert!(!screen.expose().contains("value"));
+        press(&mut tui, "\n");
+        assert!(tui.render().expose().contains("Value:    evil?[H value"));
+    }
+}
@@ -0,0 +1,389 @@
+use crate::cred::{find_row, give_credentials};
+use crate::encryption::encrypt;
+use crate::models::{
+    enums::{OtpAlgorithm, OtpKind},
+    givme::GivMe,
+    otp::Otp,
+    secret::Secret,
+};
+use crate::storage::storage_mut;
+use crate::utils::percent_decode;
+use nettle::hash::{insecure_do_not_use::Sha1, NettleHash, Sha256, Sha512};
+use nettle::mac::{Hmac, Mac};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Decode RFC 4648 base32 as found in 2FA seeds. Case, spaces, dashes
+/// and padding are ignored.
+pub fn base32_decode(input: &str) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in input.chars() {
+        if c == '=' || c == ' ' || c == '-' {
+            continue;
+        }
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&symbol| symbol as char == c.to_ascii_uppercase())
+            .ok_or_else(|| format!("Invalid base32 character '{}'", c))?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if output.is_empty() {
+        return Err(String::from("Empty secret"));
+    }
+    Ok(output)
+}
+
+/// Encode data to unpadded base32, the way `otpauth://` URIs carry secrets
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut output = String::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    output
+}
+
+/// Escape everything except unreserved characters for use in an URI
+fn percent_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Parse one time password given by user. Accepts a full
+/// `otpauth://totp/...` or `otpauth://hotp/...` URI, or just a base32
+/// secret which is treated as TOTP with SHA1, 6 digits and 30 seconds.
+pub fn parse_otp(input: &str, label: &str) -> Result<Otp, String> {
+    let input = input.trim();
+    if !input.to_lowercase().starts_with("otpauth://") {
+        return Ok(Otp {
+            label: label.to_string(),
+            issuer: None,
+            secret: base32_decode(input)?,
+            algorithm: OtpAlgorithm::Sha1,
+            digits: 6,
+            kind: OtpKind::Totp { period: 30 },
+        });
+    }
+
+    let rest = &input["otpauth://".len()..];
+    let (kind, rest) = rest
+        .split_once('/')
+        .ok_or_else(|| String::from("Missing type in otpauth URI"))?;
+    let (uri_label, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+    let mut secret = None;
+    let mut issuer = None;
+    let mut algorithm = OtpAlgorithm::Sha1;
+    let mut digits = 6;
+    let mut period = 30;
+    let mut counter = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        /* Query strings may also write spaces as `+` */
+        let value = percent_decode(&value.replace('+', " "))?;
+        match name.to_lowercase().as_str() {
+            "secret" => secret = Some(base32_decode(&value)?),
+            "issuer" => issuer = Some(value),
+            "algorithm" => {
+                algorithm = match value.to_uppercase().as_str() {
+                    "SHA1" => OtpAlgorithm::Sha1,
+                    "SHA256" => OtpAlgorithm::Sha256,
+                    "SHA512" => OtpAlgorithm::Sha512,
+                    _ => return Err(format!("Unsupported algorithm '{}'", value)),
+                }
+            }
+            "digits" => {
+                digits = value
+                    .parse()
+                    .ok()
+                    .filter(|digits| (6..=10).contains(digits))
+                    .ok_or_else(|| format!("Invalid digits '{}'", value))?
+            }
+            "period" => {
+                period = value
+                    .parse()
+                    .ok()
+                    .filter(|period| *period > 0)
+                    .ok_or_else(|| format!("Invalid period '{}'", value))?
+            }
+            "counter" => {
+                counter = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid counter '{}'", value))?,
+                )
+            }
+            _ => {}
+        }
+    }
+
+    let kind = match kind.to_lowercase().as_str() {
+        "totp" => OtpKind::Totp { period },
+        "hotp" => OtpKind::Hotp {
+            counter: counter.ok_or_else(|| String::from("HOTP URI needs a counter"))?,
+        },
+        _ => return Err(format!("Unsupported otp type '{}'", kind)),
+    };
+    let uri_label = percent_decode(&uri_label.replace('+', " "))?;
+    Ok(Otp {
+        label: if uri_label.is_empty() {
+            label.to_string()
+        } else {
+            uri_label
+        },
+        issuer,
+        secret: secret.ok_or_else(|| String::from("Missing secret in otpauth URI"))?,
+        algorithm,
+        digits,
+        kind,
+    })
+}
+
+/// Build `otpauth://` URI for `Otp`. This is the form stored in database.
+pub fn otp_to_uri(otp: &Otp) -> String {
+    let mut uri = match otp.kind {
+        OtpKind::Totp { period } => format!(
+            "otpauth://totp/{}?secret={}&period={}",
+            percent_encode(&otp.label),
+            base32_encode(&otp.secret),
+            period
+        ),
+        OtpKind::Hotp { counter } => format!(
+            "otpauth://hotp/{}?secret={}&counter={}",
+            percent_encode(&otp.label),
+            base32_encode(&otp.secret),
+            counter
+        ),
+    };
+    uri.push_str(match otp.algorithm {
+        OtpAlgorithm::Sha1 => "&algorithm=SHA1",
+        OtpAlgorithm::Sha256 => "&algorithm=SHA256",
+        OtpAlgorithm::Sha512 => "&algorithm=SHA512",
+    });
+    uri.push_str(&format!("&digits={}", otp.digits));
+    if let Some(issuer) = &otp.issuer {
+        uri.push_str(&format!("&issuer={}", percent_encode(issuer)));
+    }
+    uri
+}
+
+fn hmac<H: NettleHash>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<H>::with_key(key);
+    mac.update(data);
+    let mut digest = vec![0u8; mac.mac_size()];
+    mac.digest(&mut digest).unwrap();
+    digest
+}
+
+/// Generate HOTP code (RFC 4226) for given counter
+pub fn hotp(otp: &Otp, counter: u64) -> String {
+    let digest = match otp.algorithm {
+        OtpAlgorithm::Sha1 => hmac::<Sha1>(&otp.secret, &counter.to_be_bytes()),
+        OtpAlgorithm::Sha256 => hmac::<Sha256>(&otp.secret, &counter.to_be_bytes()),
+        OtpAlgorithm::Sha512 => hmac::<Sha512>(&otp.secret, &counter.to_be_bytes()),
+    };
+    /* Dynamic truncation */
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = ((digest[offset] as u64 & 0x7f) << 24)
+        | ((digest[offset + 1] as u64) << 16)
+        | ((digest[offset + 2] as u64) << 8)
+        | (digest[offset + 3] as u64);
+    format!(
+        "{:0width$}",
+        binary % 10u64.pow(otp.digits),
+        width = otp.digits as usize
+    )
+}
+
+/// Generate TOTP code (RFC 6238) for given unix time. Also returns
+/// seconds after which code changes.
+pub fn totp_at(otp: &Otp, period: u64, unix_time: u64) -> (String, u64) {
+    (hotp(otp, unix_time / period), period - (unix_time % period))
+}
+
+/// Generate current code for a stored entry. Returns the code and, for
+/// TOTP, seconds remaining. HOTP counter is increased and saved.
+pub fn generate_otp(key: String, handle: &mut GivMe) -> Result<(String, Option<u64>), String> {
+    let cred =
+        give_credentials(key.clone(), handle).ok_or_else(|| format!("'{}' not found!", key))?;
+    let uri = cred
+        .otp
+        .ok_or_else(|| format!("'{}' has no one time password", key))?;
+    let mut otp = parse_otp(uri.expose(), &key)?;
+    match otp.kind {
+        OtpKind::Totp { period } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|err| err.to_string())?
+                .as_secs();
+            let (code, remaining) = totp_at(&otp, period, now);
+            Ok((code, Some(remaining)))
+        }
+        OtpKind::Hotp { counter } => {
+            let code = hotp(&otp, counter);
+            otp.kind = OtpKind::Hotp {
+                counter: counter + 1,
+            };
+            let encrypted_otp = base64::encode(encrypt(&otp_to_uri(&otp), handle)?);
+            let mut row = find_row(&key, handle)?.ok_or_else(|| format!("'{}' not found!", key))?;
+            row.otp = Some(Secret::from(encrypted_otp));
+            row.modified = None;
+            storage_mut(handle).put(&row)?;
+            Ok((code, None))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc_otp(secret: &str, algorithm: OtpAlgorithm, digits: u32) -> Otp {
+        Otp {
+            label: String::from("test"),
+            issuer: None,
+            secret: secret.as_bytes().to_vec(),
+            algorithm,
+            digits,
+            kind: OtpKind::Totp { period: 30 },
+        }
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226_vectors() {
+        let otp = rfc_otp("12345678901234567890", OtpAlgorithm::Sha1, 6);
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(&otp, counter as u64), *code);
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_vectors() {
+        let sha1 = rfc_otp("12345678901234567890", OtpAlgorithm::Sha1, 8);
+        let sha256 = rfc_otp("12345678901234567890123456789012", OtpAlgorithm::Sha256, 8);
+        let sha512 = rfc_otp(
+            "1234567890123456789012345678901234567890123456789012345678901234",
+            OtpAlgorithm::Sha512,
+            8,
+        );
+        let expected = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, code_sha1, code_sha256, code_sha512) in expected.iter() {
+            assert_eq!(totp_at(&sha1, 30, *time).0, *code_sha1);
+            assert_eq!(totp_at(&sha256, 30, *time).0, *code_sha256);
+            assert_eq!(totp_at(&sha512, 30, *time).0, *code_sha512);
+        }
+        assert_eq!(totp_at(&sha1, 30, 59).1, 1);
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        let data = b"12345678901234567890";
+        assert_eq!(base32_encode(data), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(
+            base32_decode("gezd gnbv-gy3t qojq GEZDGNBVGY3TQOJQ====").unwrap(),
+            data
+        );
+        assert!(base32_decode("GEZ1").is_err());
+        assert!(base32_decode("").is_err());
+    }
+
+    #[test]
+    fn parses_totp_uri() {
+        let otp = parse_otp(
+            "otpauth://totp/Example%3Aalice%40example.com?secret=JBSWY3DPEHPK3PXP\
+             &issuer=Example%20Co&algorithm=SHA256&digits=8&period=60",
+            "fallback",
+        )
+        .unwrap();
+        assert_eq!(otp.label, "Example:alice@example.com");
+        assert_eq!(otp.issuer.as_deref(), Some("Example Co"));
+        assert_eq!(otp.secret, b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(otp.algorithm, OtpAlgorithm::Sha256);
+        assert_eq!(otp.digits, 8);
+        assert_eq!(otp.kind, OtpKind::Totp { period: 60 });
+    }
+
+    #[test]
+    fn parses_hotp_uri_and_plain_secret() {
+        let otp = parse_otp("otpauth://hotp/?secret=GEZDGNBV&counter=7", "fallback").unwrap();
+        assert_eq!(otp.label, "fallback");
+        assert_eq!(otp.kind, OtpKind::Hotp { counter: 7 });
+        assert_eq!(otp.algorithm, OtpAlgorithm::Sha1);
+
+        let otp = parse_otp(
+            "otpauth://totp/a+b%2Bc?secret=GEZDGNBV&issuer=x+y%41",
+            "fallback",
+        )
+        .unwrap();
+        assert_eq!(otp.label, "a b+c");
+        assert_eq!(otp.issuer.as_deref(), Some("x yA"));
+
+        let otp = parse_otp(" JBSWY3DPEHPK3PXP ", "plain").unwrap();
+        assert_eq!(otp.label, "plain");
+        assert_eq!(otp.digits, 6);
+        assert_eq!(otp.kind, OtpKind::Totp { period: 30 });
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        assert!(parse_otp("otpauth://totp", "x").is_err());
+        assert!(parse_otp("otpauth://totp/a?issuer=b", "x").is_err());
+        assert!(parse_otp("otpauth://hotp/a?secret=GEZDGNBV", "x").is_err());
+        assert!(parse_otp("otpauth://motp/a?secret=GEZDGNBV", "x").is_err());
+        assert!(parse_otp("otpauth://totp/a?secret=GEZDGNBV&digits=5", "x").is_err());
+        assert!(parse_otp("otpauth://totp/a?secret=GEZDGNBV&period=0", "x").is_err());
+        assert!(parse_otp("otpauth://totp/a?secret=GEZDGNBV&algorithm=MD5", "x").is_err());
+        assert!(parse_otp("otpauth://totp/a?secret=GEZDGNBV&issuer=50%", "x").is_err());
+        assert!(parse_otp("otpauth://totp/a%4?secret=GEZDGNBV", "x").is_err());
+    }
+
+    #[test]
+    fn uri_round_trips() {
+        let uri = "otpauth://hotp/me%20too?secret=JBSWY3DPEHPK3PXP&counter=3\
+                   &algorithm=SHA512&digits=7&issuer=A%26B";
+        let otp = parse_otp(uri, "x").unwrap();
+        let again = parse_otp(&otp_to_uri(&otp), "y").unwrap();
+        assert_eq!(again.label, "me too");
+        assert_eq!(again.issuer.as_deref(), Some("A&B"));
+        assert_eq!(again.secret, otp.secret);
+        assert_eq!(again.algorithm, OtpAlgorithm::Sha512);
+        assert_eq!(again.digits, 7);
+        assert_eq!(again.kind, OtpKind::Hotp { counter: 3 });
+    }
+}
@@ -2,8 +2,9 @@ use crate::encryption::encrypt;
 use crate::io::debug;
 use crate::models::credentials::Credentials;
 use crate::models::givme::GivMe;
-use crate::sql::{save_to_sql, setup_sql};
-use rand::random;
+use crate::models::secret::Secret;
+use crate::storage::{connect_storage, storage_mut};
+use rand::{random, Rng};
 use rpassword::read_password;
 use std::io::Write;
 
@@ -22,6 +23,42 @@ pub const BANNER: &str =
 █░░░░░░░░░░░░░░█░░░░░░░░░░█████░░░░░░█████░░░░░░██████████░░░░░░█░░░░░░░░░░░░░░█
 ████████████████████████████████████████████████████████████████████████████████";
 
+/// Overwrite `bytes` with zeros in a way compiler can't optimize away
+pub fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Whether `a` and `b` hold same bytes. Time taken depends only on
+/// their lengths, never on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Decode `%XX` escapes of an URI part. Fails on a malformed escape
+/// without naming the input, as it may hold a secret.
+pub fn percent_decode(input: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| String::from("Invalid percent escape"))?;
+            bytes.push(hex);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| String::from("Percent escapes are not UTF-8"))
+}
+
 /// Returns random sequence of characters with provided length
 ///
 /// Bydefault it avoids any "'" in randomly generated String.
@@ -38,6 +75,26 @@ fn rand_string(size: usize) -> String {
     }
 }
 
+/// Characters generated passwords are made of
+const PASSWORD_CHARS: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!#$%&*+-=?@^_~";
+
+/// Shown in place of a secret value, same for every length
+pub const MASKED_VALUE: &str = "********";
+
+/// Length of generated passwords when user does not choose one
+pub const GENERATED_PASSWORD_LENGTH: usize = 20;
+
+/// Random password of `length` characters for a new entry
+pub fn generate_password(length: usize) -> Secret {
+    let mut rng = rand::thread_rng();
+    Secret::from(
+        (0..length)
+            .map(|_| PASSWORD_CHARS[rng.gen_range(0..PASSWORD_CHARS.len())] as char)
+            .collect::<String>(),
+    )
+}
+
 /// Ask user to set master password. Encrypt it and save it to Sqlite.
 pub fn run_setup(handle: &mut GivMe) -> Result<bool, bool> {
     let common_passes = vec![
@@ -81,34 +138,36 @@ pub fn run_setup(handle: &mut GivMe) -> Result<bool, bool> {
             }
         }
     }
-    password = adjust_password_length(&password, 24);
+    let password = Secret::from(adjust_password_length(&password, 24));
     debug("Encrypting password to store in data");
-    setup_sql(handle);
+    if let Err(err) = connect_storage(handle) {
+        eprintln!("Can't create vault: {}", err);
+        std::process::exit(1);
+    }
     handle.password = Some(password.clone());
 
     let encrypted = encrypt(
-        format!("{}", rand_string(32)),
+        &format!("{}", rand_string(32)),
         &mut GivMe {
             key: Some(password.clone()),
             sql_con: None,
             password: Some(password.clone()),
             os: None,
             username: None,
+            storage: None,
         },
     )
     .unwrap();
 
     let encrypted_final_key = base64::encode(encrypted);
     debug("Adding to database");
-    save_to_sql(
-        Credentials::new(
+    storage_mut(handle)
+        .put(&Credentials::new(
             String::from("secret_key"),
             encrypted_final_key,
             String::new(),
-        ),
-        handle,
-    )
-    .unwrap();
+        ))
+        .unwrap();
     //println!("{}", password);
     Ok(true)
 }
@@ -136,3 +195,128 @@ pub fn adjust_password_length(input_password: &str, length: usize) -> String {
     }
     proper_length_password
 }
+
+/// Seconds since Unix epoch
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// Format current time in UTC as `YYYYmmdd-HHMMSS`. Sorts in same
+/// order as time, so it is used in names of backups.
+pub fn timestamp_now() -> String {
+    format_timestamp(unix_now())
+}
+
+/// Format Unix time `secs` in UTC as `YYYYmmdd-HHMMSS`
+pub fn format_timestamp(secs: u64) -> String {
+    /* Civil date from days since epoch, Howard Hinnant's algorithm */
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Fresh empty directory under system temp dir for a test
+#[cfg(test)]
+pub(crate) fn test_dir(label: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("givme-test-{}-{:08x}", label, random::<u32>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.display().to_string()
+}
+
+#[cfg(test)]
+lazy_static::lazy_static! {
+    static ref TEST_HOME: std::sync::Mutex<()> = std::sync::Mutex::new(());
+}
+
+/// Held by tests using `test_vault()`. Vault, config and backup paths
+/// come from home directory, which the whole test process shares.
+#[cfg(test)]
+pub(crate) fn lock_test_home() -> std::sync::MutexGuard<'static, ()> {
+    TEST_HOME.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Vault with `storage` ("sqlite", "json" or "sealed") in a fresh home
+/// directory, unlocked with Master Key "Key". Home stays there until
+/// next call, so config, backups and git working copy are found next to
+/// the vault. A vault given as `shared_with` lends its Master Key row,
+/// both then use the same keys. Caller holds `lock_test_home()`.
+#[cfg(test)]
+pub(crate) fn test_vault(storage: &str, shared_with: Option<&GivMe>) -> GivMe {
+    let home = test_dir("home");
+    std::env::set_var("HOME", &home);
+    let mut handle = GivMe::new();
+    crate::os::get_os_and_username(&mut handle);
+    let config = crate::settings::config_path(&handle).unwrap();
+    std::fs::create_dir_all(config.parent().unwrap()).unwrap();
+    std::fs::write(&config, format!("storage = {}\n", storage)).unwrap();
+    connect_storage(&mut handle).unwrap();
+
+    let secret_key = match shared_with {
+        Some(other) => crate::storage::stored_secret_key(other),
+        None => {
+            let password = Secret::from(adjust_password_length("Key", 24));
+            let mut password_handle = GivMe::new();
+            password_handle.key = Some(password.clone());
+            password_handle.password = Some(password);
+            base64::encode(encrypt(&rand_string(32), &mut password_handle).unwrap())
+        }
+    };
+    storage_mut(&mut handle)
+        .put(&Credentials::new(
+            String::from("secret_key"),
+            secret_key,
+            String::new(),
+        ))
+        .unwrap();
+    crate::io::unlock_with_master_key("Key", &mut handle).unwrap();
+    handle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_is_strict() {
+        assert_eq!(percent_decode("a%41").unwrap(), "aA");
+        assert_eq!(percent_decode("%41%42").unwrap(), "AB");
+        assert_eq!(percent_decode("my%20entry+x").unwrap(), "my entry+x");
+        assert_eq!(percent_decode("%C5%BE").unwrap(), "\u{17e}");
+        assert!(percent_decode("a%4").is_err());
+        assert!(percent_decode("50%").is_err());
+        assert!(percent_decode("%zz").is_err());
+        assert!(percent_decode("%FF").is_err());
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_input() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"tok"));
+        assert!(!constant_time_eq(b"", b"t"));
+    }
+}
@@ -0,0 +1,246 @@
+/// Minimal XML element. Mixed content is flattened, all text
+/// directly inside an element is joined into `text`.
+#[derive(Clone, Debug, Default)]
+pub struct XmlNode {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<XmlNode>,
+    pub text: String,
+}
+
+impl XmlNode {
+    /// First child element with given name
+    pub fn child(&self, name: &str) -> Option<&XmlNode> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// All child elements with given name
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlNode> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// Replace predefined and numeric entities
+pub fn xml_unescape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Escape text for use in element content or attribute values
+pub fn xml_escape(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn parse_attributes(input: &str) -> Result<Vec<(String, String)>, String> {
+    let mut attributes = Vec::new();
+    let mut rest = input.trim();
+    while !rest.is_empty() {
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| format!("Invalid attribute in '{}'", input))?;
+        let name = rest[..eq].trim().to_string();
+        rest = rest[eq + 1..].trim_start();
+        let quote = rest
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| format!("Unquoted attribute '{}'", name))?;
+        let end = rest[1..]
+            .find(quote)
+            .ok_or_else(|| format!("Unterminated attribute '{}'", name))?;
+        attributes.push((name, xml_unescape(&rest[1..end + 1])));
+        rest = rest[end + 2..].trim_start();
+    }
+    Ok(attributes)
+}
+
+/// Parse an XML document and return its root element
+pub fn parse_xml(data: &str) -> Result<XmlNode, String> {
+    let mut stack: Vec<XmlNode> = vec![XmlNode::default()];
+    let mut rest = data.trim_start_matches('\u{feff}');
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            let end = after
+                .find("-->")
+                .ok_or_else(|| String::from("Unterminated comment"))?;
+            rest = &after[end + 3..];
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after
+                .find("]]>")
+                .ok_or_else(|| String::from("Unterminated CDATA"))?;
+            stack.last_mut().unwrap().text.push_str(&after[..end]);
+            rest = &after[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest
+                .find('>')
+                .ok_or_else(|| String::from("Unterminated declaration"))?;
+            rest = &rest[end + 1..];
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after
+                .find('>')
+                .ok_or_else(|| String::from("Unterminated closing tag"))?;
+            let name = after[..end].trim();
+            let node = stack.pop().unwrap();
+            if node.name != name || stack.is_empty() {
+                return Err(format!("Unexpected closing tag '{}'", name));
+            }
+            stack.last_mut().unwrap().children.push(node);
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('<') {
+            let end = after
+                .find('>')
+                .ok_or_else(|| String::from("Unterminated tag"))?;
+            let mut tag = &after[..end];
+            let self_closing = tag.ends_with('/');
+            if self_closing {
+                tag = &tag[..tag.len() - 1];
+            }
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let node = XmlNode {
+                name: tag[..name_end].to_string(),
+                attributes: parse_attributes(&tag[name_end..])?,
+                ..XmlNode::default()
+            };
+            if self_closing {
+                stack.last_mut().unwrap().children.push(node);
+            } else {
+                stack.push(node);
+            }
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = xml_unescape(&rest[..end]);
+            if stack.len() > 1 || !text.trim().is_empty() {
+                stack.last_mut().unwrap().text.push_str(&text);
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(format!(
+            "Unclosed tag '{}'",
+            stack
+                .last()
+                .map(|node| node.name.as_str())
+                .unwrap_or_default()
+        ));
+    }
+    stack
+        .pop()
+        .unwrap()
+        .children
+        .into_iter()
+        .next()
+        .ok_or_else(|| String::from("Empty XML document"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_elements_and_attributes() {
+        let root = parse_xml(
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE x>\n<!-- note -->\n<root a=\"1\" b='x &amp; y'>\
+             <item name=\"one\"/><item name=\"two\">text</item><other/></root>",
+        )
+        .unwrap();
+        assert_eq!(root.name, "root");
+        assert_eq!(
+            root.attributes,
+            vec![
+                (String::from("a"), String::from("1")),
+                (String::from("b"), String::from("x & y")),
+            ]
+        );
+        let items: Vec<&XmlNode> = root.children_named("item").collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].text, "text");
+        assert!(root.child("other").is_some());
+        assert!(root.child("missing").is_none());
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(
+            xml_unescape("&lt;a&gt; &amp; &quot;b&quot; &apos;c&apos; &#65;&#x42;&#x1F600;"),
+            "<a> & \"b\" 'c' AB\u{1f600}"
+        );
+        assert_eq!(
+            xml_unescape("a & b; &unknown; &#xZZ; &"),
+            "a & b; &unknown; &#xZZ; &"
+        );
+        let text = "<tag attr=\"it's\"> & more";
+        assert_eq!(xml_unescape(&xml_escape(text)), text);
+    }
+
+    #[test]
+    fn keeps_cdata_literally() {
+        let root = parse_xml("<v>a <![CDATA[<b> &amp; ]]]]> c</v>").unwrap();
+        assert_eq!(root.text, "a <b> &amp; ]] c");
+    }
+
+    #[test]
+    fn rejects_malformed_and_truncated_input() {
+        for input in [
+            "",
+            "just text",
+            "<a>",
+            "<a><b></a>",
+            "</a>",
+            "<a></b>",
+            "<a",
+            "<a x=1></a>",
+            "<a x=\"1></a>",
+            "<a x></a>",
+            "<a><!-- open</a>",
+            "<a><![CDATA[open</a>",
+            "<?xml",
+            "<a></a",
+        ]
+        .iter()
+        {
+            assert!(parse_xml(input).is_err(), "{:?} was accepted", input);
+        }
+    }
+}